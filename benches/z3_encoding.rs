@@ -0,0 +1,94 @@
+//! Time to optimality of the Z3 encodings and of the MILP formulation on generated graphs.
+//!
+//! Run with `cargo bench --bench z3_encoding`.
+
+use dataspatialpim::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+use dataspatialpim::graph_cut::{
+    DataMappingError, HardwareConfig, MilpPartitioner, PartitionStats, Partitioner, SolveStatus,
+    SolverBudget, TraceOptions, WalkerTraces, Z3Encoding, Z3Partitioner,
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn main() -> anyhow::Result<()> {
+    println!(
+        "{:<8} {:>5} {:>5} {:>6}  {:>14} {:>14} {:>14}",
+        "kind", "nodes", "edges", "cores", "ite", "one-hot", "milp"
+    );
+    for kind in [GraphKind::Grid, GraphKind::Uniform, GraphKind::Rmat] {
+        for nodes in [12, 16, 24] {
+            let config = GeneratorConfig {
+                kind,
+                nodes,
+                edges: nodes * 2,
+                max_weight: 5,
+                ..Default::default()
+            };
+            let g = generate_graph(&config, &default_types())?;
+            let cores = 4;
+            // Room for a quarter of the nodes per core, plus one.
+            let hardware = HardwareConfig {
+                core_size: 4 * (nodes as i64 / cores + 1),
+                core_num: cores,
+                rank_size: None,
+                edge_placement: Default::default(),
+            };
+            let traces = WalkerTraces::sample(&g, &TraceOptions::default(), 0)?;
+            let mut times = vec![];
+            let mut optimal_cuts = vec![];
+            let z3 = |encoding| -> Box<dyn Partitioner> {
+                Box::new(Z3Partitioner {
+                    encoding,
+                    budget: SolverBudget {
+                        timeout: Some(TIMEOUT),
+                        rlimit: None,
+                    },
+                    fallback: None,
+                    ..Default::default()
+                })
+            };
+            let milp = Box::new(MilpPartitioner {
+                timeout: Some(TIMEOUT),
+                fallback: None,
+                ..Default::default()
+            });
+            for partitioner in [z3(Z3Encoding::Ite), z3(Z3Encoding::OneHot), milp] {
+                let partition = match partitioner.partition(&g, &hardware) {
+                    Err(e)
+                        if matches!(e.downcast_ref(), Some(DataMappingError::BudgetExhausted)) =>
+                    {
+                        times.push(format!("> {:?}", TIMEOUT));
+                        continue;
+                    }
+                    result => result?,
+                };
+                times.push(match partition.stats.status {
+                    SolveStatus::Optimal => format!("{:.3?}", partition.stats.elapsed),
+                    _ => format!("> {:?}", TIMEOUT),
+                });
+                let stats = PartitionStats::new(&g, &partition, &hardware, &traces)?;
+                assert!(stats
+                    .cores
+                    .iter()
+                    .all(|core| core.bytes <= hardware.core_size));
+                if partition.stats.status == SolveStatus::Optimal {
+                    optimal_cuts.push(stats.cut_weight);
+                }
+            }
+            // All three model the same problem.
+            assert!(optimal_cuts.windows(2).all(|cuts| cuts[0] == cuts[1]));
+            println!(
+                "{:<8} {:>5} {:>5} {:>6}  {:>14} {:>14} {:>14}",
+                format!("{:?}", kind),
+                nodes,
+                g.edge_insts.len(),
+                cores,
+                times[0],
+                times[1],
+                times[2]
+            );
+        }
+    }
+    Ok(())
+}
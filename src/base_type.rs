@@ -57,6 +57,8 @@ impl Size for PIMField {
 pub struct NamedBlock {
     pub name: String,
     pub fields: Vec<PIMField>,
+    /// Compute cost of an instance, as set by a `@cost(n)` annotation on a node type.
+    pub cost: Option<i64>,
 }
 
 impl Size for NamedBlock {
@@ -65,8 +67,31 @@ impl Size for NamedBlock {
     }
 }
 
+impl NamedBlock {
+    /// Compute cost of an instance for load balancing, its size unless declared.
+    pub fn cost(&self) -> i64 {
+        self.cost.unwrap_or_else(|| self.size_byte())
+    }
+}
+
+/// An argument of an annotation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationArg {
+    Num(i64),
+    Token(String),
+    /// `key = value`
+    Named(String, Box<AnnotationArg>),
+}
+
+/// An `@name(args)` annotation preceding a declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub name: String,
+    pub args: Vec<AnnotationArg>,
+}
+
 #[derive(Debug, Clone)]
-pub struct Node(pub NamedBlock);
+pub struct Node(pub NamedBlock, pub Vec<Annotation>);
 
 #[derive(Debug, Clone)]
 pub struct Edge {
@@ -86,14 +111,20 @@ pub struct Walker {
 pub struct NodeInst {
     pub node_type: String,
     pub varname: String,
+    pub annotations: Vec<Annotation>,
 }
 
-pub fn transform_node_inst(node_type: &str, token_list: &Vec<String>) -> Vec<NodeInst> {
+pub fn transform_node_inst(
+    node_type: &str,
+    token_list: &Vec<String>,
+    annotations: &[Annotation],
+) -> Vec<NodeInst> {
     token_list
         .into_iter()
         .map(|a| NodeInst {
             node_type: String::from(node_type),
             varname: a.to_string(),
+            annotations: annotations.to_vec(),
         })
         .collect()
 }
@@ -117,6 +148,8 @@ pub struct Graph {
     pub node_insts: Vec<NodeInst>,
     pub edge_insts: Vec<EdgeInst>,
     pub walker_insts: Vec<WalkerInst>,
+    /// `@name(args);` statements constraining the graph as a whole.
+    pub annotations: Vec<Annotation>,
 }
 
 #[derive(Debug, Clone)]
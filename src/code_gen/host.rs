@@ -1,6 +1,8 @@
+use std::collections::BTreeSet;
 use std::rc::Rc;
 
-use crate::sem_type::{SemanticGlobal, SemanticGraph, SemanticNodeInst};
+use crate::graph_cut::{Partition, TaskletPlan};
+use crate::sem_type::{ReplicaMode, SemanticGlobal, SemanticGraph, SemanticNodeInst};
 use indoc::{formatdoc, indoc};
 
 pub fn initialization_declaration(graph: &SemanticGraph) -> String {
@@ -138,3 +140,330 @@ pub fn main_function(
     };
     main
 }
+
+pub fn replica_code(graph: &SemanticGraph, partition: &Partition) -> String {
+    // One copy of every replicated node per DPU holding it, the primary copy first
+    let mut copies = vec![];
+    let mut broadcast = vec![];
+    let mut write_back = vec![];
+    let mut merged_types = BTreeSet::new();
+    for replicas in &partition.replicas {
+        let node = &graph.node_insts[replicas.node];
+        let (name, type_name) = (&node.varname, &node.node_type.name);
+        let dpus = std::iter::once(partition.assignment[replicas.node])
+            .chain(replicas.cores.iter().copied())
+            .map(|dpu| dpu.to_string())
+            .collect::<Vec<String>>();
+        let count = dpus.len();
+        copies.push(format!(
+            "{} {}_replicas[{}];\nconst uint32_t {}_replica_dpus[{}] = {{{}}};",
+            type_name,
+            name,
+            count,
+            name,
+            count,
+            dpus.join(", ")
+        ));
+        broadcast.push(format!(
+            "for (int k = 0; k < {}; k++) {{ {}_replicas[k] = {}; }}",
+            count, name, name
+        ));
+        // The primary copy is written back; copies that may have been written are merged
+        write_back.push(format!("{} = {}_replicas[0];", name, name));
+        if replicas.mode == ReplicaMode::Reconcile {
+            write_back.push(format!(
+                "for (int k = 1; k < {}; k++) {{ {}_merge(&{}, &{}_replicas[k]); }}",
+                count, type_name, name, name
+            ));
+            merged_types.insert(type_name.clone());
+        }
+    }
+    // Merge a copy written on a DPU into the host node, provided by the user
+    let merges = merged_types
+        .iter()
+        .map(|type_name| {
+            format!(
+                "void {}_merge({} *node, const {} *copy);\n",
+                type_name, type_name, type_name
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("");
+
+    formatdoc! {
+      r#"
+    // Replicated nodes: copy k lives on DPU <node>_replica_dpus[k]
+    {}
+    {}// Fill every copy from the host node before transferring the nodes to the DPUs
+    void broadcast_replicas(void) {{
+      {}
+    }}
+    // Write the copies retrieved from the DPUs back into the host nodes
+    void write_back_replicas(void) {{
+      {}
+    }}
+    "#,
+      copies.join("\n"),
+      merges,
+      broadcast.join("\n  "),
+      write_back.join("\n  ")
+    }
+}
+
+pub fn edge_placement_code(edge_cores: &[Vec<usize>]) -> String {
+    // Offsets into the DPU list of each edge, in declaration order
+    let mut offsets = vec![0];
+    let mut dpus = vec![];
+    for cores in edge_cores {
+        dpus.extend(cores.iter().map(|dpu| dpu.to_string()));
+        offsets.push(dpus.len());
+    }
+    let offsets = offsets
+        .iter()
+        .map(|offset| offset.to_string())
+        .collect::<Vec<String>>();
+
+    formatdoc! {
+      r#"
+    // Edge e is stored on DPUs edge_dpus[edge_dpu_offsets[e]] to edge_dpus[edge_dpu_offsets[e + 1] - 1]
+    const uint32_t edge_dpu_offsets[{}] = {{{}}};
+    const uint32_t edge_dpus[{}] = {{{}}};
+    "#,
+      offsets.len(),
+      offsets.join(", "),
+      dpus.len(),
+      dpus.join(", ")
+    }
+}
+
+/// One row per DPU, padded with zeros to the longest one, as C has no empty initializer.
+fn dpu_rows(dpus: usize, row: impl Fn(usize) -> Vec<String>) -> String {
+    (0..dpus)
+        .map(|dpu| match row(dpu) {
+            row if row.is_empty() => String::from("{0}"),
+            row => format!("{{{}}}", row.join(", ")),
+        })
+        .collect::<Vec<String>>()
+        .join(",\n  ")
+}
+
+pub fn tasklet_plan_code(plan: &TaskletPlan) -> String {
+    let to_strings = |values: &[usize]| values.iter().map(|value| value.to_string()).collect();
+    let offsets = dpu_rows(plan.dpus.len(), |dpu| to_strings(&plan.dpus[dpu].offsets));
+    let nodes = dpu_rows(plan.dpus.len(), |dpu| to_strings(&plan.dpus[dpu].nodes));
+    // Byte offset of each node in the MRAM heap of its DPU, and the end of the last one
+    let addrs = dpu_rows(plan.dpus.len(), |dpu| {
+        plan.dpus[dpu]
+            .addrs()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect()
+    });
+    let max_nodes = plan.max_dpu_nodes().max(1);
+
+    formatdoc! {
+      r#"
+    // Tasklet t of DPU d runs the nodes dpu_nodes[d][k] for k from dpu_tasklet_node_offsets[d][t]
+    // to dpu_tasklet_node_offsets[d][t + 1] - 1, stored in MRAM from dpu_node_addrs[d][k] to dpu_node_addrs[d][k + 1],
+    // each padded to 8 bytes
+    #define PLAN_TASKLETS {}
+    #define MAX_DPU_NODES {}
+    const uint32_t dpu_tasklet_node_offsets[{}][PLAN_TASKLETS + 1] = {{
+      {}
+    }};
+    const uint32_t dpu_nodes[{}][MAX_DPU_NODES] = {{
+      {}
+    }};
+    const uint32_t dpu_node_addrs[{}][MAX_DPU_NODES + 1] = {{
+      {}
+    }};
+    // Copy the tables of every DPU to its tasklets before launching
+    void push_tasklet_plan(struct dpu_set_t dpu_set) {{
+      struct dpu_set_t dpu;
+      uint32_t each_dpu;
+      DPU_FOREACH(dpu_set, dpu, each_dpu) {{ DPU_ASSERT(dpu_prepare_xfer(dpu, (void *)dpu_tasklet_node_offsets[each_dpu])); }}
+      DPU_ASSERT(dpu_push_xfer(dpu_set, DPU_XFER_TO_DPU, "tasklet_node_offsets", 0, sizeof(dpu_tasklet_node_offsets[0]), DPU_XFER_DEFAULT));
+      DPU_FOREACH(dpu_set, dpu, each_dpu) {{ DPU_ASSERT(dpu_prepare_xfer(dpu, (void *)dpu_node_addrs[each_dpu])); }}
+      DPU_ASSERT(dpu_push_xfer(dpu_set, DPU_XFER_TO_DPU, "node_addrs", 0, sizeof(dpu_node_addrs[0]), DPU_XFER_DEFAULT));
+    }}
+    "#,
+      plan.tasklets,
+      max_nodes,
+      plan.dpus.len(),
+      offsets,
+      plan.dpus.len(),
+      nodes,
+      plan.dpus.len(),
+      addrs
+    }
+}
+
+pub fn node_transfer_code(
+    graph: &SemanticGraph,
+    partition: &Partition,
+    plan: &TaskletPlan,
+) -> String {
+    // The host copy of every node held on each DPU: the node itself, or the copy of a
+    // replicated node for that DPU, filled by broadcast_replicas
+    let data = dpu_rows(plan.dpus.len(), |dpu| {
+        plan.dpus[dpu]
+            .nodes
+            .iter()
+            .map(|node| {
+                let name = &graph.node_insts[*node].varname;
+                match partition.replicas.iter().find(|r| r.node == *node) {
+                    Some(replicas) => {
+                        let copy = std::iter::once(partition.assignment[*node])
+                            .chain(replicas.cores.iter().copied())
+                            .position(|core| core == dpu)
+                            .unwrap_or(0);
+                        format!("&{}_replicas[{}]", name, copy)
+                    }
+                    None => format!("&{}", name),
+                }
+            })
+            .collect()
+    });
+    let bytes = dpu_rows(plan.dpus.len(), |dpu| {
+        plan.dpus[dpu]
+            .bytes
+            .iter()
+            .map(|bytes| bytes.to_string())
+            .collect()
+    });
+    let max_padded = plan
+        .dpus
+        .iter()
+        .flat_map(|dpu| {
+            dpu.addrs()
+                .windows(2)
+                .map(|addr| addr[1] - addr[0])
+                .collect::<Vec<i64>>()
+        })
+        .max()
+        .unwrap_or(0)
+        .max(8);
+
+    formatdoc! {
+      r#"
+    // The host copy of node dpu_nodes[d][k] and its bytes
+    #define MAX_NODE_BYTES {}
+    void *const dpu_node_data[{}][MAX_DPU_NODES] = {{
+      {}
+    }};
+    const uint32_t dpu_node_bytes[{}][MAX_DPU_NODES] = {{
+      {}
+    }};
+    // Copy the nodes of every DPU to its MRAM heap in plan order, zero padded to 8 bytes
+    void push_nodes(struct dpu_set_t dpu_set) {{
+      struct dpu_set_t dpu;
+      uint32_t each_dpu;
+      uint8_t staged[MAX_NODE_BYTES];
+      DPU_FOREACH(dpu_set, dpu, each_dpu) {{
+        for (uint32_t k = 0; k < dpu_tasklet_node_offsets[each_dpu][PLAN_TASKLETS]; k++) {{
+          uint32_t padded = dpu_node_addrs[each_dpu][k + 1] - dpu_node_addrs[each_dpu][k];
+          memset(staged, 0, padded);
+          memcpy(staged, dpu_node_data[each_dpu][k], dpu_node_bytes[each_dpu][k]);
+          DPU_ASSERT(dpu_copy_to(dpu, DPU_MRAM_HEAP_POINTER_NAME, dpu_node_addrs[each_dpu][k], staged, padded));
+        }}
+      }}
+    }}
+    // Copy the nodes of every DPU back from its MRAM heap into their host copies
+    void pull_nodes(struct dpu_set_t dpu_set) {{
+      struct dpu_set_t dpu;
+      uint32_t each_dpu;
+      uint8_t staged[MAX_NODE_BYTES];
+      DPU_FOREACH(dpu_set, dpu, each_dpu) {{
+        for (uint32_t k = 0; k < dpu_tasklet_node_offsets[each_dpu][PLAN_TASKLETS]; k++) {{
+          uint32_t padded = dpu_node_addrs[each_dpu][k + 1] - dpu_node_addrs[each_dpu][k];
+          DPU_ASSERT(dpu_copy_from(dpu, DPU_MRAM_HEAP_POINTER_NAME, dpu_node_addrs[each_dpu][k], staged, padded));
+          memcpy(dpu_node_data[each_dpu][k], staged, dpu_node_bytes[each_dpu][k]);
+        }}
+      }}
+    }}
+    "#,
+      max_padded,
+      plan.dpus.len(),
+      data,
+      plan.dpus.len(),
+      bytes
+    }
+}
+
+#[test]
+fn test_replica_code() -> anyhow::Result<()> {
+    use crate::graph_cut::{
+        HardwareConfig, MultilevelPartitioner, Partitioner, ReplicatingPartitioner,
+    };
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    let source = "
+        node Alice { n1: int8; };
+        edge Alice Alice Link {};
+        graph {
+            @replicate(reconcile) node Alice hub;
+            node Alice a0, a1, a2, a3;
+            edge Link hub a0 1;
+            edge Link hub a1 1;
+            edge Link hub a2 1;
+            edge Link hub a3 1;
+        };
+    ";
+    let graph = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let hardware = HardwareConfig {
+        core_size: 4,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = ReplicatingPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        degree_threshold: None,
+        mode: ReplicaMode::ReadOnly,
+    }
+    .partition(&graph, &hardware)?;
+    let code = replica_code(&graph, &partition);
+    assert!(code.contains("Alice hub_replicas[2];"));
+    assert!(code.contains("void Alice_merge(Alice *node, const Alice *copy);"));
+    assert!(code.contains("Alice_merge(&hub, &hub_replicas[k]);"));
+    Ok(())
+}
+
+#[test]
+fn test_edge_placement_code() {
+    let code = edge_placement_code(&[vec![0], vec![0, 1], vec![1]]);
+    assert!(code.contains("const uint32_t edge_dpu_offsets[4] = {0, 1, 3, 4};"));
+    assert!(code.contains("const uint32_t edge_dpus[4] = {0, 0, 1, 1};"));
+}
+
+#[test]
+fn test_tasklet_plan_code() {
+    use crate::graph_cut::DpuTasklets;
+
+    let dpu = |nodes: Vec<usize>, offsets: Vec<usize>| DpuTasklets {
+        bytes: vec![4; nodes.len()],
+        work: vec![0; 2],
+        nodes,
+        offsets,
+    };
+    let plan = TaskletPlan {
+        tasklets: 2,
+        share: 32,
+        block: 32,
+        dpus: vec![
+            dpu(vec![0, 2, 3], vec![0, 1, 3]),
+            dpu(vec![1], vec![0, 0, 1]),
+        ],
+    };
+    let code = tasklet_plan_code(&plan);
+    assert!(code.contains("#define MAX_DPU_NODES 3"));
+    assert!(code.contains("{0, 1, 3},\n  {0, 0, 1}"));
+    assert!(code.contains("{0, 2, 3},\n  {1}"));
+    let idle = TaskletPlan {
+        dpus: vec![dpu(vec![0], vec![0, 1, 1]), dpu(vec![], vec![0, 0, 0])],
+        ..plan
+    };
+    assert!(tasklet_plan_code(&idle).contains("{0},\n  {0}\n};"));
+    // Each 4-byte node is padded to 8 bytes, for aligned MRAM transfers.
+    assert!(code.contains("{0, 8, 16, 24},\n  {0, 8}"));
+}
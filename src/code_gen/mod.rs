@@ -1,12 +1,43 @@
 use std::fs;
+use std::io::Write;
 
-use crate::sem_type::SemanticGlobal;
+use crate::base_type::{PIMType, Size};
+use crate::graph_cut::{EdgePlacement, Partition, TaskletPlan};
+use crate::sem_type::{SemanticGlobal, SemanticGraph};
+use anyhow::Result;
 
 mod host;
 mod support;
 mod type_code;
 
-pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
+pub use type_code::TypeCodeGen;
+
+/// Append the DPUs storing each edge of `graph` under `placement` to `file_name`.
+pub fn write_edge_placement(
+    file_name: &str,
+    graph: &SemanticGraph,
+    partition: &Partition,
+    placement: EdgePlacement,
+) -> Result<()> {
+    let mut output_file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(file_name)?;
+    let edge_cores = partition.edge_cores(graph, placement)?;
+    writeln!(output_file, "{}", host::edge_placement_code(&edge_cores))?;
+    Ok(())
+}
+
+/// Write the host program to `file_name`. The tasklet tables of `plan` are pushed once the
+/// DPU binary is loaded, and the nodes of every DPU are transferred in plan order. The
+/// copies of the nodes `partition` replicates are filled before the nodes are transferred
+/// to the DPUs and written back once the results are retrieved.
+pub fn write_to_app(
+    file_name: &str,
+    sem: &SemanticGlobal,
+    partition: &Partition,
+    plan: &TaskletPlan,
+) -> Result<()> {
     let mut output_file = fs::File::create(file_name)?;
 
     // Write header includes.
@@ -91,6 +122,21 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
         }
     }
 
+    let replicated = !partition.replicas.is_empty();
+    if replicated {
+        writeln!(
+            output_file,
+            "\n{}",
+            host::replica_code(&sem.graphs[0], partition)
+        )?;
+    }
+    writeln!(output_file, "\n{}", host::tasklet_plan_code(plan))?;
+    writeln!(
+        output_file,
+        "{}",
+        host::node_transfer_code(&sem.graphs[0], partition, plan)
+    )?;
+
     let mut node_pointer_list = vec![];
     let mut node_pointer_val_list = vec![];
     let mut size_byte_dict = std::collections::HashMap::new();
@@ -143,6 +189,7 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
         output_file,
         "\tDPU_ASSERT(dpu_load(dpu_set, DPU_BINARY, NULL));"
     )?;
+    writeln!(output_file, "\tpush_tasklet_plan(dpu_set);")?;
     writeln!(
         output_file,
         "\tDPU_ASSERT(dpu_get_nr_dpus(dpu_set, &nr_of_dpus));"
@@ -285,6 +332,10 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
     writeln!(output_file, "\t\t\tinput_arguments[i].kernel=kernel_dpu;")?;
     writeln!(output_file, "\t\t}}")?;
 
+    if replicated {
+        writeln!(output_file, "\t\tbroadcast_replicas();")?;
+    }
+
     // Copy input arrays
     writeln!(output_file, "\t\t// Copy input arrays")?;
     writeln!(output_file, "\t\ti = 0;")?;
@@ -295,20 +346,7 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
     )?;
     writeln!(output_file, "\t\tDPU_ASSERT(dpu_push_xfer(dpu_set, DPU_XFER_TO_DPU, \"DPU_INPUT_ARGUMENTS\", 0, sizeof(input_arguments[0]), DPU_XFER_DEFAULT));\n")?;
 
-    writeln!(output_file, "\t\tint last_loc = 0;")?;
-    for p in &node_pointer_list {
-        writeln!(
-            output_file,
-            "\t\tDPU_FOREACH(dpu_set, dpu, i) {{ 
-            \t\tDPU_ASSERT(dpu_prepare_xfer(dpu, buffer_{} + input_size_dpu_8bytes * i));\n\t\t}}",
-            p
-        )?;
-
-        writeln!(output_file, "\t\tDPU_ASSERT(dpu_push_xfer(dpu_set, DPU_XFER_TO_DPU, DPU_MRAM_HEAP_POINTER_NAME, last_loc, dpu_input_size_bytes, DPU_XFER_DEFAULT));\n")?;
-        if p != node_pointer_list.last().unwrap() {
-            writeln!(output_file, "\t\tlast_loc += dpu_input_size_bytes;\n")?;
-        }
-    }
+    writeln!(output_file, "\t\tpush_nodes(dpu_set);")?;
 
     writeln!(
         output_file,
@@ -356,10 +394,10 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
         output_file,
         "\t\tif(rep >= p.n_warmup)\n\t\t\tstart(&timer, 3, rep - p.n_warmup);"
     )?;
-    writeln!(output_file, "\t\ti = 0;")?;
-
-    writeln!(output_file, "\t\tDPU_FOREACH(dpu_set, dpu, i) {{\n\t\t\tDPU_ASSERT(dpu_prepare_xfer(dpu, {} + input_size_dpu_8bytes * i));}}", node_pointer_val_list.last().unwrap())?;
-    writeln!(output_file, "\t\tDPU_ASSERT(dpu_push_xfer(dpu_set, DPU_XFER_FROM_DPU, DPU_MRAM_HEAP_POINTER_NAME, last_loc, dpu_input_size_bytes, DPU_XFER_DEFAULT));")?;
+    writeln!(output_file, "\t\tpull_nodes(dpu_set);")?;
+    if replicated {
+        writeln!(output_file, "\t\twrite_back_replicas();")?;
+    }
     writeln!(output_file, "\t\tif(rep >= p.n_warmup) stop(&timer, 3);\n")?;
     writeln!(output_file, "\t}}")?;
 
@@ -400,7 +438,9 @@ pub fn write_to_app(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
     Ok(())
 }
 
-pub fn write_to_task(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
+/// Write the DPU program to `file_name`. Each tasklet runs the nodes `plan` gives it, where
+/// the host put them in the MRAM heap, through a cache of `plan.block` bytes.
+pub fn write_to_task(file_name: &str, plan: &TaskletPlan) -> Result<()> {
     let mut output_file = fs::File::create(file_name)?;
 
     // Write header includes.
@@ -418,6 +458,41 @@ pub fn write_to_task(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
 
     writeln!(output_file, "__host dpu_arguments_t DPU_INPUT_ARGUMENTS;\n")?;
 
+    // Tasklet tables, pushed by the host with push_tasklet_plan
+    writeln!(output_file, "#define PLAN_TASKLETS {}", plan.tasklets)?;
+    writeln!(
+        output_file,
+        "#define MAX_DPU_NODES {}",
+        plan.max_dpu_nodes().max(1)
+    )?;
+    writeln!(
+        output_file,
+        "// Bytes each tasklet streams through its cache, within its share of WRAM"
+    )?;
+    writeln!(output_file, "#define TASKLET_CACHE_BYTES {}", plan.block)?;
+    writeln!(output_file, "#if NR_TASKLETS != PLAN_TASKLETS")?;
+    writeln!(
+        output_file,
+        "#error \"The tasklet plan was computed for PLAN_TASKLETS tasklets\""
+    )?;
+    writeln!(output_file, "#endif")?;
+    writeln!(
+        output_file,
+        "// Tasklet t runs the nodes k from tasklet_node_offsets[t] to tasklet_node_offsets[t + 1] - 1,"
+    )?;
+    writeln!(
+        output_file,
+        "// stored one after the other in the MRAM heap from node_addrs[k] to node_addrs[k + 1]"
+    )?;
+    writeln!(
+        output_file,
+        "__host uint32_t tasklet_node_offsets[NR_TASKLETS + 1];"
+    )?;
+    writeln!(
+        output_file,
+        "__host uint32_t node_addrs[MAX_DPU_NODES + 1];\n"
+    )?;
+
     // Barrier declaration
     writeln!(output_file, "BARRIER_INIT(my_barrier, NR_TASKLETS);\n")?;
 
@@ -436,94 +511,152 @@ pub fn write_to_task(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
     writeln!(output_file, "\tunsigned int tasklet_id = me();")?;
     writeln!(output_file, "\tif (tasklet_id == 0){{mem_reset();}}\n")?;
     writeln!(output_file, "\tbarrier_wait(&my_barrier);\n")?;
-    writeln!(output_file, "\tuint32_t input_size_dpu_bytes = DPU_INPUT_ARGUMENTS.size; // Input size per DPU in bytes")?;
-    writeln!(output_file, "\tuint32_t input_size_dpu_bytes_transfer = DPU_INPUT_ARGUMENTS.transfer_size; // Transfer input size per DPU in bytes\n")?;
     writeln!(
         output_file,
-        "\t// Address of the current processing block in MRAM"
+        "\t// The nodes of this tasklet, each from node_addrs[k] to node_addrs[k + 1] in the MRAM heap"
     )?;
     writeln!(
         output_file,
-        "\tuint32_t base_tasklet = tasklet_id << BLOCK_SIZE_LOG2;"
+        "\tuint32_t first_node = tasklet_node_offsets[tasklet_id];"
+    )?;
+    writeln!(
+        output_file,
+        "\tuint32_t end_node = tasklet_node_offsets[tasklet_id + 1];"
+    )?;
+    writeln!(
+        output_file,
+        "\tuint32_t mram_base_addr = (uint32_t)DPU_MRAM_HEAP_POINTER;"
     )?;
-
-    writeln!(output_file, "\tint i = 0;")?;
-    for node_inst in &sem.graphs[0].node_insts {
-        for field in &node_inst.node_type.fields {
-            if let PIMType::Array(base_type, _) = &field.pim_type {
-                let node_name = node_inst.varname.clone();
-                let field_name = field.varname.clone();
-                let pointer_name = format!("{}_{}", node_name, field_name);
-
-                writeln!(output_file, "\tuint32_t mram_base_addr_{} = (uint32_t)(DPU_MRAM_HEAP_POINTER + input_size_dpu_bytes_transfer * i);", pointer_name)?;
-                writeln!(output_file, "\ti ++;")?;
-            }
-        }
-    }
 
     writeln!(
         output_file,
         "\n\t// Initialize a local cache to store the MRAM block"
     )?;
+    writeln!(
+        output_file,
+        "\tuint8_t *cache = (uint8_t *) mem_alloc(TASKLET_CACHE_BYTES);\n"
+    )?;
 
-    for node_inst in &sem.graphs[0].node_insts {
-        for field in &node_inst.node_type.fields {
-            if let PIMType::Array(base_type, _) = &field.pim_type {
-                let node_name = node_inst.varname.clone();
-                let field_name = field.varname.clone();
-                let pointer_name = format!("{}_{}", node_name, field_name);
-                writeln!(
-                    output_file,
-                    "\t{} *cache_{} = ({} *) mem_alloc(BLOCK_SIZE);",
-                    base_type.type_code(),
-                    pointer_name,
-                    base_type.type_code()
-                )?;
-            }
-        }
-    }
-
-    writeln!(output_file, "\tfor(unsigned int byte_index = base_tasklet; byte_index < input_size_dpu_bytes; byte_index += BLOCK_SIZE * NR_TASKLETS){{\n")?;
+    writeln!(
+        output_file,
+        "\tfor(uint32_t k = first_node; k < end_node; k++){{"
+    )?;
+    writeln!(output_file, "\t\tfor(uint32_t byte_index = node_addrs[k]; byte_index < node_addrs[k + 1]; byte_index += TASKLET_CACHE_BYTES){{\n")?;
 
     // Bound checking
-    writeln!(output_file, "\t\tuint32_t l_size_bytes = (byte_index + BLOCK_SIZE >= input_size_dpu_bytes) ? (input_size_dpu_bytes - byte_index) : BLOCK_SIZE;\n")?;
+    writeln!(output_file, "\t\t\tuint32_t l_size_bytes = (byte_index + TASKLET_CACHE_BYTES >= node_addrs[k + 1]) ? (node_addrs[k + 1] - byte_index) : TASKLET_CACHE_BYTES;\n")?;
 
-    let mut name_list = vec![];
     // Load cache with current MRAM block
-    for node_inst in &sem.graphs[0].node_insts {
-        for field in &node_inst.node_type.fields {
-            if let PIMType::Array(base_type, _) = &field.pim_type {
-                let node_name = node_inst.varname.clone();
-                let field_name = field.varname.clone();
-                let pointer_name = format!("{}_{}", node_name, field_name);
-                writeln!(output_file, "\t\tmram_read((__mram_ptr void const*)(mram_base_addr_{} + byte_index), cache_{}, l_size_bytes);", pointer_name, pointer_name)?;
-                name_list.push(format!("{}", pointer_name));
-            }
-        }
-    }
+    writeln!(output_file, "\t\t\tmram_read((__mram_ptr void const*)(mram_base_addr + byte_index), cache, l_size_bytes);")?;
 
     // Computer kernel on dpu
-    writeln!(output_file, "\t\tkernel_dpu(")?;
-    for (i, cache) in name_list.iter().enumerate() {
-        if i == name_list.len() - 1 {
-            writeln!(
-                output_file,
-                "\t\t\tcache_{}, \n\t\t\tl_size_bytes >> DIV);\n",
-                cache
-            )?;
-        } else {
-            writeln!(output_file, "\t\t\tcache_{},", cache)?;
-        }
-    }
+    writeln!(
+        output_file,
+        "\t\t\tkernel_dpu(cache, l_size_bytes >> DIV);\n"
+    )?;
 
     // Write cache to current MRAM block
-    if let Some(last_name) = name_list.last() {
-        writeln!(output_file, "\t\tmram_write(cache_{}, (__mram_ptr void*)(mram_base_addr_{} + byte_index), l_size_bytes);", last_name, last_name)?;
-    }
+    writeln!(
+        output_file,
+        "\t\t\tmram_write(cache, (__mram_ptr void*)(mram_base_addr + byte_index), l_size_bytes);"
+    )?;
 
-    writeln!(output_file, "\n\t}}\n")?;
+    writeln!(output_file, "\n\t\t}}\n\t}}\n")?;
 
     writeln!(output_file, "return 0;\n}}")?;
 
     Ok(())
 }
+
+#[test]
+fn test_app_transfers_nodes_and_replicas() -> Result<()> {
+    use crate::graph_cut::{
+        HardwareConfig, MultilevelPartitioner, Partitioner, ReplicatingPartitioner, TaskletOptions,
+    };
+    use crate::sem_type::ReplicaMode;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    let source = "
+        node Alice { v: int32[3]; };
+        edge Alice Alice Link {};
+        graph {
+            @replicate(reconcile) node Alice hub;
+            node Alice a0, a1, a2, a3;
+            edge Link hub a0 1;
+            edge Link hub a1 1;
+            edge Link hub a2 1;
+            edge Link hub a3 1;
+        };
+    ";
+    let sem = semantic_analysis(parse_str(source)?)?;
+    let hardware = HardwareConfig {
+        core_size: 48,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = ReplicatingPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        degree_threshold: None,
+        mode: ReplicaMode::ReadOnly,
+    }
+    .partition(&sem.graphs[0], &hardware)?;
+    assert!(!partition.replicas.is_empty());
+    let plan = TaskletPlan::new(&sem.graphs[0], &partition, &TaskletOptions::default())?;
+    let file_name = std::env::temp_dir().join(format!(
+        "dataspatialpim_replicas_app_{}.c",
+        std::process::id()
+    ));
+    write_to_app(file_name.to_str().unwrap(), &sem, &partition, &plan)?;
+    let code = fs::read_to_string(&file_name)?;
+    fs::remove_file(&file_name)?;
+    let position = |text: &str| code.find(text).unwrap();
+    assert!(code.contains("void broadcast_replicas(void) {"));
+    // Copies are filled before the transfer and the launch, and written back after.
+    let main = position("int main(");
+    let broadcast = position("\t\tbroadcast_replicas();");
+    let launch = position("dpu_launch(dpu_set, DPU_SYNCHRONOUS)");
+    let write_back = position("\t\twrite_back_replicas();");
+    assert!(main < broadcast && broadcast < position("// Copy input arrays"));
+    assert!(broadcast < launch && launch < write_back);
+    // The tasklet tables are pushed once the binary is loaded, and the nodes of every DPU,
+    // copies included, are transferred in plan order at 8-byte aligned addresses.
+    let load = position("dpu_load(dpu_set, DPU_BINARY, NULL)");
+    let push_plan = position("\tpush_tasklet_plan(dpu_set);");
+    assert!(main < load && load < push_plan && push_plan < launch);
+    let push_nodes = position("\t\tpush_nodes(dpu_set);");
+    let pull_nodes = position("\t\tpull_nodes(dpu_set);");
+    assert!(broadcast < push_nodes && push_nodes < launch);
+    assert!(launch < pull_nodes && pull_nodes < write_back);
+    assert!(code.contains("&hub_replicas[1]"));
+    let twelve_bytes = plan.dpus.iter().find(|dpu| !dpu.nodes.is_empty()).unwrap();
+    assert_eq!(twelve_bytes.addrs()[..2], [0, 16]);
+    Ok(())
+}
+
+#[test]
+fn test_task_cache_fits_wram_share() -> Result<()> {
+    use crate::graph_cut::{DpuTasklets, TaskletPlan};
+
+    let plan = TaskletPlan {
+        tasklets: 2,
+        share: 100,
+        block: 96,
+        dpus: vec![DpuTasklets {
+            nodes: vec![0],
+            offsets: vec![0, 1, 1],
+            bytes: vec![4],
+            work: vec![4, 0],
+        }],
+    };
+    let file_name =
+        std::env::temp_dir().join(format!("dataspatialpim_task_{}.c", std::process::id()));
+    write_to_task(file_name.to_str().unwrap(), &plan)?;
+    let code = fs::read_to_string(&file_name)?;
+    fs::remove_file(&file_name)?;
+    // The cache the tasklets allocate is the one the plan fits in WRAM.
+    assert!(code.contains("#define TASKLET_CACHE_BYTES 96"));
+    assert!(code.contains("mem_alloc(TASKLET_CACHE_BYTES)"));
+    assert!(!code.contains("BLOCK_SIZE"));
+    Ok(())
+}
@@ -73,6 +73,7 @@ pub fn test_node_code_gen() {
             varname: String::from("field"),
             pim_type: PIMType::Basic(PIMBaseType::Char),
         }],
+        cost: None,
     };
     _node.type_code();
 }
@@ -0,0 +1,432 @@
+use crate::base_type::{NamedBlock, PIMBaseType, PIMField, PIMType};
+use crate::sem_type::{SemanticEdge, SemanticEdgeInst, SemanticGraph, SemanticNodeInst};
+use anyhow::Result;
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GeneratorError {
+    #[error("Edge type `{0}` must connect a node type to itself to be used by the generator")]
+    MixedEdgeType(String),
+    #[error("Cannot build a {0}-regular graph on {1} nodes")]
+    ImpossibleRegularGraph(usize, usize),
+}
+
+/// Shapes of synthetic graphs the generator knows how to build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphKind {
+    /// `edges` distinct edges picked uniformly at random (Erdős–Rényi G(n, m)).
+    Uniform,
+    /// Recursive-matrix graph with a power-law degree distribution.
+    Rmat,
+    /// 2D grid, each node linked to its right and lower neighbor.
+    Grid,
+    /// A single path n0 -> n1 -> ... -> n(k-1).
+    Chain,
+    /// Node n0 linked to every other node.
+    Star,
+    /// Every node has exactly `degree` neighbors.
+    Regular,
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub kind: GraphKind,
+    pub nodes: usize,
+    /// Number of edges for `Uniform` and `Rmat`, ignored by the other kinds.
+    pub edges: usize,
+    /// Degree for `Regular`, ignored by the other kinds.
+    pub degree: usize,
+    /// Edge weights are drawn uniformly from `1..=max_weight`.
+    pub max_weight: i64,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            kind: GraphKind::Uniform,
+            nodes: 16,
+            edges: 32,
+            degree: 3,
+            max_weight: 1,
+            seed: 0,
+        }
+    }
+}
+
+/// The node and edge types used when the caller does not provide any.
+pub fn default_types() -> Rc<SemanticEdge> {
+    let vertex = Rc::new(NamedBlock {
+        name: String::from("Vertex"),
+        fields: vec![PIMField {
+            varname: String::from("value"),
+            pim_type: PIMType::Basic(PIMBaseType::Int32),
+        }],
+        cost: None,
+    });
+    Rc::new(SemanticEdge {
+        from: vertex.clone(),
+        to: vertex,
+        named_block: NamedBlock {
+            name: String::from("Link"),
+            fields: vec![],
+            cost: None,
+        },
+    })
+}
+
+/// Build a reproducible graph of the given shape whose nodes are `edge_type.from`
+/// instances and whose edges are `edge_type` instances.
+pub fn generate_graph(
+    config: &GeneratorConfig,
+    edge_type: &Rc<SemanticEdge>,
+) -> Result<SemanticGraph> {
+    if edge_type.from.name != edge_type.to.name {
+        return Err(GeneratorError::MixedEdgeType(edge_type.named_block.name.clone()).into());
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let n = config.nodes;
+    let pairs = match config.kind {
+        GraphKind::Uniform => uniform_pairs(&mut rng, n, config.edges),
+        GraphKind::Rmat => rmat_pairs(&mut rng, n, config.edges),
+        GraphKind::Grid => grid_pairs(n),
+        GraphKind::Chain => (1..n).map(|i| (i - 1, i)).collect(),
+        GraphKind::Star => (1..n).map(|i| (0, i)).collect(),
+        GraphKind::Regular => regular_pairs(&mut rng, n, config.degree)?,
+    };
+
+    let node_insts: Vec<Rc<SemanticNodeInst>> = (0..n)
+        .map(|i| {
+            Rc::new(SemanticNodeInst {
+                varname: format!("n{}", i),
+                node_type: edge_type.from.clone(),
+                replicate: None,
+                cost: None,
+                heat: None,
+            })
+        })
+        .collect();
+    let max_weight = config.max_weight.max(1);
+    let edge_insts = pairs
+        .into_iter()
+        .map(|(from, to)| {
+            Rc::new(SemanticEdgeInst {
+                edge_type: edge_type.clone(),
+                from_var: node_insts[from].clone(),
+                to_var: node_insts[to].clone(),
+                weight: rng.gen_range(1..=max_weight),
+            })
+        })
+        .collect();
+
+    Ok(SemanticGraph {
+        node_insts,
+        edge_insts,
+        walker_insts: vec![],
+        constraints: Default::default(),
+    })
+}
+
+fn uniform_pairs(rng: &mut ChaCha8Rng, n: usize, edges: usize) -> Vec<(usize, usize)> {
+    let edges = edges.min(n * n.saturating_sub(1));
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::with_capacity(edges);
+    while pairs.len() < edges {
+        let from = rng.gen_range(0..n);
+        let to = rng.gen_range(0..n);
+        if from != to && seen.insert((from, to)) {
+            pairs.push((from, to));
+        }
+    }
+    pairs
+}
+
+fn rmat_pairs(rng: &mut ChaCha8Rng, n: usize, edges: usize) -> Vec<(usize, usize)> {
+    // Quadrant probabilities from the Graph500 specification.
+    const A: f64 = 0.57;
+    const B: f64 = 0.19;
+    const C: f64 = 0.19;
+    let edges = edges.min(n * n.saturating_sub(1));
+    let scale = n.next_power_of_two().trailing_zeros();
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::with_capacity(edges);
+    while pairs.len() < edges {
+        let (mut from, mut to) = (0, 0);
+        for bit in 0..scale {
+            let p: f64 = rng.gen();
+            let (down, right) = if p < A {
+                (0, 0)
+            } else if p < A + B {
+                (0, 1)
+            } else if p < A + B + C {
+                (1, 0)
+            } else {
+                (1, 1)
+            };
+            from |= down << bit;
+            to |= right << bit;
+        }
+        if from < n && to < n && from != to && seen.insert((from, to)) {
+            pairs.push((from, to));
+        }
+    }
+    pairs
+}
+
+fn grid_pairs(n: usize) -> Vec<(usize, usize)> {
+    let width = (n as f64).sqrt().ceil().max(1.0) as usize;
+    let mut pairs = vec![];
+    for i in 0..n {
+        if (i + 1) % width != 0 && i + 1 < n {
+            pairs.push((i, i + 1));
+        }
+        if i + width < n {
+            pairs.push((i, i + width));
+        }
+    }
+    pairs
+}
+
+fn regular_pairs(rng: &mut ChaCha8Rng, n: usize, degree: usize) -> Result<Vec<(usize, usize)>> {
+    if degree >= n.max(1) || !(n * degree).is_multiple_of(2) {
+        return Err(GeneratorError::ImpossibleRegularGraph(degree, n).into());
+    }
+    // Dense graphs are built as the complement of a sparse one.
+    if n > 0 && 2 * degree > n - 1 {
+        let sparse: HashSet<(usize, usize)> =
+            regular_pairs(rng, n, n - 1 - degree)?.into_iter().collect();
+        return Ok((0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .filter(|pair| !sparse.contains(pair))
+            .collect());
+    }
+    // Configuration model: pair up `degree` stubs per node, then repair self loops and
+    // parallel edges by switching their endpoints with those of a random other pair, as
+    // long as the switch creates neither. Switches keep every degree, and a pair that is
+    // fine is never broken again.
+    let ordered = |a: usize, b: usize| (a.min(b), a.max(b));
+    let mut stubs: Vec<usize> = (0..n).flat_map(|i| vec![i; degree]).collect();
+    stubs.shuffle(rng);
+    let mut pairs: Vec<(usize, usize)> = stubs.chunks(2).map(|s| ordered(s[0], s[1])).collect();
+    let mut count: HashMap<(usize, usize), usize> = HashMap::new();
+    for pair in &pairs {
+        *count.entry(*pair).or_insert(0) += 1;
+    }
+    let bad = |pair: (usize, usize), count: &HashMap<(usize, usize), usize>| {
+        pair.0 == pair.1 || count[&pair] > 1
+    };
+    let mut next = 0;
+    for _ in 0..100 * pairs.len() + 1000 {
+        while next < pairs.len() && !bad(pairs[next], &count) {
+            next += 1;
+        }
+        if next == pairs.len() {
+            return Ok(pairs);
+        }
+        let other = rng.gen_range(0..pairs.len());
+        let ((a, b), (c, d)) = (pairs[next], pairs[other]);
+        let (p, q) = if rng.gen_bool(0.5) {
+            (ordered(a, c), ordered(b, d))
+        } else {
+            (ordered(a, d), ordered(b, c))
+        };
+        if other == next || p.0 == p.1 || q.0 == q.1 || p == q {
+            continue;
+        }
+        if count.get(&p).is_some_and(|c| *c > 0) || count.get(&q).is_some_and(|c| *c > 0) {
+            continue;
+        }
+        for old in [pairs[next], pairs[other]] {
+            *count.get_mut(&old).unwrap() -= 1;
+        }
+        *count.entry(p).or_insert(0) += 1;
+        *count.entry(q).or_insert(0) += 1;
+        pairs[next] = p;
+        pairs[other] = q;
+    }
+    Err(GeneratorError::ImpossibleRegularGraph(degree, n).into())
+}
+
+fn block_source(block: &NamedBlock) -> String {
+    let fields: Vec<String> = block
+        .fields
+        .iter()
+        .map(|field| match &field.pim_type {
+            PIMType::Basic(t) => format!("  {}: {};\n", field.varname, base_type_source(t)),
+            PIMType::Array(t, num) => {
+                format!("  {}: {}[{}];\n", field.varname, base_type_source(t), num)
+            }
+        })
+        .collect();
+    format!("{} {{\n{}}};\n", block.name, fields.concat())
+}
+
+fn base_type_source(t: &PIMBaseType) -> &'static str {
+    match t {
+        PIMBaseType::Int8 => "int8",
+        PIMBaseType::Int16 => "int16",
+        PIMBaseType::Int32 => "int32",
+        PIMBaseType::Int64 => "int64",
+        PIMBaseType::Float => "float",
+        PIMBaseType::Double => "double",
+        PIMBaseType::Char => "char",
+    }
+}
+
+/// Render a generated graph, together with its node and edge type, as `.dspim` source.
+pub fn graph_to_dspim(edge_type: &SemanticEdge, graph: &SemanticGraph) -> String {
+    let mut result = String::new();
+    result.push_str(&format!("node {}\n", block_source(&edge_type.from)));
+    result.push_str(&format!(
+        "edge {} {} {}\n",
+        edge_type.from.name,
+        edge_type.to.name,
+        block_source(&edge_type.named_block)
+    ));
+    result.push_str("graph {\n");
+    for node in &graph.node_insts {
+        result.push_str(&format!(
+            "  node {} {};\n",
+            node.node_type.name, node.varname
+        ));
+    }
+    for edge in &graph.edge_insts {
+        result.push_str(&format!(
+            "  edge {} {} {} {};\n",
+            edge.edge_type.named_block.name,
+            edge.from_var.varname,
+            edge.to_var.varname,
+            edge.weight
+        ));
+    }
+    result.push_str("};\n");
+    result
+}
+
+#[test]
+fn test_generators() -> Result<()> {
+    let edge_type = default_types();
+    let config = |kind| GeneratorConfig {
+        kind,
+        nodes: 64,
+        edges: 200,
+        degree: 4,
+        max_weight: 10,
+        seed: 7,
+    };
+
+    let uniform = generate_graph(&config(GraphKind::Uniform), &edge_type)?;
+    assert_eq!(uniform.node_insts.len(), 64);
+    assert_eq!(uniform.edge_insts.len(), 200);
+    assert!(uniform
+        .edge_insts
+        .iter()
+        .all(|e| (1..=10).contains(&e.weight)));
+
+    let rmat = generate_graph(&config(GraphKind::Rmat), &edge_type)?;
+    assert_eq!(rmat.edge_insts.len(), 200);
+    assert!(rmat
+        .edge_insts
+        .iter()
+        .all(|e| e.from_var.varname != e.to_var.varname));
+
+    // An 8x8 grid has 2 * 8 * 7 links.
+    assert_eq!(
+        generate_graph(&config(GraphKind::Grid), &edge_type)?
+            .edge_insts
+            .len(),
+        112
+    );
+    assert_eq!(
+        generate_graph(&config(GraphKind::Chain), &edge_type)?
+            .edge_insts
+            .len(),
+        63
+    );
+    assert_eq!(
+        generate_graph(&config(GraphKind::Star), &edge_type)?
+            .edge_insts
+            .len(),
+        63
+    );
+
+    let regular = generate_graph(&config(GraphKind::Regular), &edge_type)?;
+    let mut degrees = vec![0; 64];
+    for edge in &regular.edge_insts {
+        degrees[edge.from_var.varname[1..].parse::<usize>()?] += 1;
+        degrees[edge.to_var.varname[1..].parse::<usize>()?] += 1;
+    }
+    assert!(degrees.iter().all(|d| *d == 4));
+    Ok(())
+}
+
+#[test]
+fn test_regular_high_degree() -> Result<()> {
+    let edge_type = default_types();
+    for (nodes, degree) in [(64, 8), (200, 12), (10, 7)] {
+        let config = GeneratorConfig {
+            kind: GraphKind::Regular,
+            nodes,
+            degree,
+            ..Default::default()
+        };
+        let graph = generate_graph(&config, &edge_type)?;
+        let mut degrees = vec![0; nodes];
+        let mut seen = HashSet::new();
+        for edge in &graph.edge_insts {
+            let from = edge.from_var.varname[1..].parse::<usize>()?;
+            let to = edge.to_var.varname[1..].parse::<usize>()?;
+            assert_ne!(from, to);
+            assert!(seen.insert((from.min(to), from.max(to))));
+            degrees[from] += 1;
+            degrees[to] += 1;
+        }
+        assert!(degrees.iter().all(|d| *d == degree));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_generator_is_reproducible() -> Result<()> {
+    let edge_type = default_types();
+    let config = GeneratorConfig {
+        kind: GraphKind::Rmat,
+        nodes: 100,
+        edges: 400,
+        max_weight: 5,
+        seed: 42,
+        ..Default::default()
+    };
+    let first = graph_to_dspim(&edge_type, &generate_graph(&config, &edge_type)?);
+    let second = graph_to_dspim(&edge_type, &generate_graph(&config, &edge_type)?);
+    assert_eq!(first, second);
+    let other_seed = GeneratorConfig { seed: 43, ..config };
+    assert_ne!(
+        first,
+        graph_to_dspim(&edge_type, &generate_graph(&other_seed, &edge_type)?)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_generated_source_parses() -> Result<()> {
+    use crate::parser::parse_str;
+    use crate::semantics_analysis::semantic_analysis;
+
+    let edge_type = default_types();
+    let config = GeneratorConfig {
+        kind: GraphKind::Grid,
+        nodes: 9,
+        ..Default::default()
+    };
+    let graph = generate_graph(&config, &edge_type)?;
+    let sem = semantic_analysis(parse_str(&graph_to_dspim(&edge_type, &graph))?)?;
+    assert_eq!(sem.graphs[0].node_insts.len(), 9);
+    assert_eq!(sem.graphs[0].edge_insts.len(), 12);
+    Ok(())
+}
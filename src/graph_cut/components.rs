@@ -0,0 +1,211 @@
+use super::graph::PartitionGraph;
+use super::{DataMappingError, HardwareConfig, SolveStatus};
+use anyhow::Result;
+use std::thread;
+
+/// Partition each connected component of `graph` on its own and pack the results onto the
+/// DPUs. A component that fits on one DPU, within its byte capacity and the cost limit of
+/// the graph, and has no negative edge weight is kept whole;
+/// the others are handed to `solve` in parallel threads, each on its own share of the
+/// DPUs. The whole components then go first-fit, largest first, into the room left.
+///
+/// Returns `None` when the graph is connected or the pieces cannot be packed, in which
+/// case the graph should be solved as a whole.
+pub fn solve_by_components<F>(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    solve: F,
+) -> Result<Option<(Vec<usize>, SolveStatus)>>
+where
+    F: Fn(&PartitionGraph, &HardwareConfig) -> Result<(Vec<usize>, SolveStatus)> + Sync,
+{
+    let components = graph.components();
+    if components.len() < 2 {
+        return Ok(None);
+    }
+    let core_num = hardware.core_num as usize;
+    let capacity = hardware.core_size;
+    let subgraphs: Vec<PartitionGraph> = components
+        .iter()
+        .map(|nodes| graph.subgraph(nodes))
+        .collect();
+    let (split, whole): (Vec<usize>, Vec<usize>) = (0..components.len()).partition(|c| {
+        let subgraph = &subgraphs[*c];
+        subgraph.total_load() > capacity
+            || subgraph
+                .cost_limit
+                .is_some_and(|limit| subgraph.total_cost() > limit)
+            || subgraph.edges.iter().any(|edge| edge.weight < 0)
+    });
+
+    // Share the DPUs among the split components in proportion to their size, giving each
+    // at least as many as its bytes need.
+    let loads: Vec<i64> = split.iter().map(|c| subgraphs[*c].total_load()).collect();
+    let total: i64 = loads.iter().sum();
+    let mut shares: Vec<usize> = loads
+        .iter()
+        .map(|load| {
+            let needed = (*load + capacity - 1) / capacity.max(1);
+            let fair = (core_num as i64 * load / total.max(1)).max(needed).max(1);
+            fair as usize
+        })
+        .collect();
+    while shares.iter().sum::<usize>() > core_num {
+        let Some((largest, _)) = shares
+            .iter()
+            .enumerate()
+            .filter(|(i, share)| **share as i64 * capacity > loads[*i])
+            .max_by_key(|(_, share)| **share)
+        else {
+            return Ok(None);
+        };
+        shares[largest] -= 1;
+        if shares[largest] == 0 {
+            return Ok(None);
+        }
+    }
+
+    let results: Vec<Result<(Vec<usize>, SolveStatus)>> = thread::scope(|scope| {
+        let handles: Vec<_> = split
+            .iter()
+            .zip(&shares)
+            .map(|(c, share)| {
+                let subgraph = &subgraphs[*c];
+                let solve = &solve;
+                scope.spawn(move || {
+                    solve(
+                        subgraph,
+                        &HardwareConfig {
+                            core_size: capacity,
+                            core_num: *share as i64,
+                            rank_size: None,
+                            edge_placement: hardware.edge_placement,
+                        },
+                    )
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("component solver panicked"))
+            .collect()
+    });
+
+    let mut assignment = vec![0; graph.node_count()];
+    let mut core_loads = vec![0; core_num];
+    let mut core_costs = vec![0; core_num];
+    let mut first_core = 0;
+    let mut statuses = vec![];
+    for ((c, share), result) in split.iter().zip(&shares).zip(results) {
+        let (local, status) = match result {
+            Ok(solution) => solution,
+            Err(e) if matches!(e.downcast_ref(), Some(DataMappingError::Unsatisfiable(_))) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        for (node, core) in components[*c].iter().zip(&local) {
+            assignment[*node] = first_core + core;
+        }
+        for (core, load) in subgraphs[*c].core_loads(&local, *share).iter().enumerate() {
+            core_loads[first_core + core] += load;
+        }
+        for (core, cost) in subgraphs[*c].core_costs(&local, *share).iter().enumerate() {
+            core_costs[first_core + core] += cost;
+        }
+        first_core += share;
+        statuses.push(status);
+    }
+
+    let mut whole = whole;
+    whole.sort_by_key(|c| std::cmp::Reverse(subgraphs[*c].total_load()));
+    for c in whole {
+        let load = subgraphs[c].total_load();
+        let cost = subgraphs[c].total_cost();
+        let Some(core) = (0..core_num).find(|core| {
+            core_loads[*core] + load <= capacity
+                && graph
+                    .cost_limit
+                    .is_none_or(|limit| core_costs[*core] + cost <= limit)
+        }) else {
+            return Ok(None);
+        };
+        core_loads[core] += load;
+        core_costs[core] += cost;
+        for node in &components[c] {
+            assignment[*node] = core;
+        }
+    }
+
+    // Whole components cut nothing, so a single split component solved on every DPU
+    // carries its status over to the graph. Otherwise its share of the DPUs may have
+    // been too small for the global optimum.
+    let status = match (statuses.as_slice(), shares.as_slice()) {
+        ([], _) => SolveStatus::Optimal,
+        ([status], [share]) if *share == core_num => *status,
+        _ => SolveStatus::Unknown,
+    };
+    Ok(Some((assignment, status)))
+}
+
+#[cfg(feature = "z3")]
+#[test]
+fn test_components_are_solved_apart() -> Result<()> {
+    use super::Z3Encoding;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Alice nodes a0 - a1 - a2 and Bob nodes b0 - b1 are two components.
+    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
+    let sm = semantic_analysis(parse_str(&file_context)?)?;
+    let graph = PartitionGraph::from_semantic(&sm.graphs[0])?;
+    assert_eq!(graph.components(), vec![vec![0, 1, 2], vec![3, 4]]);
+
+    // Each component fits on a DPU: nothing to solve, nothing cut.
+    let calls = AtomicUsize::new(0);
+    let solve = |g: &PartitionGraph, h: &HardwareConfig| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        super::smt::solve_graph(
+            g,
+            h.core_size,
+            h.core_num,
+            Z3Encoding::OneHot,
+            &Default::default(),
+            &Default::default(),
+        )
+    };
+    let hardware = HardwareConfig {
+        core_size: 6,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let (assignment, status) = solve_by_components(&graph, &hardware, solve)?.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    assert_eq!(status, SolveStatus::Optimal);
+    assert_eq!(graph.cut_weight(&assignment), 0);
+
+    // Neither component (5 bytes each) fits on a 4-byte DPU, and both need two DPUs.
+    let hardware = HardwareConfig {
+        core_size: 4,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    assert!(solve_by_components(&graph, &hardware, solve)?.is_none());
+    let hardware = HardwareConfig {
+        core_size: 4,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let (assignment, status) = solve_by_components(&graph, &hardware, solve)?.unwrap();
+    assert_eq!(status, SolveStatus::Unknown);
+    assert_eq!(graph.cut_weight(&assignment), 110);
+    assert!(graph
+        .core_loads(&assignment, 4)
+        .iter()
+        .all(|load| *load <= 4));
+    Ok(())
+}
@@ -0,0 +1,515 @@
+use super::{DataMappingError, EdgePlacement, HardwareConfig};
+use crate::base_type::Size;
+use crate::sem_type::{SemanticEdgeInst, SemanticGraph, SemanticNodeInst};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct GraphEdge {
+    pub from: usize,
+    pub to: usize,
+    pub weight: i64,
+    /// Bytes of the edge struct, charged to every core holding one of its endpoints. Zero
+    /// for an edge stored with a single endpoint, whose size holds its bytes instead.
+    pub size: i64,
+}
+
+/// Index-based view of a `SemanticGraph` that the heuristic partitioners work on.
+/// Node `i` is `SemanticGraph::node_insts[i]`.
+#[derive(Debug, Clone)]
+pub struct PartitionGraph {
+    pub node_sizes: Vec<i64>,
+    /// Compute cost of each node, balanced across cores on request.
+    pub node_costs: Vec<i64>,
+    /// Most compute cost a core may take, when balance is constrained.
+    pub cost_limit: Option<i64>,
+    pub edges: Vec<GraphEdge>,
+    /// The core each node is pinned to, if any.
+    pub fixed: Vec<Option<usize>>,
+    /// The nodes each node may not share a core with.
+    pub separated: Vec<Vec<usize>>,
+    /// Indices into `edges` of the edges touching each node.
+    incident: Vec<Vec<usize>>,
+}
+
+impl PartitionGraph {
+    /// A graph whose node costs are the node sizes.
+    pub fn new(node_sizes: Vec<i64>, edges: Vec<GraphEdge>) -> PartitionGraph {
+        let mut incident = vec![vec![]; node_sizes.len()];
+        for (i, edge) in edges.iter().enumerate() {
+            incident[edge.from].push(i);
+            if edge.to != edge.from {
+                incident[edge.to].push(i);
+            }
+        }
+        PartitionGraph {
+            node_costs: node_sizes.clone(),
+            cost_limit: None,
+            fixed: vec![None; node_sizes.len()],
+            separated: vec![vec![]; node_sizes.len()],
+            node_sizes,
+            edges,
+            incident,
+        }
+    }
+
+    pub fn with_costs(self, node_costs: Vec<i64>) -> PartitionGraph {
+        debug_assert_eq!(node_costs.len(), self.node_count());
+        PartitionGraph { node_costs, ..self }
+    }
+
+    pub fn from_semantic(graph: &SemanticGraph) -> Result<PartitionGraph> {
+        PartitionGraph::from_parts(&graph.node_insts, &graph.edge_insts)
+    }
+
+    /// The graph with its edges charged where `placement` stores them: an edge stored with
+    /// one endpoint only is folded into the size of that endpoint.
+    pub fn with_edge_placement(mut self, placement: EdgePlacement) -> PartitionGraph {
+        for edge in &mut self.edges {
+            let owner = match placement {
+                EdgePlacement::Source => edge.from,
+                EdgePlacement::Destination => edge.to,
+                EdgePlacement::Both => continue,
+            };
+            self.node_sizes[owner] += edge.size;
+            edge.size = 0;
+        }
+        self
+    }
+
+    /// The graph of `graph` on `hardware`, with its edges placed as the hardware stores them
+    /// and under its placement constraints: each group of colocated nodes is merged into one
+    /// node, and the node of the result holding every original node is returned with it.
+    /// Fails when the constraints contradict each other or pin a node beyond the DPUs.
+    pub fn constrained(
+        graph: &SemanticGraph,
+        hardware: &HardwareConfig,
+    ) -> Result<(PartitionGraph, Vec<usize>)> {
+        let core_num = hardware.core_num as usize;
+        let base =
+            PartitionGraph::from_semantic(graph)?.with_edge_placement(hardware.edge_placement);
+        let constraints = &graph.constraints;
+        let names: Vec<&String> = graph.node_insts.iter().map(|node| &node.varname).collect();
+        let index: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+
+        // Union-find over the colocated pairs, numbering groups by their first node.
+        let mut parent: Vec<usize> = (0..base.node_count()).collect();
+        fn root(parent: &mut [usize], mut node: usize) -> usize {
+            while parent[node] != node {
+                parent[node] = parent[parent[node]];
+                node = parent[node];
+            }
+            node
+        }
+        for (a, b) in &constraints.colocate {
+            let (a, b) = (root(&mut parent, find(a)?), root(&mut parent, find(b)?));
+            parent[a.max(b)] = a.min(b);
+        }
+        let mut group = vec![usize::MAX; base.node_count()];
+        let mut group_num = 0;
+        for node in 0..base.node_count() {
+            let r = root(&mut parent, node);
+            if group[r] == usize::MAX {
+                group[r] = group_num;
+                group_num += 1;
+            }
+            group[node] = group[r];
+        }
+
+        let (mut node_sizes, mut node_costs) = (vec![0; group_num], vec![0; group_num]);
+        for node in 0..base.node_count() {
+            node_sizes[group[node]] += base.node_sizes[node];
+            node_costs[group[node]] += base.node_costs[node];
+        }
+        let edges = base
+            .edges
+            .iter()
+            .map(|edge| GraphEdge {
+                from: group[edge.from],
+                to: group[edge.to],
+                ..edge.clone()
+            })
+            .collect();
+        let mut merged = PartitionGraph::new(node_sizes, edges).with_costs(node_costs);
+
+        let mut pinned_by: Vec<Option<usize>> = vec![None; group_num];
+        for (name, dpu) in &constraints.pins {
+            let node = find(name)?;
+            if *dpu >= core_num {
+                return Err(DataMappingError::CoreOutOfRange(name.clone(), *dpu, core_num).into());
+            }
+            let g = group[node];
+            match (merged.fixed[g], pinned_by[g]) {
+                (Some(other), Some(by)) if other != *dpu => {
+                    return Err(DataMappingError::ConflictingPins(
+                        names[by].clone(),
+                        name.clone(),
+                        other,
+                        *dpu,
+                    )
+                    .into())
+                }
+                _ => {
+                    merged.fixed[g] = Some(*dpu);
+                    pinned_by[g] = Some(node);
+                }
+            }
+        }
+        for (a, b) in &constraints.separate {
+            let (ga, gb) = (group[find(a)?], group[find(b)?]);
+            if ga == gb || (merged.fixed[ga].is_some() && merged.fixed[ga] == merged.fixed[gb]) {
+                return Err(DataMappingError::ConflictingSeparation(a.clone(), b.clone()).into());
+            }
+            if !merged.separated[ga].contains(&gb) {
+                merged.separated[ga].push(gb);
+                merged.separated[gb].push(ga);
+            }
+        }
+        Ok((merged, group))
+    }
+
+    pub fn from_parts(
+        nodes: &[Rc<SemanticNodeInst>],
+        edges: &[Rc<SemanticEdgeInst>],
+    ) -> Result<PartitionGraph> {
+        let index: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+        let edges = edges
+            .iter()
+            .map(|edge| -> Result<GraphEdge> {
+                Ok(GraphEdge {
+                    from: find(&edge.from_var.varname)?,
+                    to: find(&edge.to_var.varname)?,
+                    weight: edge.weight,
+                    size: edge.edge_type.named_block.size_byte(),
+                })
+            })
+            .collect::<Result<Vec<GraphEdge>>>()?;
+        let node_sizes = nodes
+            .iter()
+            .map(|node| node.node_type.size_byte())
+            .collect();
+        // Costs are summed per core, so their total must fit as well.
+        let mut total_cost: i64 = 0;
+        let node_costs = nodes
+            .iter()
+            .map(|node| -> Result<i64> {
+                let overflow = || DataMappingError::CostOverflow(node.varname.clone());
+                let cost = node.checked_cost().ok_or_else(overflow)?;
+                total_cost = total_cost.checked_add(cost).ok_or_else(overflow)?;
+                Ok(cost)
+            })
+            .collect::<Result<Vec<i64>>>()?;
+        Ok(PartitionGraph::new(node_sizes, edges).with_costs(node_costs))
+    }
+
+    /// The connected components, each as its nodes in increasing order.
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        let mut component = vec![usize::MAX; self.node_count()];
+        let mut components: Vec<Vec<usize>> = vec![];
+        for root in 0..self.node_count() {
+            if component[root] != usize::MAX {
+                continue;
+            }
+            component[root] = components.len();
+            let mut nodes = vec![root];
+            let mut next = 0;
+            while let Some(&node) = nodes.get(next) {
+                next += 1;
+                for &edge in &self.incident[node] {
+                    let other = self.other_end(edge, node);
+                    if component[other] == usize::MAX {
+                        component[other] = components.len();
+                        nodes.push(other);
+                    }
+                }
+            }
+            nodes.sort_unstable();
+            components.push(nodes);
+        }
+        components
+    }
+
+    /// The graph induced by `nodes`, whose node `i` is `nodes[i]`.
+    pub fn subgraph(&self, nodes: &[usize]) -> PartitionGraph {
+        let mut local = vec![usize::MAX; self.node_count()];
+        for (i, node) in nodes.iter().enumerate() {
+            local[*node] = i;
+        }
+        let edges = self
+            .edges
+            .iter()
+            .filter(|edge| local[edge.from] != usize::MAX && local[edge.to] != usize::MAX)
+            .map(|edge| GraphEdge {
+                from: local[edge.from],
+                to: local[edge.to],
+                ..edge.clone()
+            })
+            .collect();
+        let node_sizes = nodes.iter().map(|node| self.node_sizes[*node]).collect();
+        let node_costs = nodes.iter().map(|node| self.node_costs[*node]).collect();
+        let mut subgraph = PartitionGraph::new(node_sizes, edges).with_costs(node_costs);
+        subgraph.cost_limit = self.cost_limit;
+        for (i, node) in nodes.iter().enumerate() {
+            subgraph.fixed[i] = self.fixed[*node];
+            subgraph.separated[i] = self.separated[*node]
+                .iter()
+                .filter(|other| local[**other] != usize::MAX)
+                .map(|other| local[*other])
+                .collect();
+        }
+        subgraph
+    }
+
+    /// Whether some node is pinned or separated from another.
+    pub fn is_constrained(&self) -> bool {
+        self.fixed.iter().any(Option::is_some) || self.separated.iter().any(|s| !s.is_empty())
+    }
+
+    /// Whether `node` may go on `core` given the core of the other nodes, if placed.
+    pub fn allowed(
+        &self,
+        node: usize,
+        core: usize,
+        core_of: impl Fn(usize) -> Option<usize>,
+    ) -> bool {
+        self.fixed[node].is_none_or(|fixed| fixed == core)
+            && self.separated[node]
+                .iter()
+                .all(|other| core_of(*other) != Some(core))
+    }
+
+    /// Bytes the whole graph takes on a single core.
+    pub fn total_load(&self) -> i64 {
+        self.node_sizes.iter().sum::<i64>() + self.edges.iter().map(|edge| edge.size).sum::<i64>()
+    }
+
+    pub fn total_cost(&self) -> i64 {
+        self.node_costs.iter().sum()
+    }
+
+    /// Compute cost on each core of a complete assignment.
+    pub fn core_costs(&self, assignment: &[usize], core_num: usize) -> Vec<i64> {
+        let mut costs = vec![0; core_num];
+        for (node, core) in assignment.iter().enumerate() {
+            costs[*core] += self.node_costs[node];
+        }
+        costs
+    }
+
+    /// Whether a core already costing `cost` can take `node` without exceeding the limit.
+    pub fn cost_fits(&self, node: usize, cost: i64) -> bool {
+        self.cost_limit
+            .is_none_or(|limit| cost + self.node_costs[node] <= limit)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_sizes.len()
+    }
+
+    pub fn incident(&self, node: usize) -> &[usize] {
+        &self.incident[node]
+    }
+
+    /// The endpoint of `edge` that is not `node`.
+    pub fn other_end(&self, edge: usize, node: usize) -> usize {
+        let edge = &self.edges[edge];
+        if edge.from == node {
+            edge.to
+        } else {
+            edge.from
+        }
+    }
+
+    /// Bytes `node` occupies on `core` given where the other nodes are: its own size plus
+    /// every incident edge that is not already charged to `core` through its other endpoint.
+    pub fn load_on(&self, node: usize, core: usize, placement: &[Option<usize>]) -> i64 {
+        let mut load = self.node_sizes[node];
+        for &edge in &self.incident[node] {
+            let other = self.other_end(edge, node);
+            if other == node || placement[other] != Some(core) {
+                load += self.edges[edge].size;
+            }
+        }
+        load
+    }
+
+    /// Bytes used on each core by a complete assignment.
+    pub fn core_loads(&self, assignment: &[usize], core_num: usize) -> Vec<i64> {
+        let mut loads = vec![0; core_num];
+        for (node, core) in assignment.iter().enumerate() {
+            loads[*core] += self.node_sizes[node];
+        }
+        for edge in &self.edges {
+            loads[assignment[edge.from]] += edge.size;
+            if assignment[edge.to] != assignment[edge.from] {
+                loads[assignment[edge.to]] += edge.size;
+            }
+        }
+        loads
+    }
+
+    pub fn cut_weight(&self, assignment: &[usize]) -> i64 {
+        self.edges
+            .iter()
+            .filter(|edge| assignment[edge.from] != assignment[edge.to])
+            .map(|edge| edge.weight)
+            .sum()
+    }
+
+    /// Weight of the edges whose endpoints lie on different ranks.
+    pub fn rank_cut_weight(&self, assignment: &[usize], hardware: &HardwareConfig) -> i64 {
+        self.edges
+            .iter()
+            .filter(|edge| {
+                hardware.rank_of(assignment[edge.from]) != hardware.rank_of(assignment[edge.to])
+            })
+            .map(|edge| edge.weight)
+            .sum()
+    }
+}
+
+#[test]
+fn test_load_on_matches_core_loads() -> Result<()> {
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::fs;
+
+    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
+    let sm = semantic_analysis(parse_str(&file_context)?)?;
+    let graph = PartitionGraph::from_semantic(&sm.graphs[0])?;
+    let assignment = vec![0, 0, 1, 1, 2];
+    let mut placement = vec![None; graph.node_count()];
+    let mut loads = vec![0; 3];
+    for (node, core) in assignment.iter().enumerate() {
+        loads[*core] += graph.load_on(node, *core, &placement);
+        placement[node] = Some(*core);
+    }
+    // Edge a1 -> a2 and edge b0 -> b1 are cut and charged on both sides.
+    assert_eq!(loads, vec![4, 5, 3]);
+    assert_eq!(graph.core_loads(&assignment, 3), loads);
+    assert_eq!(graph.cut_weight(&assignment), 110);
+    Ok(())
+}
+
+#[test]
+fn test_placement_constraints() -> Result<()> {
+    use super::{
+        Balance, GreedyPartitioner, HierarchicalPartitioner, MilpPartitioner,
+        MultilevelPartitioner, Partitioner, StreamHeuristic, StreamingPartitioner, VisitOrder,
+    };
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    let analyze = |graph: &str| {
+        let source = format!(
+            "node Alice {{ n1: int32; }}; edge Alice Alice Link {{ n1: int8; }}; graph {{ {} }};",
+            graph
+        );
+        semantic_analysis(parse_str(&source)?).map(|sm| sm.graphs[0].clone())
+    };
+    // A chain a0 - a5 with a0 pinned to the last DPU, a5 next to it, and a1, a2 apart.
+    let g = analyze(
+        "@pin(dpu = 3) node Alice a0; node Alice a1, a2, a3, a4, a5;
+         edge Link a0 a1 3; edge Link a1 a2 3; edge Link a2 a3 3; edge Link a3 a4 3;
+         edge Link a4 a5 3; @colocate(a0, a5); @separate(a1, a2);",
+    )?;
+    let hardware = HardwareConfig {
+        core_size: 64,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partitioners: Vec<(Box<dyn Partitioner>, HardwareConfig)> = vec![
+        (Box::new(MilpPartitioner::default()), hardware.clone()),
+        (
+            Box::new(GreedyPartitioner {
+                order: VisitOrder::Bfs,
+                balance: Balance::default(),
+            }),
+            hardware.clone(),
+        ),
+        (Box::new(MultilevelPartitioner::default()), hardware.clone()),
+        (
+            Box::new(StreamingPartitioner {
+                heuristic: StreamHeuristic::Ldg,
+                ..Default::default()
+            }),
+            hardware.clone(),
+        ),
+        (
+            Box::new(HierarchicalPartitioner {
+                inner: Box::new(MultilevelPartitioner::default()),
+                inter_rank_factor: 4,
+                refine_passes: 8,
+            }),
+            HardwareConfig {
+                rank_size: Some(2),
+                edge_placement: Default::default(),
+                ..hardware.clone()
+            },
+        ),
+    ];
+    #[cfg(feature = "z3")]
+    let mut partitioners = partitioners;
+    #[cfg(feature = "z3")]
+    for encoding in [super::Z3Encoding::Ite, super::Z3Encoding::OneHot] {
+        let z3 = super::Z3Partitioner {
+            encoding,
+            ..Default::default()
+        };
+        partitioners.push((Box::new(z3), hardware.clone()));
+    }
+    for (partitioner, hardware) in partitioners {
+        let assignment = partitioner.partition(&g, &hardware)?.assignment;
+        assert_eq!(
+            (assignment[0], assignment[5]),
+            (3, 3),
+            "{}",
+            partitioner.name()
+        );
+        assert_ne!(assignment[1], assignment[2], "{}", partitioner.name());
+    }
+
+    // Contradictory constraints are rejected before partitioning.
+    let error = |graph: &str| -> Result<DataMappingError> {
+        let g = analyze(graph)?;
+        let error = MultilevelPartitioner::default()
+            .partition(&g, &hardware)
+            .unwrap_err();
+        error.downcast::<DataMappingError>()
+    };
+    assert!(matches!(
+        error("@pin(dpu = 0) node Alice a0; @pin(dpu = 1) node Alice a1; @colocate(a0, a1);")?,
+        DataMappingError::ConflictingPins(..)
+    ));
+    assert!(matches!(
+        error("node Alice a0, a1, a2; @colocate(a0, a1, a2); @separate(a2, a0);")?,
+        DataMappingError::ConflictingSeparation(..)
+    ));
+    assert!(matches!(
+        error("@pin(dpu = 2) node Alice a0; @pin(dpu = 2) node Alice a1; @separate(a0, a1);")?,
+        DataMappingError::ConflictingSeparation(..)
+    ));
+    assert!(matches!(
+        error("@pin(dpu = 4) node Alice a0;")?,
+        DataMappingError::CoreOutOfRange(..)
+    ));
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use super::graph::PartitionGraph;
+use super::{Balance, DataMappingError, HardwareConfig, Partition, Partitioner};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+/// Order in which the greedy partitioner places nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VisitOrder {
+    /// Breadth-first from each walker start node, then from any node not reached yet
+    Bfs,
+    /// Highest degree first
+    Degree,
+    /// Order of declaration in the graph
+    Declaration,
+}
+
+/// Places each node on the core already holding most of its (weighted) neighbors,
+/// falling back to the first core with room. A port of `greedy_schedule` in
+/// `evaluation/main.py`, with byte capacities instead of a node count limit.
+pub struct GreedyPartitioner {
+    pub order: VisitOrder,
+    pub balance: Balance,
+}
+
+impl Partitioner for GreedyPartitioner {
+    fn name(&self) -> &'static str {
+        "greedy"
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, hardware.core_num as usize);
+        let starts: Vec<usize> = graph
+            .walker_insts
+            .iter()
+            .filter_map(|walker| {
+                graph
+                    .node_insts
+                    .iter()
+                    .position(|node| node.varname == walker.start_node.varname)
+                    .map(|node| group[node])
+            })
+            .collect();
+        let order = visit_order(&pgraph, self.order, &starts);
+        let placement = greedy_assign(&pgraph, &order, hardware, &vec![None; pgraph.node_count()])
+            .map_err(|g| {
+                let node = group.iter().position(|other| *other == g).unwrap();
+                DataMappingError::CapacityExceeded(graph.node_insts[node].varname.clone())
+            })?;
+        Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| placement[*g]).collect(),
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )
+    }
+}
+
+pub fn visit_order(graph: &PartitionGraph, order: VisitOrder, starts: &[usize]) -> Vec<usize> {
+    let n = graph.node_count();
+    match order {
+        VisitOrder::Declaration => (0..n).collect(),
+        VisitOrder::Degree => {
+            let mut nodes: Vec<usize> = (0..n).collect();
+            nodes.sort_by_key(|node| std::cmp::Reverse(graph.incident(*node).len()));
+            nodes
+        }
+        VisitOrder::Bfs => {
+            let mut visited = vec![false; n];
+            let mut result = Vec::with_capacity(n);
+            for root in starts.iter().copied().chain(0..n) {
+                if visited[root] {
+                    continue;
+                }
+                visited[root] = true;
+                let mut queue = VecDeque::from([root]);
+                while let Some(node) = queue.pop_front() {
+                    result.push(node);
+                    for &edge in graph.incident(node) {
+                        let next = graph.other_end(edge, node);
+                        if !visited[next] {
+                            visited[next] = true;
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+            result
+        }
+    }
+}
+
+/// Greedily place the nodes of `order` that `fixed` leaves open, within the capacity, the
+/// cost limit and the placement constraints of the graph. Nodes the graph pins are placed
+/// first. Returns the complete assignment, or the first node that fits on no core.
+pub fn greedy_assign(
+    graph: &PartitionGraph,
+    order: &[usize],
+    hardware: &HardwareConfig,
+    fixed: &[Option<usize>],
+) -> std::result::Result<Vec<usize>, usize> {
+    let core_num = hardware.core_num as usize;
+    let fixed: Vec<Option<usize>> = fixed
+        .iter()
+        .zip(&graph.fixed)
+        .map(|(core, pinned)| core.or(*pinned))
+        .collect();
+    let mut placement = fixed.clone();
+    let mut loads = vec![0; core_num];
+    let mut costs = vec![0; core_num];
+    for (node, core) in fixed.iter().enumerate() {
+        if let Some(core) = core {
+            loads[*core] += graph.node_sizes[node];
+            costs[*core] += graph.node_costs[node];
+        }
+    }
+    for edge in &graph.edges {
+        if let Some(core) = fixed[edge.from] {
+            loads[core] += edge.size;
+        }
+        if let Some(core) = fixed[edge.to] {
+            if fixed[edge.from] != Some(core) {
+                loads[core] += edge.size;
+            }
+        }
+    }
+    // Pinned nodes may already overflow their core.
+    let overflowing = |core: usize| {
+        loads[core] > hardware.core_size
+            || graph.cost_limit.is_some_and(|limit| costs[core] > limit)
+    };
+    if let Some(node) = (0..fixed.len()).find(|node| fixed[*node].is_some_and(overflowing)) {
+        return Err(node);
+    }
+    // Cores before `first_open` cannot take even the smallest node.
+    let smallest = graph.node_sizes.iter().copied().min().unwrap_or(0);
+    let mut first_open = 0;
+
+    for &node in order {
+        if placement[node].is_some() {
+            continue;
+        }
+        let mut affinity: HashMap<usize, i64> = HashMap::new();
+        for &edge in graph.incident(node) {
+            let other = graph.other_end(edge, node);
+            if let Some(core) = placement[other] {
+                *affinity.entry(core).or_insert(0) += graph.edges[edge].weight;
+            }
+        }
+        let mut ranked: Vec<(usize, i64)> = affinity.into_iter().collect();
+        ranked.sort_by_key(|(core, weight)| (std::cmp::Reverse(*weight), *core));
+        let attracting = ranked.iter().filter(|(_, w)| *w > 0).map(|(c, _)| *c);
+        let repelling = ranked.iter().filter(|(_, w)| *w < 0).map(|(c, _)| *c);
+        let neutral =
+            (first_open..core_num).filter(|c| !ranked.iter().any(|(r, w)| r == c && *w != 0));
+
+        let fits = |core: &usize| {
+            loads[*core] + graph.load_on(node, *core, &placement) <= hardware.core_size
+                && graph.cost_fits(node, costs[*core])
+                && graph.allowed(node, *core, |other| placement[other])
+        };
+        let core = attracting
+            .chain(neutral)
+            .chain(repelling)
+            .find(fits)
+            .ok_or(node)?;
+        loads[core] += graph.load_on(node, core, &placement);
+        costs[core] += graph.node_costs[node];
+        placement[node] = Some(core);
+        while first_open < core_num && loads[first_open] + smallest > hardware.core_size {
+            first_open += 1;
+        }
+    }
+    Ok(placement
+        .into_iter()
+        .map(|core| core.unwrap_or(0))
+        .collect())
+}
+
+#[test]
+fn test_greedy_chain() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 12,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 16,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    for order in [VisitOrder::Bfs, VisitOrder::Declaration] {
+        let partition = GreedyPartitioner {
+            order,
+            balance: Balance::default(),
+        }
+        .partition(&g, &hardware)?;
+        assert_eq!(partition.stats.cut_weight, 2);
+        assert_eq!(partition.core_sizes(), vec![4, 4, 4]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_greedy_zero_weight_edge() -> Result<()> {
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // A core the node has no net affinity with is as good as an untouched one.
+    let source = "
+        node Alice { n1: int8; };
+        edge Alice Alice Link {};
+        graph {
+            node Alice a0, a1;
+            edge Link a0 a1 0;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let hardware = HardwareConfig {
+        core_size: 100,
+        core_num: 1,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = GreedyPartitioner {
+        order: VisitOrder::Bfs,
+        balance: Balance::default(),
+    }
+    .partition(&g, &hardware)?;
+    assert_eq!(partition.assignment, vec![0, 0]);
+    Ok(())
+}
+
+#[test]
+fn test_greedy_respects_capacity() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Rmat,
+        nodes: 500,
+        edges: 3000,
+        max_weight: 10,
+        seed: 1,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 40,
+        core_num: 16,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    for order in [VisitOrder::Bfs, VisitOrder::Degree, VisitOrder::Declaration] {
+        let partition = GreedyPartitioner {
+            order,
+            balance: Balance::default(),
+        }
+        .partition(&g, &hardware)?;
+        let loads = pgraph.core_loads(&partition.assignment, 16);
+        assert!(loads.iter().all(|load| *load <= hardware.core_size));
+        assert_eq!(
+            partition.stats.cut_weight,
+            pgraph.cut_weight(&partition.assignment)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_greedy_reports_overflow() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Star,
+        nodes: 10,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 8,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partitioner = GreedyPartitioner {
+        order: VisitOrder::Declaration,
+        balance: Balance::default(),
+    };
+    assert!(partitioner.partition(&g, &hardware).is_err());
+    Ok(())
+}
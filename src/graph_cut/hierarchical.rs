@@ -0,0 +1,293 @@
+use super::graph::PartitionGraph;
+use super::refine::rank_refine;
+use super::{DataMappingError, EdgePlacement, HardwareConfig, Partition, Partitioner};
+use crate::sem_type::{PlacementConstraints, SemanticEdgeInst, SemanticGraph};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Partitions in two levels when the hardware has ranks: first across ranks, each as large
+/// as its DPUs together, then across the DPUs of every rank. The result is refined on a
+/// cut where an edge between ranks weighs `inter_rank_factor` times one within a rank.
+/// Without a rank size, the graph goes to `inner` unchanged.
+pub struct HierarchicalPartitioner {
+    pub inner: Box<dyn Partitioner>,
+    pub inter_rank_factor: i64,
+    pub refine_passes: usize,
+}
+
+impl HierarchicalPartitioner {
+    /// The nodes on `rank`, with their edges and the constraints among them, pins counting
+    /// DPUs from the first of the rank. An edge leaving the rank turns into a loop on its
+    /// local endpoint when `placement` stores it there, which charges its bytes to that
+    /// DPU without being cut.
+    fn rank_graph(
+        graph: &SemanticGraph,
+        ranks: &[usize],
+        rank: usize,
+        rank_size: usize,
+        placement: EdgePlacement,
+        index: &HashMap<&str, usize>,
+    ) -> (Vec<usize>, SemanticGraph) {
+        let nodes: Vec<usize> = (0..ranks.len()).filter(|i| ranks[*i] == rank).collect();
+        let on_rank = |name: &String| ranks[index[name.as_str()]] == rank;
+        let edge_insts = graph
+            .edge_insts
+            .iter()
+            .filter_map(|edge| {
+                match (
+                    on_rank(&edge.from_var.varname),
+                    on_rank(&edge.to_var.varname),
+                ) {
+                    (true, true) => Some(edge.clone()),
+                    (false, false) => None,
+                    (from, to) => {
+                        let stored = match placement {
+                            EdgePlacement::Source => from,
+                            EdgePlacement::Destination => to,
+                            EdgePlacement::Both => true,
+                        };
+                        if !stored {
+                            return None;
+                        }
+                        let owner = if from { &edge.from_var } else { &edge.to_var };
+                        Some(Rc::new(SemanticEdgeInst {
+                            edge_type: edge.edge_type.clone(),
+                            from_var: owner.clone(),
+                            to_var: owner.clone(),
+                            weight: 0,
+                        }))
+                    }
+                }
+            })
+            .collect();
+        let constraints = &graph.constraints;
+        let pairs_on_rank = |pairs: &[(String, String)]| {
+            pairs
+                .iter()
+                .filter(|(a, b)| on_rank(a) && on_rank(b))
+                .cloned()
+                .collect()
+        };
+        let sub = SemanticGraph {
+            node_insts: nodes.iter().map(|i| graph.node_insts[*i].clone()).collect(),
+            edge_insts,
+            walker_insts: graph
+                .walker_insts
+                .iter()
+                .filter(|walker| on_rank(&walker.start_node.varname))
+                .cloned()
+                .collect(),
+            constraints: PlacementConstraints {
+                pins: constraints
+                    .pins
+                    .iter()
+                    .filter(|(name, _)| on_rank(name))
+                    .map(|(name, dpu)| (name.clone(), dpu % rank_size))
+                    .collect(),
+                colocate: pairs_on_rank(&constraints.colocate),
+                separate: pairs_on_rank(&constraints.separate),
+            },
+        };
+        (nodes, sub)
+    }
+}
+
+impl Partitioner for HierarchicalPartitioner {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        hardware.check()?;
+        let rank_size = match hardware.rank_size {
+            Some(size) if size < hardware.core_num => size,
+            _ => return self.inner.partition(graph, hardware),
+        };
+        if hardware.core_num % rank_size != 0 {
+            return Err(DataMappingError::PartialRank(hardware.core_num, rank_size).into());
+        }
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        for edge in &graph.edge_insts {
+            for name in [&edge.from_var.varname, &edge.to_var.varname] {
+                if !index.contains_key(name.as_str()) {
+                    return Err(DataMappingError::NodeNotFound(name.clone()).into());
+                }
+            }
+        }
+
+        // Check the constraints against the DPUs before they are restated per rank.
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        let ranked = SemanticGraph {
+            constraints: PlacementConstraints {
+                pins: graph
+                    .constraints
+                    .pins
+                    .iter()
+                    .map(|(name, dpu)| (name.clone(), dpu / rank_size as usize))
+                    .collect(),
+                colocate: graph.constraints.colocate.clone(),
+                separate: vec![],
+            },
+            ..graph.clone()
+        };
+        let ranks = self.inner.partition(
+            &ranked,
+            &HardwareConfig {
+                core_size: hardware.core_size * rank_size,
+                core_num: hardware.core_num / rank_size,
+                rank_size: None,
+                edge_placement: hardware.edge_placement,
+            },
+        )?;
+        let dpus = HardwareConfig {
+            core_size: hardware.core_size,
+            core_num: rank_size,
+            rank_size: None,
+            edge_placement: hardware.edge_placement,
+        };
+        let mut assignment = vec![0; graph.node_insts.len()];
+        let mut status = ranks.stats.status;
+        for rank in 0..ranks.core_num {
+            let (nodes, sub) = Self::rank_graph(
+                graph,
+                &ranks.assignment,
+                rank,
+                rank_size as usize,
+                hardware.edge_placement,
+                &index,
+            );
+            if nodes.is_empty() {
+                continue;
+            }
+            let local = self.inner.partition(&sub, &dpus)?;
+            if local.stats.status != status {
+                status = super::SolveStatus::Unknown;
+            }
+            for (node, core) in nodes.into_iter().zip(local.assignment) {
+                assignment[node] = rank * rank_size as usize + core;
+            }
+        }
+
+        // Moves may not raise the cost of the most loaded DPU, so balance is kept. Colocated
+        // nodes share a DPU, so they move together as one node of the constrained graph.
+        let mut merged = vec![0; pgraph.node_count()];
+        for (node, g) in group.iter().enumerate() {
+            merged[*g] = assignment[node];
+        }
+        pgraph.cost_limit = pgraph
+            .core_costs(&merged, hardware.core_num as usize)
+            .into_iter()
+            .max();
+        rank_refine(
+            &pgraph,
+            &mut merged,
+            hardware,
+            self.inter_rank_factor,
+            self.refine_passes,
+        );
+        let mut partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| merged[*g]).collect(),
+            hardware.core_num as usize,
+            ranks.stats.partitioner,
+            start.elapsed(),
+        )?;
+        partition.stats.status = status;
+        Ok(partition)
+    }
+}
+
+#[test]
+fn test_hierarchical_keeps_clusters_in_ranks() -> Result<()> {
+    use super::MultilevelPartitioner;
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // A 4x4 grid of 4-byte nodes on 2 ranks of 2 DPUs, each holding 4 nodes.
+    let config = GeneratorConfig {
+        kind: GraphKind::Grid,
+        nodes: 16,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 5,
+        core_num: 4,
+        rank_size: Some(2),
+        edge_placement: Default::default(),
+    };
+    let partitioner = HierarchicalPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        inter_rank_factor: 4,
+        refine_passes: 8,
+    };
+    let partition = partitioner.partition(&g, &hardware)?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    assert!(pgraph
+        .core_loads(&partition.assignment, 4)
+        .iter()
+        .all(|load| *load <= hardware.core_size));
+    // Halving the grid cuts 4 edges between the ranks.
+    assert_eq!(pgraph.rank_cut_weight(&partition.assignment, &hardware), 4);
+
+    let odd = HardwareConfig {
+        core_num: 5,
+        ..hardware.clone()
+    };
+    assert!(partitioner.partition(&g, &odd).is_err());
+    for size in [0, -2] {
+        let empty = HardwareConfig {
+            rank_size: Some(size),
+            ..hardware.clone()
+        };
+        let error = partitioner.partition(&g, &empty).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(DataMappingError::RankSize(s)) if *s == size
+        ));
+        assert_eq!(empty.rank_of(3), 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hierarchical_charges_cross_rank_edges_once() -> Result<()> {
+    use super::MultilevelPartitioner;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // A 4-byte and an 8-byte node joined by a 4-byte edge, on 2 ranks of one 8-byte DPU:
+    // stored with its source, the edge fits next to `a` but not next to `b`.
+    let source = "
+        node Small { n1: int32; };
+        node Large { n1: int64; };
+        edge Small Large Link { w: int32; };
+        graph {
+            node Small a;
+            node Large b;
+            edge Link a b 1;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let hardware = HardwareConfig {
+        core_size: 8,
+        core_num: 2,
+        rank_size: Some(1),
+        edge_placement: EdgePlacement::Source,
+    };
+    let partition = HierarchicalPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        inter_rank_factor: 4,
+        refine_passes: 8,
+    }
+    .partition(&g, &hardware)?;
+    assert_ne!(partition.assignment[0], partition.assignment[1]);
+    Ok(())
+}
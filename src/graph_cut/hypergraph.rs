@@ -0,0 +1,234 @@
+use super::graph::PartitionGraph;
+use super::refine::load_of;
+use super::{Balance, HardwareConfig, Partition, Partitioner};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// A net of the walker-step hypergraph: a node and the targets of its outgoing edges, which
+/// one step of a walker on the node reads all at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Net {
+    /// The node the walker is on.
+    pub source: usize,
+    /// The source and the targets of its outgoing edges, without duplicates.
+    pub pins: Vec<usize>,
+    /// The largest weight of the outgoing edges, as a step is as frequent as the most
+    /// frequent edge it reads.
+    pub weight: i64,
+}
+
+/// The graph seen as a hypergraph, with one net per node with outgoing edges.
+#[derive(Debug, Clone)]
+pub struct Hypergraph {
+    pub nets: Vec<Net>,
+    /// Indices into `nets` of the nets each node is a pin of.
+    incident: Vec<Vec<usize>>,
+}
+
+impl Hypergraph {
+    pub fn from_graph(graph: &PartitionGraph) -> Hypergraph {
+        let n = graph.node_count();
+        let mut outgoing: Vec<Option<Net>> = vec![None; n];
+        for edge in &graph.edges {
+            let net = outgoing[edge.from].get_or_insert_with(|| Net {
+                source: edge.from,
+                pins: vec![edge.from],
+                weight: edge.weight,
+            });
+            if !net.pins.contains(&edge.to) {
+                net.pins.push(edge.to);
+            }
+            net.weight = net.weight.max(edge.weight);
+        }
+        let nets: Vec<Net> = outgoing.into_iter().flatten().collect();
+        let mut incident = vec![vec![]; n];
+        for (i, net) in nets.iter().enumerate() {
+            for pin in &net.pins {
+                incident[*pin].push(i);
+            }
+        }
+        Hypergraph { nets, incident }
+    }
+
+    pub fn from_semantic(graph: &SemanticGraph) -> Result<Hypergraph> {
+        Ok(Hypergraph::from_graph(&PartitionGraph::from_semantic(
+            graph,
+        )?))
+    }
+
+    /// The connectivity-minus-one of `assignment`: the weight of every net times the number
+    /// of cores its pins span, less one.
+    pub fn connectivity_cost(&self, assignment: &[usize]) -> i64 {
+        self.nets
+            .iter()
+            .map(|net| {
+                let mut cores: Vec<usize> = net.pins.iter().map(|pin| assignment[*pin]).collect();
+                cores.sort_unstable();
+                cores.dedup();
+                net.weight * (cores.len() as i64 - 1)
+            })
+            .sum()
+    }
+}
+
+/// Move nodes to the core that lowers the connectivity-minus-one of `hypergraph` most,
+/// among the cores its nets span, as long as the core has room, stays within the cost
+/// limit and is allowed for the node. Stops when a sweep over the nodes moves nothing or
+/// after `max_passes` sweeps, and returns the reduction.
+pub fn connectivity_refine(
+    hypergraph: &Hypergraph,
+    graph: &PartitionGraph,
+    assignment: &mut [usize],
+    hardware: &HardwareConfig,
+    max_passes: usize,
+) -> i64 {
+    let core_num = hardware.core_num as usize;
+    let mut loads = graph.core_loads(assignment, core_num);
+    let mut costs = graph.core_costs(assignment, core_num);
+    // Pins of every net on each core it spans.
+    let mut counts: Vec<HashMap<usize, usize>> = hypergraph
+        .nets
+        .iter()
+        .map(|net| {
+            let mut count = HashMap::new();
+            for pin in &net.pins {
+                *count.entry(assignment[*pin]).or_insert(0) += 1;
+            }
+            count
+        })
+        .collect();
+    let mut improvement = 0;
+    for _ in 0..max_passes {
+        let mut moved = false;
+        for node in 0..graph.node_count() {
+            let from = assignment[node];
+            let nets = &hypergraph.incident[node];
+            // Moving saves the nets the node alone keeps on `from`, and costs the nets not
+            // on the target core yet.
+            let mut leave = 0;
+            let mut joined: HashMap<usize, i64> = HashMap::new();
+            for &net in nets {
+                let weight = hypergraph.nets[net].weight;
+                if counts[net][&from] == 1 {
+                    leave += weight;
+                }
+                for core in counts[net].keys().filter(|core| **core != from) {
+                    *joined.entry(*core).or_insert(0) += weight;
+                }
+            }
+            let total: i64 = nets.iter().map(|net| hypergraph.nets[*net].weight).sum();
+            let mut candidates: Vec<(i64, usize)> = joined
+                .into_iter()
+                .map(|(core, on_core)| (leave - (total - on_core), core))
+                .filter(|(gain, _)| *gain > 0)
+                .collect();
+            candidates.sort_by_key(|(gain, core)| (std::cmp::Reverse(*gain), *core));
+            let best = candidates.into_iter().find(|(_, core)| {
+                loads[*core] + load_of(graph, node, *core, assignment) <= hardware.core_size
+                    && graph.cost_fits(node, costs[*core])
+                    && graph.allowed(node, *core, |other| Some(assignment[other]))
+            });
+            if let Some((gain, core)) = best {
+                loads[from] -= load_of(graph, node, from, assignment);
+                loads[core] += load_of(graph, node, core, assignment);
+                costs[from] -= graph.node_costs[node];
+                costs[core] += graph.node_costs[node];
+                for &net in nets {
+                    let count = counts[net].get_mut(&from).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        counts[net].remove(&from);
+                    }
+                    *counts[net].entry(core).or_insert(0) += 1;
+                }
+                assignment[node] = core;
+                improvement += gain;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    improvement
+}
+
+/// Partitions with `inner`, then refines for the connectivity-minus-one of the walker-step
+/// hypergraph instead of the pairwise cut: a walker step on a node reads all its outgoing
+/// edges, so the cost of a step is the number of other DPUs they lead to, however many
+/// edges lead there.
+pub struct HypergraphPartitioner {
+    pub inner: Box<dyn Partitioner>,
+    pub balance: Balance,
+    /// Sweeps over the nodes of the refinement.
+    pub refine_passes: usize,
+}
+
+impl Partitioner for HypergraphPartitioner {
+    fn name(&self) -> &'static str {
+        "hypergraph"
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let inner = self.inner.partition(graph, hardware)?;
+        if !inner.replicas.is_empty() {
+            return Ok(inner);
+        }
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, hardware.core_num as usize);
+        let mut assignment = vec![0; pgraph.node_count()];
+        for (node, core) in inner.assignment.iter().enumerate() {
+            assignment[group[node]] = *core;
+        }
+        let hypergraph = Hypergraph::from_graph(&pgraph);
+        connectivity_refine(
+            &hypergraph,
+            &pgraph,
+            &mut assignment,
+            hardware,
+            self.refine_passes,
+        );
+        Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| assignment[*g]).collect(),
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )
+    }
+}
+
+#[test]
+fn test_connectivity_refine() {
+    use super::graph::GraphEdge;
+    use super::refine::fm_refine;
+
+    // A hub reading three neighbors, its own core full: moving a neighbor next to another
+    // leaves the pairwise cut as is, but spans one core less.
+    let edge = |to| GraphEdge {
+        from: 0,
+        to,
+        weight: 1,
+        size: 0,
+    };
+    let graph = PartitionGraph::new(vec![1; 4], vec![edge(1), edge(2), edge(3)]);
+    let hypergraph = Hypergraph::from_graph(&graph);
+    assert_eq!(hypergraph.nets.len(), 1);
+    let hardware = HardwareConfig {
+        core_size: 2,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let mut assignment = vec![0, 0, 1, 2];
+    assert_eq!(hypergraph.connectivity_cost(&assignment), 2);
+    assert_eq!(fm_refine(&graph, &mut assignment, &hardware, 10), 0);
+    let gain = connectivity_refine(&hypergraph, &graph, &mut assignment, &hardware, 10);
+    assert_eq!(gain, 1);
+    assert_eq!(hypergraph.connectivity_cost(&assignment), 1);
+    assert_eq!(graph.cut_weight(&assignment), 2);
+}
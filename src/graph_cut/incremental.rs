@@ -0,0 +1,370 @@
+use super::graph::PartitionGraph;
+use super::refine::load_of;
+use super::{Balance, DataMappingError, HardwareConfig, Partition, PartitionFile};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// Most sweeps over the nodes looking for moves that reduce the cut.
+const MAX_PASSES: usize = 8;
+
+/// A node that changes DPU.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Migration {
+    pub node: String,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// A node new to the graph and the DPU it is placed on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Placement {
+    pub node: String,
+    pub dpu: usize,
+}
+
+/// How a deployed partition turns into its update.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MigrationPlan {
+    pub placed: Vec<Placement>,
+    pub moved: Vec<Migration>,
+    /// Nodes of the previous partition that are no longer in the graph.
+    pub removed: Vec<String>,
+    /// Moved nodes beyond `max_migrations`, all of them evicted from a DPU that ran out
+    /// of room.
+    pub over_budget: usize,
+}
+
+/// Updates a previous partition for an edited graph. Nodes keep their DPU unless it no
+/// longer has room for them; new and evicted nodes go to the DPU they are most connected
+/// to. Nodes are then moved where that lowers the cut, as long as at most `max_migrations`
+/// previously placed nodes, evicted ones included, end up on another DPU. Evictions are
+/// never refused: those beyond the budget are reported in `MigrationPlan::over_budget`.
+/// Copies of replicated nodes are not carried over.
+pub struct IncrementalPartitioner {
+    pub max_migrations: usize,
+    pub balance: Balance,
+}
+
+impl IncrementalPartitioner {
+    pub fn repartition(
+        &self,
+        graph: &SemanticGraph,
+        previous: &PartitionFile,
+        hardware: &HardwareConfig,
+    ) -> Result<(Partition, MigrationPlan)> {
+        let start = Instant::now();
+        let core_num = hardware.core_num as usize;
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, core_num);
+        let previous_node: Vec<Option<usize>> = graph
+            .node_insts
+            .iter()
+            .map(|node| previous.assignment.get(&node.varname).copied())
+            .collect();
+        // Colocated nodes move as one, from the DPU of the first of them previously placed.
+        let mut previous_core: Vec<Option<usize>> = vec![None; pgraph.node_count()];
+        for (node, g) in group.iter().enumerate() {
+            previous_core[*g] = previous_core[*g].or(previous_node[node]);
+        }
+
+        // Keep the previous placements that still fit, in graph order.
+        let mut placed: Vec<Option<usize>> = vec![None; pgraph.node_count()];
+        let mut loads = vec![0; core_num];
+        let mut costs = vec![0; core_num];
+        let mut pending = vec![];
+        for (node, core) in previous_core.iter().enumerate() {
+            match core {
+                Some(core)
+                    if *core < core_num
+                        && pgraph.allowed(node, *core, |other| placed[other])
+                        && loads[*core] + pgraph.load_on(node, *core, &placed)
+                            <= hardware.core_size
+                        && pgraph.cost_fits(node, costs[*core]) =>
+                {
+                    loads[*core] += pgraph.load_on(node, *core, &placed);
+                    costs[*core] += pgraph.node_costs[node];
+                    placed[node] = Some(*core);
+                }
+                _ => pending.push(node),
+            }
+        }
+
+        // Place the others next to their heaviest placed neighbors, most connected first.
+        while !pending.is_empty() {
+            let (i, _) = pending
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, node)| {
+                    let weight: i64 = affinity(&pgraph, **node, |other| placed[other])
+                        .values()
+                        .sum();
+                    (weight, usize::MAX - **node)
+                })
+                .unwrap();
+            let node = pending.swap_remove(i);
+            let weights = affinity(&pgraph, node, |other| placed[other]);
+            let core = (0..core_num)
+                .filter(|core| {
+                    loads[*core] + pgraph.load_on(node, *core, &placed) <= hardware.core_size
+                        && pgraph.cost_fits(node, costs[*core])
+                        && pgraph.allowed(node, *core, |other| placed[other])
+                })
+                .max_by_key(|core| {
+                    let weight = weights.get(core).copied().unwrap_or(0);
+                    (weight, -loads[*core], usize::MAX - core)
+                })
+                .ok_or_else(|| {
+                    let first = group.iter().position(|g| *g == node).unwrap();
+                    DataMappingError::CapacityExceeded(graph.node_insts[first].varname.clone())
+                })?;
+            loads[core] += pgraph.load_on(node, core, &placed);
+            costs[core] += pgraph.node_costs[node];
+            placed[node] = Some(core);
+        }
+        let mut assignment: Vec<usize> = placed.into_iter().map(Option::unwrap).collect();
+
+        // Spend the migration budget on the moves that reduce the cut the most. Moving a
+        // group of colocated nodes migrates each of them that was placed before.
+        let mut members = vec![0; pgraph.node_count()];
+        for (node, g) in group.iter().enumerate() {
+            members[*g] += previous_node[node].is_some() as usize;
+        }
+        let moved = |node: usize, core: usize| {
+            if previous_core[node].is_some_and(|previous| previous != core) {
+                members[node]
+            } else {
+                0
+            }
+        };
+        let mut migrations: usize = (0..assignment.len())
+            .map(|node| moved(node, assignment[node]))
+            .sum();
+        for _ in 0..MAX_PASSES {
+            let mut candidates = vec![];
+            for node in 0..assignment.len() {
+                let own = assignment[node];
+                let allowed =
+                    |core| migrations + moved(node, core) - moved(node, own) <= self.max_migrations;
+                if let Some((gain, _)) = best_move(
+                    &pgraph,
+                    node,
+                    &assignment,
+                    &loads,
+                    &costs,
+                    hardware,
+                    allowed,
+                ) {
+                    candidates.push((gain, node));
+                }
+            }
+            candidates.sort_by_key(|(gain, node)| (-gain, *node));
+            let mut improved = false;
+            for (_, node) in candidates {
+                // Earlier moves may have changed the gain, the room, or the budget.
+                let own = assignment[node];
+                let allowed =
+                    |core| migrations + moved(node, core) - moved(node, own) <= self.max_migrations;
+                let Some((_, core)) = best_move(
+                    &pgraph,
+                    node,
+                    &assignment,
+                    &loads,
+                    &costs,
+                    hardware,
+                    allowed,
+                ) else {
+                    continue;
+                };
+                migrations = migrations + moved(node, core) - moved(node, own);
+                loads[own] -= load_of(&pgraph, node, own, &assignment);
+                loads[core] += load_of(&pgraph, node, core, &assignment);
+                costs[own] -= pgraph.node_costs[node];
+                costs[core] += pgraph.node_costs[node];
+                assignment[node] = core;
+                improved = true;
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        let assignment: Vec<usize> = group.iter().map(|g| assignment[*g]).collect();
+        let mut plan = MigrationPlan {
+            over_budget: migrations.saturating_sub(self.max_migrations),
+            ..Default::default()
+        };
+        for (node, inst) in graph.node_insts.iter().enumerate() {
+            match previous_node[node] {
+                None => plan.placed.push(Placement {
+                    node: inst.varname.clone(),
+                    dpu: assignment[node],
+                }),
+                Some(from) if from != assignment[node] => plan.moved.push(Migration {
+                    node: inst.varname.clone(),
+                    from,
+                    to: assignment[node],
+                }),
+                Some(_) => {}
+            }
+        }
+        let known: HashSet<&str> = graph
+            .node_insts
+            .iter()
+            .map(|node| node.varname.as_str())
+            .collect();
+        plan.removed = previous
+            .assignment
+            .keys()
+            .filter(|name| !known.contains(name.as_str()))
+            .cloned()
+            .collect();
+        let partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            assignment,
+            core_num,
+            "incremental",
+            start.elapsed(),
+        )?;
+        Ok((partition, plan))
+    }
+}
+
+/// Weight of the edges from `node` to the nodes on each core.
+fn affinity(
+    graph: &PartitionGraph,
+    node: usize,
+    core_of: impl Fn(usize) -> Option<usize>,
+) -> HashMap<usize, i64> {
+    let mut weights = HashMap::new();
+    for &edge in graph.incident(node) {
+        let other = graph.other_end(edge, node);
+        if other != node {
+            if let Some(core) = core_of(other) {
+                *weights.entry(core).or_insert(0) += graph.edges[edge].weight;
+            }
+        }
+    }
+    weights
+}
+
+/// The cut-reducing move of `node` with the highest gain, as (gain, core), among the
+/// allowed cores of its neighbors with room for it that its placement constraints permit.
+fn best_move(
+    graph: &PartitionGraph,
+    node: usize,
+    assignment: &[usize],
+    loads: &[i64],
+    costs: &[i64],
+    hardware: &HardwareConfig,
+    allowed: impl Fn(usize) -> bool,
+) -> Option<(i64, usize)> {
+    let own = assignment[node];
+    let weights = affinity(graph, node, |other| Some(assignment[other]));
+    let internal = weights.get(&own).copied().unwrap_or(0);
+    weights
+        .into_iter()
+        .filter(|(core, weight)| {
+            *core != own
+                && *weight > internal
+                && allowed(*core)
+                && graph.allowed(node, *core, |other| Some(assignment[other]))
+                && loads[*core] + load_of(graph, node, *core, assignment) <= hardware.core_size
+                && graph.cost_fits(node, costs[*core])
+        })
+        .map(|(core, weight)| (weight - internal, core))
+        .max_by_key(|(gain, core)| (*gain, usize::MAX - core))
+}
+
+#[test]
+fn test_incremental_keeps_placements() -> Result<()> {
+    use super::{MultilevelPartitioner, Partitioner};
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // A chain of 8 nodes on 2 DPUs with room for 5 nodes each, grown to 10 nodes.
+    let chain = |nodes| {
+        let config = GeneratorConfig {
+            kind: GraphKind::Chain,
+            nodes,
+            ..Default::default()
+        };
+        generate_graph(&config, &default_types())
+    };
+    let (g, grown) = (chain(8)?, chain(10)?);
+    let hardware = HardwareConfig {
+        core_size: 4 * 5,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = MultilevelPartitioner::default().partition(&g, &hardware)?;
+    let previous = PartitionFile::new(&g, &partition, &hardware);
+    let incremental = IncrementalPartitioner {
+        max_migrations: 0,
+        balance: Balance::default(),
+    };
+    let (updated, plan) = incremental.repartition(&grown, &previous, &hardware)?;
+    assert_eq!(updated.assignment[..8], partition.assignment[..]);
+    assert!(plan.moved.is_empty() && plan.removed.is_empty());
+    assert_eq!(
+        plan.placed
+            .iter()
+            .map(|p| p.node.as_str())
+            .collect::<Vec<_>>(),
+        ["n8", "n9"]
+    );
+
+    // Alternating DPUs cut every edge; two migrations are enough to reduce the cut.
+    let mut scattered = previous.clone();
+    for (i, core) in scattered.assignment.values_mut().enumerate() {
+        *core = i % 2;
+    }
+    let budget = IncrementalPartitioner {
+        max_migrations: 2,
+        balance: Balance::default(),
+    };
+    let (updated, plan) = budget.repartition(&g, &scattered, &hardware)?;
+    assert!(plan.moved.len() <= 2);
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    let before = pgraph.cut_weight(&scattered.to_partition(&g, &hardware)?.assignment);
+    assert!(updated.stats.cut_weight < before);
+    Ok(())
+}
+
+#[test]
+fn test_incremental_reports_forced_evictions() -> Result<()> {
+    use super::{MultilevelPartitioner, Partitioner};
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // All 8 nodes of a chain saved on DPU 0, which only has room for 5 of them.
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 8,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 5,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = MultilevelPartitioner::default().partition(&g, &hardware)?;
+    let mut crowded = PartitionFile::new(&g, &partition, &hardware);
+    crowded.assignment.values_mut().for_each(|core| *core = 0);
+    let incremental = IncrementalPartitioner {
+        max_migrations: 0,
+        balance: Balance::default(),
+    };
+    let (updated, plan) = incremental.repartition(&g, &crowded, &hardware)?;
+    assert_eq!(plan.moved.len(), 3);
+    assert_eq!(plan.over_budget, 3);
+    assert_eq!(
+        updated.assignment.iter().filter(|core| **core == 0).count(),
+        5
+    );
+    Ok(())
+}
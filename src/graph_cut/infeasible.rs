@@ -0,0 +1,261 @@
+use super::graph::PartitionGraph;
+use super::greedy::{greedy_assign, visit_order, VisitOrder};
+use super::{DataMappingError, HardwareConfig};
+use std::fmt;
+
+/// A constraint of the Z3 model that can take part in an unsat core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requirement {
+    /// The byte capacity of a core.
+    Capacity(usize),
+    /// The cost limit shared by all cores.
+    CostLimit,
+    /// A node pinned to its core.
+    Pin(usize),
+    /// Two nodes kept on different cores.
+    Separate(usize, usize),
+}
+
+/// Why a graph has no partition, and how many DPUs of the same capacity it would take.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Infeasibility {
+    /// The constraints that conflict, in words.
+    pub reasons: Vec<String>,
+    /// No fewer DPUs can hold the graph, or `None` when no number of DPUs can.
+    pub min_dpus: Option<usize>,
+    /// A partition onto this many DPUs was found, when more than the DPUs given.
+    pub enough_dpus: Option<usize>,
+}
+
+/// `bytes` in the largest binary unit that keeps it at least 1.
+pub fn human_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes.abs() < 1024 {
+        return format!("{} byte{}", bytes, if bytes == 1 { "" } else { "s" });
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value.fract().abs() < 0.05 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl Infeasibility {
+    /// Explain the unsat `core` of the model of `graph` on `hardware`, where the node named
+    /// `names[i]` is part of node `group[i]` of `graph`.
+    ///
+    /// A node too large for a DPU on its own and a graph larger than all DPUs together are
+    /// reported as such, whatever the core. Otherwise the core is listed.
+    pub fn explain(
+        graph: &PartitionGraph,
+        names: &[&str],
+        group: &[usize],
+        hardware: &HardwareConfig,
+        core: &[Requirement],
+    ) -> Infeasibility {
+        let n = graph.node_count();
+        let capacity = hardware.core_size;
+        let core_num = hardware.core_num.max(0) as usize;
+        let node_name = |node: usize| {
+            let members: Vec<String> = (0..group.len())
+                .filter(|i| group[*i] == node)
+                .map(|i| format!("`{}`", names[i]))
+                .collect();
+            match members.len() {
+                1 => format!("node {}", members[0]),
+                _ => format!("colocated nodes {}", members.join(", ")),
+            }
+        };
+        let open = vec![None; n];
+        // Wherever a node goes, its edges are stored next to it.
+        let alone: Vec<i64> = (0..n).map(|node| graph.load_on(node, 0, &open)).collect();
+
+        let mut reasons: Vec<String> = (0..n)
+            .filter(|node| alone[*node] > capacity)
+            .map(|node| {
+                format!(
+                    "{} alone ({}) exceeds DPU capacity {}",
+                    node_name(node),
+                    human_bytes(alone[node]),
+                    human_bytes(capacity)
+                )
+            })
+            .collect();
+        let total = graph.total_load();
+        let total_capacity = capacity * core_num as i64;
+        if reasons.is_empty() && total > total_capacity {
+            reasons.push(format!(
+                "total graph size ({}) exceeds total capacity ({}) by {}",
+                human_bytes(total),
+                human_bytes(total_capacity),
+                human_bytes(total - total_capacity)
+            ));
+        }
+        if reasons.is_empty() {
+            let mut full: Vec<usize> = vec![];
+            for requirement in core {
+                match requirement {
+                    Requirement::Capacity(dpu) => full.push(*dpu),
+                    Requirement::CostLimit => reasons.push(format!(
+                        "the balance limit of {} cost per DPU",
+                        graph.cost_limit.unwrap_or(0)
+                    )),
+                    Requirement::Pin(node) => reasons.push(format!(
+                        "{} pinned to DPU {}",
+                        node_name(*node),
+                        graph.fixed[*node].unwrap_or(0)
+                    )),
+                    Requirement::Separate(a, b) => reasons.push(format!(
+                        "{} kept apart from {}",
+                        node_name(*a),
+                        node_name(*b)
+                    )),
+                }
+            }
+            if !full.is_empty() {
+                full.sort_unstable();
+                let dpus: Vec<String> = full.iter().map(ToString::to_string).collect();
+                reasons.push(format!(
+                    "the capacity of DPU{} {} ({} each) for a graph of {}",
+                    if full.len() > 1 { "s" } else { "" },
+                    dpus.join(", "),
+                    human_bytes(capacity),
+                    human_bytes(total)
+                ));
+            }
+        }
+
+        // Enough DPUs for the bytes, and for every pinned DPU to exist.
+        let min_dpus = (alone.iter().all(|load| *load <= capacity)).then(|| {
+            let pinned = graph.fixed.iter().flatten().map(|dpu| dpu + 1).max();
+            let by_size = (total + capacity - 1) / capacity.max(1);
+            (by_size.max(1) as usize).max(pinned.unwrap_or(0))
+        });
+        // More DPUs are sure to help only when capacity is part of the conflict.
+        let enough_dpus = min_dpus
+            .filter(|_| core.iter().any(|r| matches!(r, Requirement::Capacity(_))))
+            .and_then(|min| {
+                let mut uncapped = graph.clone();
+                uncapped.cost_limit = None;
+                let order = visit_order(&uncapped, VisitOrder::Degree, &[]);
+                (min.max(core_num + 1)..=min.max(core_num + 1) + n).find(|dpus| {
+                    let hardware = HardwareConfig {
+                        core_num: *dpus as i64,
+                        ..hardware.clone()
+                    };
+                    greedy_assign(&uncapped, &order, &hardware, &open).is_ok()
+                })
+            });
+        Infeasibility {
+            reasons,
+            min_dpus,
+            enough_dpus,
+        }
+    }
+}
+
+/// Replace the conflicting requirements of an `Unsatisfiable` error for `graph`, such as a
+/// Z3 unsat core, by an explanation naming the nodes, where the node named `names[i]` is
+/// part of node `group[i]` of `graph`.
+pub(super) fn explain_unsat(
+    error: anyhow::Error,
+    graph: &PartitionGraph,
+    names: &[&str],
+    group: &[usize],
+    hardware: &HardwareConfig,
+) -> anyhow::Error {
+    match error.downcast_ref() {
+        Some(DataMappingError::Unsatisfiable(core)) => DataMappingError::Infeasible(
+            Infeasibility::explain(graph, names, group, hardware, core),
+        )
+        .into(),
+        _ => error,
+    }
+}
+
+impl fmt::Display for Infeasibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reasons.join("; "))?;
+        match (self.min_dpus, self.enough_dpus) {
+            (None, _) => write!(f, ". No number of DPUs of this capacity is enough"),
+            (Some(min), Some(enough)) if min >= enough => {
+                write!(f, ". {} DPUs are needed", enough)
+            }
+            (Some(min), Some(enough)) => write!(
+                f,
+                ". At least {} DPUs are needed, and {} are enough",
+                min, enough
+            ),
+            (Some(_), None) => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_explain_infeasible() -> anyhow::Result<()> {
+    use super::{DataMappingError, MilpPartitioner, Partitioner};
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    let explain =
+        |partitioner: &dyn Partitioner, graph: &str, core_size| -> anyhow::Result<Infeasibility> {
+            let source = format!(
+                "node Small {{ n1: int32; }}; node Big {{ n1: int64[10]; }}; graph {{ {} }};",
+                graph
+            );
+            let g = semantic_analysis(parse_str(&source)?)?.graphs[0].clone();
+            let hardware = HardwareConfig {
+                core_size,
+                core_num: 2,
+                rank_size: None,
+                edge_placement: Default::default(),
+            };
+            let error = partitioner.partition(&g, &hardware).unwrap_err();
+            match error.downcast::<DataMappingError>()? {
+                DataMappingError::Infeasible(infeasibility) => Ok(infeasibility),
+                error => panic!("unexpected error {}", error),
+            }
+        };
+    let milp = MilpPartitioner::default();
+    let big = explain(&milp, "node Small a; node Big b;", 64)?;
+    assert_eq!(
+        big.reasons,
+        ["node `b` alone (80 bytes) exceeds DPU capacity 64 bytes"]
+    );
+    assert_eq!(big.min_dpus, None);
+
+    // Three pinned nodes overflow their DPU, however many others there are: the conflict is
+    // narrowed down to the pins and the capacity of the pinned DPU, by the deletion
+    // filtering of the MILP as by the unsat core of Z3.
+    let pins =
+        "@pin(dpu = 0) node Small a; @pin(dpu = 0) node Small b; @pin(dpu = 0) node Small c;";
+    let partitioners: Vec<Box<dyn Partitioner>> = vec![Box::new(MilpPartitioner::default())];
+    #[cfg(feature = "z3")]
+    let mut partitioners = partitioners;
+    #[cfg(feature = "z3")]
+    partitioners.push(Box::new(super::Z3Partitioner::default()));
+    for partitioner in &partitioners {
+        let pinned = explain(partitioner.as_ref(), pins, 8)?;
+        assert_eq!(
+            pinned.reasons,
+            [
+                "node `a` pinned to DPU 0",
+                "node `b` pinned to DPU 0",
+                "node `c` pinned to DPU 0",
+                "the capacity of DPU 0 (8 bytes each) for a graph of 12 bytes"
+            ],
+            "{}",
+            partitioner.name()
+        );
+        assert_eq!(pinned.enough_dpus, None);
+    }
+
+    assert_eq!(human_bytes(72 * 1024 * 1024), "72 MB");
+    assert_eq!(human_bytes(1288490189), "1.2 GB");
+    Ok(())
+}
@@ -0,0 +1,462 @@
+use super::components::solve_by_components;
+use super::graph::PartitionGraph;
+use super::infeasible::explain_unsat;
+use super::{
+    Balance, DataMappingError, HardwareConfig, MultilevelPartitioner, Objective, Partition,
+    Partitioner, Requirement, SolveStatus,
+};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use good_lp::constraint::{eq, geq, leq};
+use good_lp::{
+    microlp, variable, Expression, ProblemVariables, ResolutionError, Solution, SolutionStatus,
+    SolverModel, Variable, WithTimeLimit,
+};
+use std::time::{Duration, Instant};
+
+/// The exact formulation as an integer linear program, solved by the pure-Rust `microlp`
+/// branch and bound. It models the same capacities, cost limit and placement constraints
+/// as `Z3Partitioner`, minimizing the cut weight directly.
+pub struct MilpPartitioner {
+    /// Wall clock budget for the whole optimization; without one, the solver runs until it
+    /// proves optimality.
+    pub timeout: Option<Duration>,
+    pub balance: Balance,
+    /// Partitions instead when the solver finds nothing within the budget.
+    pub fallback: Option<MultilevelPartitioner>,
+}
+
+impl Default for MilpPartitioner {
+    fn default() -> Self {
+        MilpPartitioner {
+            timeout: None,
+            balance: Balance::default(),
+            fallback: Some(MultilevelPartitioner::default()),
+        }
+    }
+}
+
+impl Partitioner for MilpPartitioner {
+    fn name(&self) -> &'static str {
+        "milp"
+    }
+
+    /// Disconnected components are solved independently and packed onto the DPUs, as for
+    /// `Z3Partitioner`.
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, hardware.core_num as usize);
+        let solve = |graph: &PartitionGraph, hardware: &HardwareConfig| {
+            let budget = self
+                .timeout
+                .map(|timeout| timeout.saturating_sub(start.elapsed()));
+            solve_milp(graph, hardware, budget, &self.balance)
+        };
+        let decomposed = match self.balance.objective {
+            Objective::Cut if !pgraph.is_constrained() => {
+                solve_by_components(&pgraph, hardware, solve)
+            }
+            _ => Ok(None),
+        };
+        let result = match decomposed {
+            Ok(Some(solution)) => Ok(solution),
+            Ok(None) => solve(&pgraph, hardware),
+            Err(e) => Err(e),
+        };
+        let (assignment, status) = match result {
+            Err(e) if matches!(e.downcast_ref(), Some(DataMappingError::BudgetExhausted)) => {
+                let Some(fallback) = &self.fallback else {
+                    return Err(e);
+                };
+                let mut partition = fallback.partition(graph, hardware)?;
+                partition.stats.status = SolveStatus::Fallback(self.name());
+                return Ok(partition);
+            }
+            result => {
+                let names: Vec<&str> = graph
+                    .node_insts
+                    .iter()
+                    .map(|node| node.varname.as_str())
+                    .collect();
+                result.map_err(|e| explain_unsat(e, &pgraph, &names, &group, hardware))?
+            }
+        };
+        let mut partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| assignment[*g]).collect(),
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )?;
+        partition.stats.status = status;
+        Ok(partition)
+    }
+}
+
+/// What the program minimizes.
+enum Goal {
+    /// The cut weight plus `penalty` times the largest core cost.
+    Cut { penalty: i64 },
+    /// The largest core cost, among the partitions cutting at most this weight.
+    MaxCost { cut_limit: i64 },
+}
+
+/// The variables of the program.
+struct Program {
+    /// `cores[i][j]` is 1 when node `i` is on core `j`, or `None` when it may not be.
+    cores: Vec<Vec<Option<Variable>>>,
+    cut: Expression,
+    max_cost: Variable,
+}
+
+/// Write the program for `graph` on `hardware`:
+///
+/// * `x[i][j]` binary, node `i` on core `j`, each node on exactly one core;
+/// * `y[e][j]` in [0, 1], at least both `x` of the endpoints of edge `e` on core `j`, so
+///   that an edge is charged to every core holding one of its endpoints;
+/// * `z[e]` in [0, 1], at least `|x[from][j] - x[to][j]|` on every core for a positive
+///   weight, at most `2 - x[from][j] - x[to][j]` for a negative one, so that it is 1
+///   exactly when the edge is cut;
+/// * capacities over node sizes and `y`, the cost limit, pins and separations;
+/// * `max_cost` at least the cost of every core.
+///
+/// Without placement constraints, node `i` may only use the first `i + 1` cores, which
+/// breaks the symmetry between cores as the one-hot Z3 encoding does. The `relaxed`
+/// requirements are left out, and with them the symmetry breaking.
+fn program(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    goal: &Goal,
+    relaxed: &[Requirement],
+) -> (Program, ProblemVariables, Vec<good_lp::Constraint>) {
+    let n = graph.node_count();
+    let core_num = hardware.core_num.max(0) as usize;
+    let symmetric = !graph.is_constrained() && relaxed.is_empty();
+    let kept = |requirement: Requirement| !relaxed.contains(&requirement);
+    let mut vars = ProblemVariables::new();
+    let cores: Vec<Vec<Option<Variable>>> = (0..n)
+        .map(|i| {
+            (0..core_num)
+                .map(|j| {
+                    let open = match graph.fixed[i] {
+                        Some(pinned) if kept(Requirement::Pin(i)) => pinned == j,
+                        _ => !symmetric || j <= i,
+                    };
+                    open.then(|| vars.add(variable().binary()))
+                })
+                .collect()
+        })
+        .collect();
+    let on = |i: usize, j: usize| -> Expression {
+        cores[i][j].map_or(Expression::from(0), Expression::from)
+    };
+    let mut constraints = vec![];
+    for row in &cores {
+        let placed: Expression = row.iter().flatten().copied().sum();
+        constraints.push(eq(placed, 1));
+    }
+
+    let mut loads: Vec<Expression> = (0..core_num)
+        .map(|j| (0..n).map(|i| graph.node_sizes[i] as f64 * on(i, j)).sum())
+        .collect();
+    let mut cut = Expression::from(0);
+    for edge in &graph.edges {
+        if edge.from == edge.to {
+            for (j, load) in loads.iter_mut().enumerate() {
+                *load += edge.size as f64 * on(edge.from, j);
+            }
+            continue;
+        }
+        if edge.size != 0 {
+            for (j, load) in loads.iter_mut().enumerate() {
+                let stored = vars.add(variable().min(0).max(1));
+                constraints.push(geq(stored, on(edge.from, j)));
+                constraints.push(geq(stored, on(edge.to, j)));
+                *load += edge.size as f64 * stored;
+            }
+        }
+        if edge.weight != 0 {
+            let cut_edge = vars.add(variable().min(0).max(1));
+            for j in 0..core_num {
+                if edge.weight > 0 {
+                    constraints.push(geq(cut_edge, on(edge.from, j) - on(edge.to, j)));
+                    constraints.push(geq(cut_edge, on(edge.to, j) - on(edge.from, j)));
+                } else {
+                    constraints.push(leq(cut_edge, 2 - on(edge.from, j) - on(edge.to, j)));
+                }
+            }
+            cut += edge.weight as f64 * cut_edge;
+        }
+    }
+    for (j, load) in loads.into_iter().enumerate() {
+        if kept(Requirement::Capacity(j)) {
+            constraints.push(leq(load, hardware.core_size as f64));
+        }
+    }
+
+    let max_cost = vars.add(variable().min(0));
+    for j in 0..core_num {
+        let cost: Expression = (0..n).map(|i| graph.node_costs[i] as f64 * on(i, j)).sum();
+        if let Some(limit) = graph.cost_limit.filter(|_| kept(Requirement::CostLimit)) {
+            constraints.push(leq(cost.clone(), limit as f64));
+        }
+        constraints.push(geq(max_cost, cost));
+    }
+    for (a, others) in graph.separated.iter().enumerate() {
+        for &b in others.iter().filter(|b| **b > a) {
+            if !kept(Requirement::Separate(a, b)) {
+                continue;
+            }
+            for j in 0..core_num {
+                constraints.push(leq(on(a, j) + on(b, j), 1));
+            }
+        }
+    }
+    if let Goal::MaxCost { cut_limit } = goal {
+        constraints.push(leq(cut.clone(), *cut_limit as f64));
+    }
+    (
+        Program {
+            cores,
+            cut,
+            max_cost,
+        },
+        vars,
+        constraints,
+    )
+}
+
+/// Every requirement of the program.
+fn requirements(graph: &PartitionGraph, core_num: usize) -> Vec<Requirement> {
+    let mut all: Vec<Requirement> = (0..core_num).map(Requirement::Capacity).collect();
+    if graph.cost_limit.is_some() {
+        all.push(Requirement::CostLimit);
+    }
+    all.extend(
+        (0..graph.node_count())
+            .filter(|node| graph.fixed[*node].is_some())
+            .map(Requirement::Pin),
+    );
+    for (a, others) in graph.separated.iter().enumerate() {
+        all.extend(
+            others
+                .iter()
+                .filter(|b| **b > a)
+                .map(|b| Requirement::Separate(a, *b)),
+        );
+    }
+    all
+}
+
+/// Narrow the requirements of an infeasible program down to a minimal conflicting set by
+/// deletion filtering: a requirement is dropped for good when the program stays infeasible
+/// without it. A check running out of `budget` keeps its requirement, so the set still
+/// conflicts but may not be minimal then.
+fn conflict(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    budget: Option<Duration>,
+) -> Result<Vec<Requirement>> {
+    let start = Instant::now();
+    let all = requirements(graph, hardware.core_num.max(0) as usize);
+    let mut relaxed = vec![];
+    let mut conflict = vec![];
+    for requirement in all {
+        relaxed.push(requirement);
+        let (_, vars, constraints) = program(graph, hardware, &Goal::Cut { penalty: 0 }, &relaxed);
+        let mut model = vars.minimise(Expression::from(0)).using(microlp);
+        if let Some(budget) = budget {
+            model = model.with_time_limit(budget.saturating_sub(start.elapsed()).as_secs_f64());
+        }
+        for constraint in constraints {
+            model.add_constraint(constraint);
+        }
+        let needed = match model.solve() {
+            Ok(_) => true,
+            Err(ResolutionError::Infeasible) => false,
+            Err(ResolutionError::Other(message)) if message.starts_with("Time limit") => true,
+            Err(e) => return Err(anyhow::anyhow!("{}", e)),
+        };
+        if needed {
+            relaxed.pop();
+            conflict.push(requirement);
+        }
+    }
+    Ok(conflict)
+}
+
+/// Solve one program, returning the assignment, its objective and the proven lower bound
+/// on the objective when the budget ran out first.
+fn solve_program(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    goal: Goal,
+    budget: Option<Duration>,
+) -> Result<(Vec<usize>, Option<i64>)> {
+    let (program, vars, constraints) = program(graph, hardware, &goal, &[]);
+    let objective = match goal {
+        Goal::Cut { penalty } => program.cut.clone() + penalty as f64 * program.max_cost,
+        Goal::MaxCost { .. } => Expression::from(program.max_cost),
+    };
+    let mut model = vars.minimise(objective).using(microlp);
+    if let Some(budget) = budget {
+        model = model.with_time_limit(budget.as_secs_f64());
+    }
+    for constraint in constraints {
+        model.add_constraint(constraint);
+    }
+    let solution = match model.solve() {
+        Ok(solution) => solution,
+        Err(ResolutionError::Infeasible) => {
+            let conflict = conflict(graph, hardware, budget)?;
+            return Err(DataMappingError::Unsatisfiable(conflict).into());
+        }
+        // The only status microlp reports for a time limit hit before any solution.
+        Err(ResolutionError::Other(message)) if message.starts_with("Time limit") => {
+            return Err(DataMappingError::BudgetExhausted.into())
+        }
+        Err(e) => return Err(anyhow::anyhow!("{}", e)),
+    };
+    let assignment = program
+        .cores
+        .iter()
+        .enumerate()
+        .map(|(node, row)| {
+            row.iter()
+                .position(|x| x.is_some_and(|x| solution.value(x) > 0.5))
+                .ok_or(DataMappingError::Unplaced(node))
+        })
+        .collect::<Result<_, _>>()?;
+    let optimal = matches!(solution.status(), SolutionStatus::Optimal);
+    let bound = solution.into_inner().stats().best_bound;
+    // The objective is integral, so a fractional bound rounds up.
+    let bound = (!optimal).then(|| bound.map_or(i64::MIN, |b| (b - 1e-6).ceil() as i64));
+    Ok((assignment, bound))
+}
+
+/// Find the assignment of the nodes of `graph` to `hardware.core_num` cores with the
+/// smallest cut, within the byte capacity of every core, `graph.cost_limit` and the
+/// placement constraints of the graph, and with `balance` selecting how the largest core
+/// cost enters the objective, as `solve_graph` does with Z3.
+///
+/// When `budget` runs out, the best assignment found is returned with a `Feasible` status
+/// carrying the lower bound proven by the branch and bound; `BudgetExhausted` means no
+/// assignment was found at all, and `Unsatisfiable` that none exists.
+pub fn solve_milp(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    budget: Option<Duration>,
+    balance: &Balance,
+) -> Result<(Vec<usize>, SolveStatus)> {
+    let start = Instant::now();
+    let penalty = match balance.objective {
+        Objective::Weighted => balance.weight,
+        Objective::Cut | Objective::Lexicographic => 0,
+    };
+    let (assignment, bound) = solve_program(graph, hardware, Goal::Cut { penalty }, budget)?;
+    let status = match bound {
+        None => SolveStatus::Optimal,
+        Some(bound) => SolveStatus::Feasible { bound },
+    };
+    if balance.objective != Objective::Lexicographic || status != SolveStatus::Optimal {
+        return Ok((assignment, status));
+    }
+
+    // Among the minimum cuts, look for the one with the cheapest most loaded core.
+    let cut_limit = graph.cut_weight(&assignment);
+    let budget = budget.map(|budget| budget.saturating_sub(start.elapsed()));
+    match solve_program(graph, hardware, Goal::MaxCost { cut_limit }, budget) {
+        Ok((balanced, None)) => Ok((balanced, SolveStatus::Optimal)),
+        Ok((balanced, Some(_))) => Ok((balanced, SolveStatus::Feasible { bound: cut_limit })),
+        Err(e) if matches!(e.downcast_ref(), Some(DataMappingError::BudgetExhausted)) => {
+            Ok((assignment, SolveStatus::Feasible { bound: cut_limit }))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[test]
+fn test_milp() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // The same optimum as Z3 on a small random graph.
+    let config = GeneratorConfig {
+        kind: GraphKind::Uniform,
+        nodes: 10,
+        edges: 20,
+        max_weight: 5,
+        seed: 3,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 4,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let milp = MilpPartitioner::default().partition(&g, &hardware)?;
+    assert_eq!(milp.stats.status, SolveStatus::Optimal);
+    #[cfg(feature = "z3")]
+    {
+        let z3 = super::Z3Partitioner::default().partition(&g, &hardware)?;
+        assert_eq!(milp.stats.cut_weight, z3.stats.cut_weight);
+    }
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    assert!(pgraph
+        .core_loads(&milp.assignment, 3)
+        .iter()
+        .all(|load| *load <= hardware.core_size));
+
+    // Too little room in total, explained as for Z3.
+    let small = HardwareConfig {
+        core_size: 4 * 3,
+        ..hardware
+    };
+    let error = MilpPartitioner::default()
+        .partition(&g, &small)
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast::<DataMappingError>()?,
+        DataMappingError::Infeasible(_)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_milp_budget_fallback() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // No time at all to find a partition of a chain that needs three DPUs.
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 10,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 4,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let milp = MilpPartitioner {
+        timeout: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let partition = milp.partition(&g, &hardware)?;
+    assert_eq!(partition.stats.status, SolveStatus::Fallback("milp"));
+    let error = MilpPartitioner {
+        fallback: None,
+        ..milp
+    }
+    .partition(&g, &hardware)
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::BudgetExhausted)
+    ));
+    Ok(())
+}
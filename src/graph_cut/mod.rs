@@ -1,19 +1,55 @@
-use crate::{
-    base_type::{NamedBlock, Size},
-    parser::parse_str,
-    sem_type::{SemanticEdgeInst, SemanticNodeInst},
-    semantics_analysis::semantic_analysis,
-};
+use crate::base_type::Size;
+use crate::sem_type::{ReplicaMode, SemanticEdgeInst, SemanticGraph, SemanticNodeInst};
 use anyhow::Result;
+use clap::ValueEnum;
+use graph::PartitionGraph;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
-use std::{collections::HashMap, f32::consts::E};
+use std::time::Duration;
 use thiserror::Error;
-use z3::{
-    ast::{Ast, Bool, Int, BV},
-    Config, Context, Optimize, SatResult,
-};
 
-use std::fs;
+mod components;
+pub mod graph;
+mod greedy;
+mod hierarchical;
+mod hypergraph;
+mod incremental;
+mod infeasible;
+mod milp;
+mod multilevel;
+mod persist;
+mod profile;
+pub mod refine;
+mod replicate;
+#[cfg(feature = "z3")]
+mod smt;
+mod stats;
+mod streaming;
+mod tasklet;
+mod trace;
+
+pub use components::solve_by_components;
+pub use greedy::{greedy_assign, visit_order, GreedyPartitioner, VisitOrder};
+pub use hierarchical::HierarchicalPartitioner;
+pub use hypergraph::{connectivity_refine, Hypergraph, HypergraphPartitioner, Net};
+pub use incremental::{IncrementalPartitioner, Migration, MigrationPlan, Placement};
+pub use infeasible::{human_bytes, Infeasibility, Requirement};
+pub use milp::{solve_milp, MilpPartitioner};
+pub use multilevel::MultilevelPartitioner;
+pub use persist::{graph_hash, PartitionFile, PartitionFileError, SavedReplicas};
+pub use profile::{Hop, HopReader, Profile, ProfileError};
+pub use refine::{refine_partition, RefineRound};
+pub use replicate::ReplicatingPartitioner;
+#[cfg(feature = "z3")]
+pub use smt::{assign_with_z3, solve_graph};
+pub use stats::{CoreStats, PartitionStats, WalkerJumps};
+pub use streaming::{
+    EdgeListReader, StreamEdge, StreamError, StreamHeuristic, StreamingPartitioner,
+};
+pub use tasklet::{DpuTasklets, TaskletOptions, TaskletPlan, MAX_TASKLETS};
+pub use trace::{TraceModel, TraceOptions, TracePartitioner, WalkerTraces};
 
 #[derive(Error, Debug)]
 pub enum DataMappingError {
@@ -21,133 +57,620 @@ pub enum DataMappingError {
     NodeNotFound(String),
     #[error("No solution found")]
     NoSolutionFound,
+    #[error("No partition satisfies the constraints {0:?}")]
+    Unsatisfiable(Vec<Requirement>),
+    #[error("No partition exists: {0}")]
+    Infeasible(Infeasibility),
+    #[error("The solver ran out of budget before finding a solution")]
+    BudgetExhausted,
+    #[error("Value {0} is too large for a pseudo-boolean constraint")]
+    CoefficientOverflow(i64),
+    #[error("The assignment places {0} nodes, but the graph has {1}")]
+    AssignmentLength(usize, usize),
+    #[error("Node `{0}` is assigned to core {1}, but there are only {2} cores")]
+    CoreOutOfRange(String, usize, usize),
+    #[error("The solver put node {0} of the partition graph on no core")]
+    Unplaced(usize),
+    #[error("The compute costs overflow at node `{0}`; profile fewer runs or lower the `@cost` annotations")]
+    CostOverflow(String),
+    #[error("Node `{0}` does not fit on any core")]
+    CapacityExceeded(String),
+    #[error("Nodes `{0}` and `{1}` must share a DPU but are pinned to DPUs {2} and {3}")]
+    ConflictingPins(String, String, usize, usize),
+    #[error("Nodes `{0}` and `{1}` are separated but must share a DPU")]
+    ConflictingSeparation(String, String),
+    #[error("Trace weights need at least one walker in the graph")]
+    NoWalkers,
+    #[error("{0} DPUs do not fill whole ranks of {1} DPUs")]
+    PartialRank(i64, i64),
+    #[error("A rank holds at least one DPU, not {0}")]
+    RankSize(i64),
+    #[error("Node `{0}` is pinned or separated, so it cannot be replicated")]
+    ConstrainedReplica(String),
+    #[error("Partitions with replicated nodes cannot be refined")]
+    RefineReplicated,
+    #[error("This build has no Z3 solver; rebuild with `--features z3`, or choose another partitioner such as milp")]
+    Z3Unavailable,
+    #[error("A DPU runs 1 to {MAX_TASKLETS} tasklets, not {0}")]
+    TaskletCount(usize),
+    #[error(
+        "A tasklet needs at least {0} bytes of WRAM, more than its {1} bytes; use fewer tasklets"
+    )]
+    WramExceeded(i64, i64),
 
     #[error("Unknown error.")]
     Unknown,
 }
 
-pub struct PIMCoreAssignment {
-    pub nodes: Vec<Rc<SemanticNodeInst>>,
+/// Where the struct of an edge is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EdgePlacement {
+    /// On the DPU of its source node
+    Source,
+    /// On the DPU of its destination node
+    Destination,
+    /// On the DPU of each endpoint, once when they share a DPU
+    #[default]
+    Both,
+}
+
+impl EdgePlacement {
+    /// The cores storing an edge between nodes on `from` and `to`.
+    pub fn cores(self, from: usize, to: usize) -> Vec<usize> {
+        match self {
+            EdgePlacement::Source => vec![from],
+            EdgePlacement::Destination => vec![to],
+            EdgePlacement::Both if from == to => vec![from],
+            EdgePlacement::Both => vec![from, to],
+        }
+    }
+}
+
+/// The DPUs a graph is mapped onto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HardwareConfig {
+    /// Bytes available on each DPU.
+    pub core_size: i64,
+    pub core_num: i64,
+    /// DPUs per rank, when the partition should keep traffic within ranks.
+    pub rank_size: Option<i64>,
+    /// Where edges are stored, and charged against the capacity.
+    #[serde(default)]
+    pub edge_placement: EdgePlacement,
 }
 
-fn find_node_with_name(nodes: &Vec<(Rc<SemanticNodeInst>, Int)>, target: String) -> Result<usize> {
-    for i in 0..nodes.len() {
-        if nodes[i].0.varname == target {
-            return Ok(i);
+impl HardwareConfig {
+    /// Reject rank sizes that hold no DPU.
+    pub fn check(&self) -> Result<()> {
+        match self.rank_size {
+            Some(size) if size <= 0 => Err(DataMappingError::RankSize(size).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The rank holding `core`, or 0 when ranks are not modeled.
+    pub fn rank_of(&self, core: usize) -> usize {
+        match self.rank_size {
+            Some(size) if size > 0 => core / size as usize,
+            _ => 0,
         }
     }
-    Err(DataMappingError::NodeNotFound(target).into())
 }
 
-fn construct_result(
-    optimizer: &Optimize,
-    xs: &Vec<(Rc<SemanticNodeInst>, Int)>,
-    core_num: i64,
-) -> Result<Vec<Vec<Rc<SemanticNodeInst>>>> {
-    let mut result: Vec<Vec<Rc<SemanticNodeInst>>> = vec![vec![]; core_num as usize];
+/// How good a partition is known to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolveStatus {
+    /// No partition has a smaller cut.
+    Optimal,
+    /// The solver stopped early; no partition has an objective below `bound`. The
+    /// objective is the cut weight, plus the weighted largest core cost when balance is
+    /// weighted in.
+    Feasible { bound: i64 },
+    /// Nothing is known, as for heuristic partitions.
+    Unknown,
+    /// The exact solver named ran out of budget, and the multilevel heuristic partitioned
+    /// the graph instead.
+    Fallback(&'static str),
+}
 
-    match optimizer.check(&[]) {
-        SatResult::Sat => {
-            let model = optimizer.get_model().unwrap();
-            for (i, x) in xs.iter().enumerate() {
-                let val = model.eval(&x.1, true).unwrap().as_i64().unwrap();
-                result[val as usize].extend([x.0.clone()]);
-                println!("Node {} assigned to core {}", i, val);
+impl fmt::Display for SolveStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveStatus::Optimal => write!(f, "optimal"),
+            SolveStatus::Feasible { bound } => write!(f, "feasible, objective >= {}", bound),
+            SolveStatus::Unknown => write!(f, "optimality unknown"),
+            SolveStatus::Fallback(solver) => {
+                write!(f, "{} ran out of budget, multilevel fallback", solver)
             }
         }
-        _ => return Err(DataMappingError::NoSolutionFound.into()),
-    };
-    return Ok(result);
-}
-
-pub fn assign_with_z3(
-    unions: &Vec<Rc<SemanticNodeInst>>,
-    edges: &Vec<Rc<SemanticEdgeInst>>,
-    core_size: i64,
-    core_num: i64,
-) -> Result<Vec<Vec<Rc<SemanticNodeInst>>>> {
-    let config = Config::new();
-    let context = Context::new(&config);
-    let optimizer = Optimize::new(&context);
-    let union_size = unions.len();
-    let xs: Vec<(Rc<SemanticNodeInst>, Int)> = (0..union_size)
-        .map(|i| {
-            (
-                unions[i].clone(),
-                Int::new_const(&context, format! {"x_{}", i}),
-            )
+    }
+}
+
+/// What a partitioner reports about its own run.
+#[derive(Debug, Clone)]
+pub struct SolveStats {
+    pub partitioner: &'static str,
+    pub elapsed: Duration,
+    /// Total weight of edges whose endpoints live on different cores.
+    pub cut_weight: i64,
+    pub status: SolveStatus,
+}
+
+/// How the compute cost of the most loaded core enters the exact objective.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Objective {
+    /// Minimize the cut weight only
+    #[default]
+    Cut,
+    /// Minimize the cut weight, then the largest core cost among the minimum cuts
+    Lexicographic,
+    /// Minimize the cut weight plus the balance weight times the largest core cost
+    Weighted,
+}
+
+/// Load balancing requirements on the compute cost of the cores.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Balance {
+    /// No core may cost more than `1 + epsilon` times the average.
+    pub epsilon: Option<f64>,
+    pub objective: Objective,
+    /// Cut weight traded for one unit of the largest core cost under `Objective::Weighted`.
+    pub weight: i64,
+}
+
+impl Balance {
+    /// Most compute cost a core may take out of `total_cost` spread over `core_num` cores.
+    pub fn cost_limit(&self, total_cost: i64, core_num: usize) -> Option<i64> {
+        self.epsilon.map(|epsilon| {
+            ((1.0 + epsilon) * total_cost as f64 / core_num.max(1) as f64).ceil() as i64
         })
-        .collect();
+    }
+
+    /// Constrain the core costs of `graph` as required.
+    pub fn apply(&self, graph: &mut PartitionGraph, core_num: usize) {
+        graph.cost_limit = self.cost_limit(graph.total_cost(), core_num);
+    }
+}
+
+/// Copies of a node on cores besides its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replicas {
+    /// Index of the node in `Partition::nodes`.
+    pub node: usize,
+    pub mode: ReplicaMode,
+    /// Cores holding a copy, in increasing order, not including the node's own core.
+    pub cores: Vec<usize>,
+}
 
-    let mut var_map: HashMap<String, Int> = HashMap::new();
+/// A node -> DPU mapping, shared by every partitioning strategy.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    /// Node varnames, in the order of `SemanticGraph::node_insts`, or in order of first
+    /// appearance for a partition computed from an edge stream.
+    pub nodes: Vec<String>,
+    /// `assignment[i]` is the core holding `nodes[i]`.
+    pub assignment: Vec<usize>,
+    pub core_num: usize,
+    /// The replicated nodes.
+    pub replicas: Vec<Replicas>,
+    pub stats: SolveStats,
+}
 
-    for x in &xs {
-        optimizer.assert(&x.1.ge(&Int::from_i64(&context, 0)));
-        optimizer.assert(&x.1.lt(&Int::from_i64(&context, core_num)));
+impl Partition {
+    pub fn new(
+        nodes: &[Rc<SemanticNodeInst>],
+        edges: &[Rc<SemanticEdgeInst>],
+        assignment: Vec<usize>,
+        core_num: usize,
+        partitioner: &'static str,
+        elapsed: Duration,
+    ) -> Result<Partition> {
+        if assignment.len() != nodes.len() {
+            return Err(DataMappingError::AssignmentLength(assignment.len(), nodes.len()).into());
+        }
+        for (node, core) in nodes.iter().zip(&assignment) {
+            if *core >= core_num {
+                return Err(DataMappingError::CoreOutOfRange(
+                    node.varname.clone(),
+                    *core,
+                    core_num,
+                )
+                .into());
+            }
+        }
+        let index: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let mut cut_weight = 0;
+        for edge in edges {
+            let from =
+                index
+                    .get(edge.from_var.varname.as_str())
+                    .ok_or(DataMappingError::NodeNotFound(
+                        edge.from_var.varname.clone(),
+                    ))?;
+            let to = index
+                .get(edge.to_var.varname.as_str())
+                .ok_or(DataMappingError::NodeNotFound(edge.to_var.varname.clone()))?;
+            if assignment[*from] != assignment[*to] {
+                cut_weight += edge.weight;
+            }
+        }
+        Ok(Partition {
+            nodes: nodes.iter().map(|node| node.varname.clone()).collect(),
+            assignment,
+            core_num,
+            replicas: vec![],
+            stats: SolveStats {
+                partitioner,
+                elapsed,
+                cut_weight,
+                status: SolveStatus::Unknown,
+            },
+        })
     }
 
-    for j in 0..core_num {
-        // For bag j, compute total weight.
-        // We build an Int expression representing the total weight in bag j.
-        let mut total_weight = Int::from_i64(&context, 0);
-        for i in 0..union_size {
-            // Convert the boolean decision (true/false) to an integer 1/0.
-            // This is done via an if-then-else.
+    /// Number of nodes on each core.
+    pub fn core_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.core_num];
+        for core in &self.assignment {
+            sizes[*core] += 1;
+        }
+        sizes
+    }
 
-            let node_in_core = xs[i].1._eq(&Int::from_i64(&context, j));
-            let node_size_in_core = node_in_core.ite(
-                &Int::from_i64(&context, unions[i].node_type.size_byte()),
-                &Int::from_i64(&context, 0),
-            );
-            // Multiply by the weight of the item.
-            // let weighted = node.mul(&[&Int::from_i64(&ctx, weights[i] as i64)]);
-            total_weight = total_weight + node_size_in_core;
+    /// The nodes of `graph` grouped by core, as consumed by the code generator. Replicated
+    /// nodes appear on every core holding a copy.
+    pub fn core_nodes(&self, graph: &SemanticGraph) -> Vec<Vec<Rc<SemanticNodeInst>>> {
+        let mut result = vec![vec![]; self.core_num];
+        for (node, core) in graph.node_insts.iter().zip(&self.assignment) {
+            result[*core].push(node.clone());
         }
+        for replicas in &self.replicas {
+            for core in &replicas.cores {
+                result[*core].push(graph.node_insts[replicas.node].clone());
+            }
+        }
+        result
+    }
 
-        // Calculate all edges
-        for edge in edges {
-            let from_id = find_node_with_name(&xs, edge.from_var.varname.clone())?;
-            let to_id = find_node_with_name(&xs, edge.to_var.varname.clone())?;
-            let list = [
-                &Int::from_i64(&context, j)._eq(&xs[from_id].1),
-                &Int::from_i64(&context, j)._eq(&xs[to_id].1),
-            ];
-            let edge_in_core = Bool::or(&context, &list);
-            let edge_weight = edge_in_core.ite(
-                &Int::from_i64(&context, edge.edge_type.named_block.size_byte()),
-                &Int::from_i64(&context, 0),
-            );
-            total_weight = total_weight + edge_weight;
-        }
-        // The total weight must be <= capacity of bag j.
-        let capacity_expr = Int::from_i64(&context, core_size);
-        let capacity_constraint = total_weight.le(&capacity_expr);
-        optimizer.assert(&capacity_constraint);
-    }
-
-    let mut evaluation = Int::from_i64(&context, 0);
-    for edge in edges {
-        let from_id = find_node_with_name(&xs, edge.from_var.varname.clone())?;
-        let to_id = find_node_with_name(&xs, edge.to_var.varname.clone())?;
-        let to_x = &xs[to_id].1;
-        let weight = Int::from_i64(&context, edge.weight);
-        evaluation = evaluation
-            + xs[from_id]
-                .1
-                ._eq(to_x)
-                .ite(&weight, &Int::from_i64(&context, 0));
-    }
-
-    optimizer.maximize(&evaluation);
-    construct_result(&optimizer, &xs, core_num)
+    /// Bytes held on each core: the nodes, copies included, and the edges stored there
+    /// under `placement`.
+    pub fn core_bytes(&self, graph: &SemanticGraph, placement: EdgePlacement) -> Result<Vec<i64>> {
+        let mut bytes = vec![0; self.core_num];
+        for (node, inst) in graph.node_insts.iter().enumerate() {
+            bytes[self.assignment[node]] += inst.node_type.size_byte();
+        }
+        for replicas in &self.replicas {
+            for core in &replicas.cores {
+                bytes[*core] += graph.node_insts[replicas.node].node_type.size_byte();
+            }
+        }
+        for (edge, cores) in graph
+            .edge_insts
+            .iter()
+            .zip(self.edge_cores(graph, placement)?)
+        {
+            for core in cores {
+                bytes[core] += edge.edge_type.named_block.size_byte();
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// The cores storing each edge of `graph`, in the order of `SemanticGraph::edge_insts`.
+    /// An edge between nodes held together by a core, copies included, is stored there
+    /// once; a cut edge where `placement` puts it.
+    pub fn edge_cores(
+        &self,
+        graph: &SemanticGraph,
+        placement: EdgePlacement,
+    ) -> Result<Vec<Vec<usize>>> {
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+        let mut copies: Vec<&[usize]> = vec![&[]; graph.node_insts.len()];
+        for replicas in &self.replicas {
+            copies[replicas.node] = &replicas.cores;
+        }
+        let holds = |core: usize, node: usize| {
+            self.assignment[node] == core || copies[node].contains(&core)
+        };
+        graph
+            .edge_insts
+            .iter()
+            .map(|edge| {
+                let (from, to) = (find(&edge.from_var.varname)?, find(&edge.to_var.varname)?);
+                let (from_core, to_core) = (self.assignment[from], self.assignment[to]);
+                Ok(if holds(from_core, to) {
+                    vec![from_core]
+                } else if holds(to_core, from) {
+                    vec![to_core]
+                } else {
+                    placement.cores(from_core, to_core)
+                })
+            })
+            .collect()
+    }
+}
+
+/// A strategy for mapping the nodes of a graph onto DPUs.
+pub trait Partitioner {
+    fn name(&self) -> &'static str;
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition>;
+}
+
+/// Limits on the solver's effort. Without any, Z3 runs until it proves optimality.
+#[derive(Debug, Clone, Default)]
+pub struct SolverBudget {
+    /// Wall clock budget for the whole optimization.
+    pub timeout: Option<Duration>,
+    /// Z3 resource limit (`rlimit`) of each solver query; unlike the timeout, it makes runs
+    /// reproducible.
+    pub rlimit: Option<u32>,
+}
+
+/// How the partitioning problem is written down for Z3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Z3Encoding {
+    /// One integer core id per node, with `ite` sums for the capacities and the objective
+    Ite,
+    /// One boolean per node and core, with pseudo-boolean capacity constraints and
+    /// symmetry breaking
+    OneHot,
+}
+
+/// The exact formulation, maximizing co-located edge weight.
+pub struct Z3Partitioner {
+    pub encoding: Z3Encoding,
+    pub budget: SolverBudget,
+    pub balance: Balance,
+    /// Partitions instead when Z3 finds nothing within the budget.
+    pub fallback: Option<MultilevelPartitioner>,
+}
+
+impl Default for Z3Partitioner {
+    fn default() -> Self {
+        Z3Partitioner {
+            encoding: Z3Encoding::OneHot,
+            budget: SolverBudget::default(),
+            balance: Balance::default(),
+            fallback: Some(MultilevelPartitioner::default()),
+        }
+    }
+}
+
+/// Without the `z3` feature, the Z3 partitioner only reports that it is missing.
+#[cfg(not(feature = "z3"))]
+impl Partitioner for Z3Partitioner {
+    fn name(&self) -> &'static str {
+        "z3"
+    }
+
+    fn partition(&self, _graph: &SemanticGraph, _hardware: &HardwareConfig) -> Result<Partition> {
+        Err(DataMappingError::Z3Unavailable.into())
+    }
+}
+
+/// The partitioners selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PartitionerKind {
+    /// Exact optimization with the Z3 SMT solver
+    #[cfg_attr(feature = "z3", default)]
+    Z3,
+    /// Exact optimization as an integer linear program, with the pure-Rust microlp solver
+    #[cfg_attr(not(feature = "z3"), default)]
+    Milp,
+    /// Neighbor-affinity greedy placement
+    Greedy,
+    /// Multilevel coarsening with Fiduccia–Mattheyses refinement
+    Multilevel,
+    /// Multilevel partition refined for the connectivity-minus-one of the walker-step hypergraph
+    Hypergraph,
+    /// One-pass streaming placement with the Linear Deterministic Greedy heuristic
+    Ldg,
+    /// One-pass streaming placement with the Fennel heuristic
+    Fennel,
+}
+
+/// Command line options selecting and tuning the partitioner.
+#[derive(Debug, Clone, clap::Args)]
+pub struct PartitionerOptions {
+    /// Strategy used to map graph nodes onto DPUs; z3 by default, or milp in builds without
+    /// the z3 feature
+    #[arg(long, value_enum, default_value_t = PartitionerKind::default())]
+    pub partitioner: PartitionerKind,
+    /// Node visit order of the greedy partitioner
+    #[arg(long, value_enum, default_value_t = VisitOrder::Bfs)]
+    pub visit_order: VisitOrder,
+    /// Seed for the randomized choices of the heuristic partitioners
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Refinement passes per level of the multilevel partitioner
+    #[arg(long, default_value_t = 8)]
+    pub refine_passes: usize,
+    /// Formulation handed to Z3
+    #[arg(long, value_enum, default_value_t = Z3Encoding::OneHot)]
+    pub z3_encoding: Z3Encoding,
+    /// Seconds Z3 may spend before returning the best partition found so far
+    #[arg(long)]
+    pub z3_timeout: Option<f64>,
+    /// Z3 resource limit per solver query, for reproducible budgets
+    #[arg(long)]
+    pub z3_rlimit: Option<u32>,
+    /// Seconds the MILP solver may spend before returning the best partition found so far
+    #[arg(long)]
+    pub milp_timeout: Option<f64>,
+    /// Fail instead of falling back to the multilevel partitioner when the exact solver
+    /// finds nothing
+    #[arg(long)]
+    pub no_fallback: bool,
+    /// Keep the compute cost of every core within (1 + epsilon) times the average; not
+    /// enforced by the streaming partitioners
+    #[arg(long)]
+    pub balance_epsilon: Option<f64>,
+    /// How the cost of the most loaded core enters the objective of the exact solvers
+    #[arg(long, value_enum, default_value_t = Objective::Cut)]
+    pub objective: Objective,
+    /// Cut weight worth one unit of the largest core cost in the weighted objective
+    #[arg(long, default_value_t = 1)]
+    pub balance_weight: i64,
+    #[command(flatten)]
+    pub trace: TraceOptions,
+    /// Replicate every node with at least this many edges on the DPUs of its neighbors
+    #[arg(long)]
+    pub replicate_degree: Option<usize>,
+    /// Consistency of the copies of nodes replicated by degree
+    #[arg(long, value_enum, default_value_t = ReplicaMode::ReadOnly)]
+    pub replica_mode: ReplicaMode,
+    /// How much more an edge between ranks costs than one within a rank, with `--rank-size`
+    #[arg(long, default_value_t = 4)]
+    pub inter_rank_factor: i64,
+}
+
+impl Default for PartitionerOptions {
+    fn default() -> Self {
+        PartitionerOptions {
+            partitioner: PartitionerKind::default(),
+            visit_order: VisitOrder::Bfs,
+            seed: 0,
+            refine_passes: 8,
+            z3_encoding: Z3Encoding::OneHot,
+            z3_timeout: None,
+            z3_rlimit: None,
+            milp_timeout: None,
+            no_fallback: false,
+            balance_epsilon: None,
+            objective: Objective::Cut,
+            balance_weight: 1,
+            trace: TraceOptions::default(),
+            replicate_degree: None,
+            replica_mode: ReplicaMode::ReadOnly,
+            inter_rank_factor: 4,
+        }
+    }
+}
+
+impl PartitionerOptions {
+    pub fn balance(&self) -> Balance {
+        Balance {
+            epsilon: self.balance_epsilon,
+            objective: self.objective,
+            weight: self.balance_weight,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Partitioner> {
+        let mut partitioner: Box<dyn Partitioner> = Box::new(HierarchicalPartitioner {
+            inner: self.build_untraced(),
+            inter_rank_factor: self.inter_rank_factor,
+            refine_passes: self.refine_passes,
+        });
+        if self.trace.trace_weights {
+            partitioner = Box::new(TracePartitioner {
+                inner: partitioner,
+                options: self.trace.clone(),
+                seed: self.seed,
+            });
+        }
+        Box::new(ReplicatingPartitioner {
+            inner: partitioner,
+            degree_threshold: self.replicate_degree,
+            mode: self.replica_mode,
+        })
+    }
+
+    fn multilevel(&self, balance: Balance) -> MultilevelPartitioner {
+        MultilevelPartitioner {
+            seed: self.seed,
+            refine_passes: self.refine_passes,
+            balance,
+        }
+    }
+
+    fn build_untraced(&self) -> Box<dyn Partitioner> {
+        let balance = self.balance();
+        match self.partitioner {
+            PartitionerKind::Z3 => Box::new(Z3Partitioner {
+                encoding: self.z3_encoding,
+                budget: SolverBudget {
+                    timeout: self.z3_timeout.map(Duration::from_secs_f64),
+                    rlimit: self.z3_rlimit,
+                },
+                balance,
+                fallback: (!self.no_fallback).then(|| self.multilevel(balance)),
+            }),
+            PartitionerKind::Milp => Box::new(MilpPartitioner {
+                timeout: self.milp_timeout.map(Duration::from_secs_f64),
+                balance,
+                fallback: (!self.no_fallback).then(|| self.multilevel(balance)),
+            }),
+            PartitionerKind::Greedy => Box::new(GreedyPartitioner {
+                order: self.visit_order,
+                balance,
+            }),
+            PartitionerKind::Multilevel => Box::new(self.multilevel(balance)),
+            PartitionerKind::Hypergraph => Box::new(HypergraphPartitioner {
+                inner: Box::new(self.multilevel(balance)),
+                balance,
+                refine_passes: self.refine_passes,
+            }),
+            PartitionerKind::Ldg => Box::new(StreamingPartitioner {
+                heuristic: StreamHeuristic::Ldg,
+                ..Default::default()
+            }),
+            PartitionerKind::Fennel => Box::new(StreamingPartitioner {
+                heuristic: StreamHeuristic::Fennel,
+                ..Default::default()
+            }),
+        }
+    }
 }
 
 #[test]
+fn test_partition_checks_assignment() -> Result<()> {
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
 
-fn test_z3() -> Result<()> {
-    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
-    let sm = semantic_analysis(parse_str(&file_context)?)?;
-    let g = sm.graphs[0].clone();
-    assign_with_z3(&g.node_insts, &g.edge_insts, 3, 3)?;
+    let source = "
+        node Alice { n1: int8; };
+        edge Alice Alice Link {};
+        graph {
+            node Alice a0, a1, a2;
+            edge Link a0 a1 1;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let partition = |assignment| {
+        Partition::new(
+            &g.node_insts,
+            &g.edge_insts,
+            assignment,
+            2,
+            "test",
+            Duration::ZERO,
+        )
+    };
+    assert_eq!(partition(vec![0, 1, 1])?.stats.cut_weight, 1);
+    let error = partition(vec![0, 1]).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::AssignmentLength(2, 3))
+    ));
+    let error = partition(vec![0, 1, 2]).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::CoreOutOfRange(_, 2, 2))
+    ));
     Ok(())
 }
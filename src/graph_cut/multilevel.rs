@@ -0,0 +1,470 @@
+use super::graph::{GraphEdge, PartitionGraph};
+use super::greedy::{greedy_assign, visit_order, VisitOrder};
+use super::refine::fm_refine;
+use super::{Balance, DataMappingError, HardwareConfig, Partition, Partitioner};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+
+/// Stop coarsening once a level shrinks the graph by less than this factor.
+const MIN_SHRINK: f64 = 0.95;
+
+/// Neighbors of each middle node looked at when searching for a two-hop match.
+const MAX_TWO_HOP_SCAN: usize = 32;
+
+/// METIS-style partitioner: coarsen the graph by heavy-edge matching, partition the
+/// coarsest graph greedily, then project back level by level with FM refinement.
+pub struct MultilevelPartitioner {
+    pub seed: u64,
+    /// FM passes run at every level while uncoarsening.
+    pub refine_passes: usize,
+    pub balance: Balance,
+}
+
+impl Default for MultilevelPartitioner {
+    fn default() -> Self {
+        MultilevelPartitioner {
+            seed: 0,
+            refine_passes: 8,
+            balance: Balance::default(),
+        }
+    }
+}
+
+/// One coarsening step: `graph` is the coarse graph and `coarse_of[i]` the coarse node
+/// that node `i` of the finer level was merged into.
+struct Level {
+    graph: PartitionGraph,
+    coarse_of: Vec<usize>,
+}
+
+/// Whether nodes `a` and `b`, joined by edges of `size` bytes, may be merged into a node
+/// of at most `max_size` bytes and a quarter of the cost limit. Nodes that must not share
+/// a core are never merged.
+fn can_merge(graph: &PartitionGraph, a: usize, b: usize, size: i64, max_size: i64) -> bool {
+    graph.node_sizes[a] + graph.node_sizes[b] + size <= max_size
+        && (graph.fixed[a].is_none()
+            || graph.fixed[b].is_none()
+            || graph.fixed[a] == graph.fixed[b])
+        && !graph.separated[a].contains(&b)
+        && graph
+            .cost_limit
+            .is_none_or(|limit| graph.node_costs[a] + graph.node_costs[b] <= limit / 4)
+}
+
+/// Match every node with its unmatched neighbor of heaviest positive edge weight,
+/// without letting a merged node grow beyond `max_size` bytes.
+fn heavy_edge_matching(graph: &PartitionGraph, rng: &mut ChaCha8Rng, max_size: i64) -> Vec<usize> {
+    let n = graph.node_count();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+    let mut mate: Vec<Option<usize>> = vec![None; n];
+    for &node in &order {
+        if mate[node].is_some() {
+            continue;
+        }
+        let mut best: Option<(i64, usize)> = None;
+        for &edge in graph.incident(node) {
+            let other = graph.other_end(edge, node);
+            let weight = graph.edges[edge].weight;
+            if other == node || mate[other].is_some() || weight <= 0 {
+                continue;
+            }
+            if !can_merge(graph, node, other, graph.edges[edge].size, max_size) {
+                continue;
+            }
+            if best.is_none_or(|(w, _)| weight > w) {
+                best = Some((weight, other));
+            }
+        }
+        if let Some((_, other)) = best {
+            mate[node] = Some(other);
+            mate[other] = Some(node);
+        }
+    }
+    // Pair up the nodes left without a partner with an unmatched node two hops away, and
+    // isolated nodes with each other, so that coarsening does not stall on star-like
+    // neighborhoods.
+    let mut isolated: Option<usize> = None;
+    for &node in &order {
+        if mate[node].is_some() {
+            continue;
+        }
+        if graph.incident(node).is_empty() {
+            match isolated {
+                Some(other) if can_merge(graph, node, other, 0, max_size) => {
+                    mate[node] = Some(other);
+                    mate[other] = Some(node);
+                    isolated = None;
+                }
+                _ => isolated = Some(node),
+            }
+            continue;
+        }
+        let two_hop = graph
+            .incident(node)
+            .iter()
+            .map(|edge| graph.other_end(*edge, node))
+            .flat_map(|middle| {
+                graph
+                    .incident(middle)
+                    .iter()
+                    .take(MAX_TWO_HOP_SCAN)
+                    .map(move |edge| graph.other_end(*edge, middle))
+            })
+            .find(|other| {
+                *other != node
+                    && mate[*other].is_none()
+                    && can_merge(graph, node, *other, 0, max_size)
+            });
+        if let Some(other) = two_hop {
+            mate[node] = Some(other);
+            mate[other] = Some(node);
+        }
+    }
+    for (node, mate) in mate.iter_mut().enumerate() {
+        mate.get_or_insert(node);
+    }
+
+    let mut coarse_of = vec![usize::MAX; n];
+    let mut next = 0;
+    for node in 0..n {
+        if coarse_of[node] == usize::MAX {
+            coarse_of[node] = next;
+            coarse_of[mate[node].unwrap()] = next;
+            next += 1;
+        }
+    }
+    coarse_of
+}
+
+/// Merge the nodes of `graph` according to `coarse_of`. Edges inside a coarse node are
+/// folded into its size; parallel edges between coarse nodes are summed, so loads on
+/// the coarse graph equal loads of the corresponding fine assignment.
+fn contract(graph: &PartitionGraph, coarse_of: &[usize]) -> PartitionGraph {
+    let coarse_num = coarse_of.iter().max().map_or(0, |max| max + 1);
+    let mut node_sizes = vec![0; coarse_num];
+    let mut node_costs = vec![0; coarse_num];
+    for node in 0..graph.node_count() {
+        node_sizes[coarse_of[node]] += graph.node_sizes[node];
+        node_costs[coarse_of[node]] += graph.node_costs[node];
+    }
+    let mut edges: Vec<GraphEdge> = vec![];
+    let mut index: HashMap<(usize, usize), usize> = HashMap::new();
+    for edge in &graph.edges {
+        let (from, to) = (coarse_of[edge.from], coarse_of[edge.to]);
+        if from == to {
+            node_sizes[from] += edge.size;
+            continue;
+        }
+        let key = (from.min(to), from.max(to));
+        match index.get(&key) {
+            Some(i) => {
+                edges[*i].weight += edge.weight;
+                edges[*i].size += edge.size;
+            }
+            None => {
+                index.insert(key, edges.len());
+                edges.push(GraphEdge {
+                    from: key.0,
+                    to: key.1,
+                    weight: edge.weight,
+                    size: edge.size,
+                });
+            }
+        }
+    }
+    let mut coarse = PartitionGraph::new(node_sizes, edges).with_costs(node_costs);
+    coarse.cost_limit = graph.cost_limit;
+    for node in 0..graph.node_count() {
+        let merged = coarse_of[node];
+        coarse.fixed[merged] = coarse.fixed[merged].or(graph.fixed[node]);
+        for other in &graph.separated[node] {
+            if !coarse.separated[merged].contains(&coarse_of[*other]) {
+                coarse.separated[merged].push(coarse_of[*other]);
+            }
+        }
+    }
+    coarse
+}
+
+/// Greedy graph growing: fill the cores one after another, each grown from the first
+/// unassigned node of `order` by absorbing the unassigned node most connected to it,
+/// up to an even share of the total load. Leftover nodes are placed greedily.
+fn grow_regions(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    order: &[usize],
+) -> Option<Vec<usize>> {
+    let n = graph.node_count();
+    let core_num = hardware.core_num.max(1) as usize;
+    let total = graph.core_loads(&vec![0; n], 1)[0];
+    let target = (total / core_num as i64 + 1).min(hardware.core_size);
+    let mut placement: Vec<Option<usize>> = vec![None; n];
+    let mut seeds = order.iter();
+    for core in 0..core_num {
+        let Some(&seed) = seeds.find(|node| placement[**node].is_none()) else {
+            break;
+        };
+        let (mut load, mut cost) = (0, 0);
+        let mut connection: HashMap<usize, i64> = HashMap::new();
+        let mut heap = BinaryHeap::from([(0, seed)]);
+        while let Some((weight, node)) = heap.pop() {
+            if placement[node].is_some() || connection.get(&node).copied().unwrap_or(0) != weight {
+                continue;
+            }
+            let added = graph.load_on(node, core, &placement);
+            if load + added > target
+                || !graph.cost_fits(node, cost)
+                || !graph.allowed(node, core, |other| placement[other])
+            {
+                continue;
+            }
+            placement[node] = Some(core);
+            load += added;
+            cost += graph.node_costs[node];
+            for &edge in graph.incident(node) {
+                let other = graph.other_end(edge, node);
+                if placement[other].is_none() {
+                    let weight = connection.entry(other).or_insert(0);
+                    *weight += graph.edges[edge].weight;
+                    heap.push((*weight, other));
+                }
+            }
+        }
+    }
+    greedy_assign(graph, order, hardware, &placement).ok()
+}
+
+/// Number of shuffled visit orders tried, besides the fixed ones, for the initial partition.
+const INITIAL_TRIES: usize = 8;
+
+/// Best refined greedy assignment of the coarsest graph over several visit orders.
+fn initial_partition(
+    graph: &PartitionGraph,
+    hardware: &HardwareConfig,
+    rng: &mut ChaCha8Rng,
+    refine_passes: usize,
+) -> Option<Vec<usize>> {
+    let open = vec![None; graph.node_count()];
+    let mut orders: Vec<Vec<usize>> =
+        [VisitOrder::Bfs, VisitOrder::Degree, VisitOrder::Declaration]
+            .into_iter()
+            .map(|order| visit_order(graph, order, &[]))
+            .collect();
+    for _ in 0..INITIAL_TRIES {
+        let mut order: Vec<usize> = (0..graph.node_count()).collect();
+        order.shuffle(rng);
+        orders.push(order);
+    }
+    let grown = [&orders[0], &orders[1]]
+        .into_iter()
+        .filter_map(|order| grow_regions(graph, hardware, order));
+    orders
+        .iter()
+        .filter_map(|order| greedy_assign(graph, order, hardware, &open).ok())
+        .chain(grown)
+        .map(|mut assignment| {
+            fm_refine(graph, &mut assignment, hardware, refine_passes);
+            assignment
+        })
+        .min_by_key(|assignment| graph.cut_weight(assignment))
+}
+
+impl MultilevelPartitioner {
+    /// Partition an index graph, returning the core of every node.
+    pub fn assign(&self, graph: &PartitionGraph, hardware: &HardwareConfig) -> Option<Vec<usize>> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let core_num = hardware.core_num.max(1) as usize;
+        let coarsest_size = (core_num * 8).max(64);
+        let max_size = hardware.core_size / 4;
+
+        let mut levels: Vec<Level> = vec![];
+        loop {
+            let current = levels.last().map_or(graph, |level| &level.graph);
+            if current.node_count() <= coarsest_size {
+                break;
+            }
+            let coarse_of = heavy_edge_matching(current, &mut rng, max_size);
+            let coarse = contract(current, &coarse_of);
+            if coarse.node_count() as f64 > current.node_count() as f64 * MIN_SHRINK {
+                break;
+            }
+            levels.push(Level {
+                graph: coarse,
+                coarse_of,
+            });
+        }
+
+        // If the coarsest graph cannot be packed, its nodes are too large: retry one level finer.
+        let mut assignment = loop {
+            let current = levels.last().map_or(graph, |level| &level.graph);
+            if let Some(assignment) =
+                initial_partition(current, hardware, &mut rng, self.refine_passes)
+            {
+                break assignment;
+            }
+            levels.pop()?;
+        };
+
+        while let Some(level) = levels.pop() {
+            let finer = levels.last().map_or(graph, |level| &level.graph);
+            fm_refine(&level.graph, &mut assignment, hardware, self.refine_passes);
+            assignment = level
+                .coarse_of
+                .iter()
+                .map(|coarse| assignment[*coarse])
+                .collect();
+            debug_assert_eq!(assignment.len(), finer.node_count());
+        }
+        fm_refine(graph, &mut assignment, hardware, self.refine_passes);
+        Some(assignment)
+    }
+}
+
+impl Partitioner for MultilevelPartitioner {
+    fn name(&self) -> &'static str {
+        "multilevel"
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, hardware.core_num as usize);
+        let assignment = self
+            .assign(&pgraph, hardware)
+            .ok_or(DataMappingError::NoSolutionFound)?;
+        Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| assignment[*g]).collect(),
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )
+    }
+}
+
+#[test]
+fn test_contract_preserves_loads() -> Result<()> {
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::fs;
+
+    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
+    let sm = semantic_analysis(parse_str(&file_context)?)?;
+    let graph = PartitionGraph::from_semantic(&sm.graphs[0])?;
+    // Merge a0 with a1 and b0 with b1.
+    let coarse_of = vec![0, 0, 1, 2, 2];
+    let coarse = contract(&graph, &coarse_of);
+    let coarse_assignment = vec![0, 1, 1];
+    let fine_assignment: Vec<usize> = coarse_of.iter().map(|c| coarse_assignment[*c]).collect();
+    assert_eq!(
+        coarse.core_loads(&coarse_assignment, 2),
+        graph.core_loads(&fine_assignment, 2)
+    );
+    assert_eq!(
+        coarse.cut_weight(&coarse_assignment),
+        graph.cut_weight(&fine_assignment)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_multilevel_large_grid() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Grid,
+        nodes: 64 * 64,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    // Room for a quarter of the grid, plus some slack, on each of the 4 cores.
+    let hardware = HardwareConfig {
+        core_size: 4 * 1100,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = MultilevelPartitioner::default().partition(&g, &hardware)?;
+    assert!(pgraph
+        .core_loads(&partition.assignment, 4)
+        .iter()
+        .all(|load| *load <= hardware.core_size));
+    // Cutting the grid into quadrants costs 128 edges; stay within a small factor of it.
+    assert!(partition.stats.cut_weight <= 3 * 128);
+    Ok(())
+}
+
+/// Cut weights and runtimes of the multilevel heuristic against the optimum of `exact` on
+/// small random graphs, within a quarter of the optimum and never better than it.
+#[cfg(test)]
+fn compare_against_exact(
+    exact: &dyn Partitioner,
+) -> Result<(std::time::Duration, std::time::Duration)> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+    use std::time::Duration;
+
+    let (mut heuristic_time, mut exact_time) = (Duration::ZERO, Duration::ZERO);
+    for (kind, seed) in [
+        (GraphKind::Grid, 0),
+        (GraphKind::Uniform, 1),
+        (GraphKind::Rmat, 2),
+    ] {
+        let config = GeneratorConfig {
+            kind,
+            nodes: 12,
+            edges: 20,
+            max_weight: 5,
+            seed,
+            ..Default::default()
+        };
+        let g = generate_graph(&config, &default_types())?;
+        let hardware = HardwareConfig {
+            core_size: 4 * 5,
+            core_num: 3,
+            rank_size: None,
+            edge_placement: Default::default(),
+        };
+        let optimal = exact.partition(&g, &hardware)?;
+        let heuristic = MultilevelPartitioner::default().partition(&g, &hardware)?;
+        let (cut, optimum) = (heuristic.stats.cut_weight, optimal.stats.cut_weight);
+        assert!(
+            optimum <= cut && cut * 4 <= optimum * 5,
+            "{:?}: cut {} in {:?} against the {} optimum {} in {:?}",
+            kind,
+            cut,
+            heuristic.stats.elapsed,
+            exact.name(),
+            optimum,
+            optimal.stats.elapsed
+        );
+        heuristic_time += heuristic.stats.elapsed;
+        exact_time += optimal.stats.elapsed;
+    }
+    Ok((heuristic_time, exact_time))
+}
+
+#[test]
+fn test_multilevel_against_milp() -> Result<()> {
+    compare_against_exact(&super::MilpPartitioner::default())?;
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "z3")]
+fn test_multilevel_against_z3() -> Result<()> {
+    // Z3 spends tens of milliseconds proving each optimum, the heuristic well under one.
+    let (heuristic, exact) = compare_against_exact(&super::Z3Partitioner::default())?;
+    assert!(
+        heuristic < exact,
+        "multilevel took {:?}, z3 {:?}",
+        heuristic,
+        exact
+    );
+    Ok(())
+}
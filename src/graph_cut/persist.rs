@@ -0,0 +1,314 @@
+use super::{DataMappingError, HardwareConfig, Partition, Replicas};
+use crate::base_type::Size;
+use crate::sem_type::{ReplicaMode, SemanticGraph};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PartitionFileError {
+    #[error(
+        "Partition file does not match the graph; missing nodes: [{}], unknown nodes: [{}]",
+        .0.join(", "),
+        .1.join(", ")
+    )]
+    NodeMismatch(Vec<String>, Vec<String>),
+    #[error("The partition file puts {1} bytes on DPU {0}, which holds {2}")]
+    CapacityExceeded(usize, i64, i64),
+    #[error("The partition file puts `{0}` on DPU {1}, but it is pinned to DPU {2}")]
+    PinViolated(String, usize, usize),
+    #[error("The partition file puts `{0}` and `{1}` on different DPUs, but they are colocated")]
+    ColocationViolated(String, String),
+    #[error("The partition file puts `{0}` and `{1}` on the same DPU, but they are separated")]
+    SeparationViolated(String, String),
+}
+
+/// Copies of a node kept in a partition file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedReplicas {
+    pub mode: ReplicaMode,
+    pub dpus: Vec<usize>,
+}
+
+/// A partition as stored on disk, keyed by node name so that it can be edited by hand and
+/// reloaded against a graph whose nodes come in another order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionFile {
+    /// `graph_hash` of the graph and hardware the partition was computed for.
+    pub graph_hash: String,
+    pub hardware: HardwareConfig,
+    pub partitioner: String,
+    pub cut_weight: i64,
+    pub assignment: BTreeMap<String, usize>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub replicas: BTreeMap<String, SavedReplicas>,
+}
+
+/// A hash of everything a partition depends on: the nodes with their sizes and costs, the
+/// edges with their weights and sizes, the placement constraints and the DPUs. Stable
+/// across runs and builds.
+pub fn graph_hash(graph: &SemanticGraph, hardware: &HardwareConfig) -> String {
+    // 64-bit FNV-1a, as `DefaultHasher` may change between Rust releases.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |text: String| {
+        for byte in text.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for node in &graph.node_insts {
+        feed(format!(
+            "node {} {} {} {}",
+            node.node_type.name,
+            node.varname,
+            node.node_type.size_byte(),
+            node.cost()
+        ));
+    }
+    for edge in &graph.edge_insts {
+        let block = &edge.edge_type.named_block;
+        feed(format!(
+            "edge {} {} {} {} {}",
+            block.name,
+            edge.from_var.varname,
+            edge.to_var.varname,
+            edge.weight,
+            block.size_byte()
+        ));
+    }
+    let constraints = &graph.constraints;
+    for (node, dpu) in &constraints.pins {
+        feed(format!("pin {} {}", node, dpu));
+    }
+    for (a, b) in &constraints.colocate {
+        feed(format!("colocate {} {}", a, b));
+    }
+    for (a, b) in &constraints.separate {
+        feed(format!("separate {} {}", a, b));
+    }
+    feed(format!(
+        "dpus {} {} {:?}",
+        hardware.core_size, hardware.core_num, hardware.rank_size
+    ));
+    feed(format!("edges {:?}", hardware.edge_placement));
+    format!("{:016x}", hash)
+}
+
+impl PartitionFile {
+    pub fn new(graph: &SemanticGraph, partition: &Partition, hardware: &HardwareConfig) -> Self {
+        PartitionFile {
+            graph_hash: graph_hash(graph, hardware),
+            hardware: hardware.clone(),
+            partitioner: partition.stats.partitioner.to_string(),
+            cut_weight: partition.stats.cut_weight,
+            assignment: partition
+                .nodes
+                .iter()
+                .cloned()
+                .zip(partition.assignment.iter().copied())
+                .collect(),
+            replicas: partition
+                .replicas
+                .iter()
+                .map(|replicas| {
+                    (
+                        partition.nodes[replicas.node].clone(),
+                        SavedReplicas {
+                            mode: replicas.mode,
+                            dpus: replicas.cores.clone(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(file_name: &str) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(file_name)?)?)
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<()> {
+        fs::write(file_name, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    /// Whether the partition was computed for this very graph and hardware.
+    pub fn matches(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> bool {
+        self.graph_hash == graph_hash(graph, hardware)
+    }
+
+    /// The nodes of `graph` absent from the file, and the nodes of the file absent from
+    /// `graph`, each in name order.
+    pub fn mismatch(&self, graph: &SemanticGraph) -> (Vec<String>, Vec<String>) {
+        let known: HashSet<&str> = graph
+            .node_insts
+            .iter()
+            .map(|node| node.varname.as_str())
+            .collect();
+        let mut missing: Vec<String> = graph
+            .node_insts
+            .iter()
+            .filter(|node| !self.assignment.contains_key(&node.varname))
+            .map(|node| node.varname.clone())
+            .collect();
+        missing.sort();
+        let unknown = self
+            .assignment
+            .keys()
+            .chain(self.replicas.keys())
+            .filter(|name| !known.contains(name.as_str()))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        (missing, unknown)
+    }
+
+    /// The stored placement of every node of `graph`, which must be the exact node set of
+    /// the file. The placement must fit `hardware` and keep the placement constraints of
+    /// `graph`, as the file may have been edited by hand. The cut weight is recomputed on
+    /// `graph`.
+    pub fn to_partition(
+        &self,
+        graph: &SemanticGraph,
+        hardware: &HardwareConfig,
+    ) -> Result<Partition> {
+        let (missing, unknown) = self.mismatch(graph);
+        if !missing.is_empty() || !unknown.is_empty() {
+            return Err(PartitionFileError::NodeMismatch(missing, unknown).into());
+        }
+        let assignment = graph
+            .node_insts
+            .iter()
+            .map(|node| self.assignment[&node.varname])
+            .collect();
+        let mut partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            assignment,
+            hardware.core_num as usize,
+            "file",
+            Default::default(),
+        )?;
+        for (node, inst) in graph.node_insts.iter().enumerate() {
+            if let Some(saved) = self.replicas.get(&inst.varname) {
+                if let Some(core) = saved.dpus.iter().find(|core| **core >= partition.core_num) {
+                    return Err(DataMappingError::CoreOutOfRange(
+                        inst.varname.clone(),
+                        *core,
+                        partition.core_num,
+                    )
+                    .into());
+                }
+                partition.replicas.push(Replicas {
+                    node,
+                    mode: saved.mode,
+                    cores: saved.dpus.clone(),
+                });
+            }
+        }
+        let core_bytes = partition.core_bytes(graph, hardware.edge_placement)?;
+        if let Some((core, bytes)) = core_bytes
+            .into_iter()
+            .enumerate()
+            .find(|(_, bytes)| *bytes > hardware.core_size)
+        {
+            return Err(
+                PartitionFileError::CapacityExceeded(core, bytes, hardware.core_size).into(),
+            );
+        }
+        let dpu_of = |name: &String| self.assignment.get(name).copied();
+        let constraints = &graph.constraints;
+        for (node, pin) in &constraints.pins {
+            match dpu_of(node) {
+                Some(dpu) if dpu != *pin => {
+                    return Err(PartitionFileError::PinViolated(node.clone(), dpu, *pin).into())
+                }
+                _ => {}
+            }
+        }
+        for (a, b) in &constraints.colocate {
+            if dpu_of(a) != dpu_of(b) {
+                return Err(PartitionFileError::ColocationViolated(a.clone(), b.clone()).into());
+            }
+        }
+        for (a, b) in &constraints.separate {
+            if dpu_of(a) == dpu_of(b) {
+                return Err(PartitionFileError::SeparationViolated(a.clone(), b.clone()).into());
+            }
+        }
+        Ok(partition)
+    }
+}
+
+#[test]
+fn test_partition_file_round_trip() -> Result<()> {
+    use super::{MilpPartitioner, Partitioner};
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 6,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 12,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = MilpPartitioner::default().partition(&g, &hardware)?;
+    let file_name = std::env::temp_dir().join(format!(
+        "dataspatialpim_round_trip_{}.json",
+        std::process::id()
+    ));
+    let file_name = file_name.to_str().unwrap();
+    PartitionFile::new(&g, &partition, &hardware).save(file_name)?;
+    let file = PartitionFile::load(file_name)?;
+    fs::remove_file(file_name)?;
+    assert!(file.matches(&g, &hardware));
+    assert!(!file.matches(
+        &g,
+        &HardwareConfig {
+            core_num: 3,
+            ..hardware.clone()
+        }
+    ));
+    let loaded = file.to_partition(&g, &hardware)?;
+    assert_eq!(loaded.assignment, partition.assignment);
+    assert_eq!(loaded.stats.cut_weight, partition.stats.cut_weight);
+
+    // A hand-edited file naming a node the graph does not have, and missing another.
+    let mut edited = file.clone();
+    let core = edited.assignment.remove("n5").unwrap();
+    edited.assignment.insert(String::from("n9"), core);
+    assert_eq!(
+        edited.mismatch(&g),
+        (vec![String::from("n5")], vec![String::from("n9")])
+    );
+    assert!(edited.to_partition(&g, &hardware).is_err());
+
+    // A hand-edited file overfilling a DPU.
+    let mut crowded = file.clone();
+    crowded.assignment.values_mut().for_each(|core| *core = 0);
+    let error = crowded.to_partition(&g, &hardware).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(PartitionFileError::CapacityExceeded(0, 24, 12))
+    ));
+
+    // Pinning a node elsewhere changes the hash and rejects the stored placement.
+    let other = 1 - partition.assignment[0];
+    let mut pinned = g.clone();
+    pinned.constraints.pins.push((String::from("n0"), other));
+    assert!(!file.matches(&pinned, &hardware));
+    let error = file.to_partition(&pinned, &hardware).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(PartitionFileError::PinViolated(_, _, pin)) if *pin == other
+    ));
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use super::DataMappingError;
+use crate::sem_type::{SemanticEdgeInst, SemanticGraph, SemanticNodeInst, SemanticWalkerInst};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Line {0} of the profile is malformed: `{1}`")]
+    MalformedLine(usize, String),
+    #[error("The profile names edge {0}, but the graph has {1} edges")]
+    EdgeOutOfRange(usize, usize),
+    #[error("The profile reaches `{0}` over edge {1}, which does not touch it")]
+    EdgeMismatch(String, usize),
+}
+
+/// One step of a walker, as recorded by the generated code or a simulator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub timestamp: u64,
+    /// DPU the walker runs on after the step.
+    pub dpu: usize,
+    /// Node the walker arrives on.
+    pub node: String,
+    /// Index of the edge taken into `SemanticGraph::edge_insts`, as in the generated
+    /// `edge_dpus` table, or `None` where the walker starts.
+    pub edge: Option<usize>,
+}
+
+/// A hop as a line of a profile.
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} ", self.timestamp, self.dpu, self.node)?;
+        match self.edge {
+            Some(edge) => write!(f, "{}", edge),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// Reads a whitespace separated profile, one `timestamp dpu node edge` per line, with `-`
+/// as the edge of a walker's start. Blank lines and lines starting with `#` are skipped.
+pub struct HopReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line_no: usize,
+}
+
+impl<R: BufRead> HopReader<R> {
+    pub fn new(reader: R) -> HopReader<R> {
+        HopReader {
+            lines: reader.lines(),
+            line_no: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for HopReader<R> {
+    type Item = Result<Hop>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line_no += 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hop = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [timestamp, dpu, node, edge] => (|| {
+                    Some(Hop {
+                        timestamp: timestamp.parse().ok()?,
+                        dpu: dpu.parse().ok()?,
+                        node: node.to_string(),
+                        edge: match edge {
+                            "-" => None,
+                            edge => Some(edge.parse().ok()?),
+                        },
+                    })
+                })(),
+                _ => None,
+            };
+            return Some(hop.ok_or_else(|| {
+                ProfileError::MalformedLine(self.line_no, line.to_string()).into()
+            }));
+        }
+    }
+}
+
+/// Walker traffic of one or more runs, aggregated over the nodes and edges of a graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub hops: usize,
+    /// Traversals of each edge, in the order of `SemanticGraph::edge_insts`.
+    pub edge_counts: Vec<i64>,
+    /// Walker arrivals on each node, starts included, in the order of
+    /// `SemanticGraph::node_insts`.
+    pub node_heat: Vec<i64>,
+    /// Hops recorded on each DPU.
+    pub dpu_hops: BTreeMap<usize, usize>,
+    /// Earliest and latest timestamps.
+    pub span: Option<(u64, u64)>,
+}
+
+impl Profile {
+    pub fn new(graph: &SemanticGraph) -> Profile {
+        Profile {
+            hops: 0,
+            edge_counts: vec![0; graph.edge_insts.len()],
+            node_heat: vec![0; graph.node_insts.len()],
+            dpu_hops: BTreeMap::new(),
+            span: None,
+        }
+    }
+
+    /// Aggregate the profiles in `file_names` against `graph`.
+    pub fn load(graph: &SemanticGraph, file_names: &[String]) -> Result<Profile> {
+        let mut profile = Profile::new(graph);
+        for file_name in file_names {
+            let reader = BufReader::new(fs::File::open(file_name)?);
+            profile.add(graph, HopReader::new(reader))?;
+        }
+        Ok(profile)
+    }
+
+    pub fn add(
+        &mut self,
+        graph: &SemanticGraph,
+        hops: impl IntoIterator<Item = Result<Hop>>,
+    ) -> Result<()> {
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        for hop in hops {
+            let hop = hop?;
+            let node = *index
+                .get(hop.node.as_str())
+                .ok_or(DataMappingError::NodeNotFound(hop.node.clone()))?;
+            if let Some(edge) = hop.edge {
+                let inst = graph
+                    .edge_insts
+                    .get(edge)
+                    .ok_or(ProfileError::EdgeOutOfRange(edge, graph.edge_insts.len()))?;
+                if inst.from_var.varname != hop.node && inst.to_var.varname != hop.node {
+                    return Err(ProfileError::EdgeMismatch(hop.node, edge).into());
+                }
+                self.edge_counts[edge] += 1;
+            }
+            self.node_heat[node] += 1;
+            *self.dpu_hops.entry(hop.dpu).or_insert(0) += 1;
+            self.span = Some(match self.span {
+                Some((first, last)) => (first.min(hop.timestamp), last.max(hop.timestamp)),
+                None => (hop.timestamp, hop.timestamp),
+            });
+            self.hops += 1;
+        }
+        Ok(())
+    }
+
+    /// A copy of `graph` whose edge weights are the traversal counts and whose nodes cost
+    /// their heat times the cost of a visit, so that the next partition follows the
+    /// observed traffic. A node no walker arrived on keeps no heat and costs one visit,
+    /// rather than nothing.
+    pub fn weighted_graph(&self, graph: &SemanticGraph) -> SemanticGraph {
+        let node_insts: Vec<Rc<SemanticNodeInst>> = graph
+            .node_insts
+            .iter()
+            .zip(&self.node_heat)
+            .map(|(node, heat)| {
+                Rc::new(SemanticNodeInst {
+                    varname: node.varname.clone(),
+                    node_type: node.node_type.clone(),
+                    replicate: node.replicate,
+                    cost: node.cost,
+                    heat: (*heat > 0).then_some(*heat),
+                })
+            })
+            .collect();
+        let by_name: HashMap<&str, &Rc<SemanticNodeInst>> = node_insts
+            .iter()
+            .map(|node| (node.varname.as_str(), node))
+            .collect();
+        let edge_insts = graph
+            .edge_insts
+            .iter()
+            .zip(&self.edge_counts)
+            .map(|(edge, count)| {
+                Rc::new(SemanticEdgeInst {
+                    edge_type: edge.edge_type.clone(),
+                    from_var: by_name[edge.from_var.varname.as_str()].clone(),
+                    to_var: by_name[edge.to_var.varname.as_str()].clone(),
+                    weight: *count,
+                })
+            })
+            .collect();
+        let walker_insts = graph
+            .walker_insts
+            .iter()
+            .map(|walker| {
+                Rc::new(SemanticWalkerInst {
+                    walker_type: walker.walker_type.clone(),
+                    start_node: by_name[walker.start_node.varname.as_str()].clone(),
+                })
+            })
+            .collect();
+        SemanticGraph {
+            node_insts,
+            edge_insts,
+            walker_insts,
+            constraints: graph.constraints.clone(),
+        }
+    }
+}
+
+/// A one-line summary of the traffic.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Profile of {} hops on {} DPUs, {} of {} edges traversed",
+            self.hops,
+            self.dpu_hops.len(),
+            self.edge_counts.iter().filter(|count| **count > 0).count(),
+            self.edge_counts.len()
+        )?;
+        if let Some((first, last)) = self.span {
+            write!(f, ", timestamps {} to {}", first, last)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_profile() -> Result<()> {
+    use super::graph::PartitionGraph;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    let source = "
+        node Alice { n1: int8; };
+        edge Alice Alice Link {};
+        walker Alice Visitor;
+        graph {
+            @cost(3) node Alice a0;
+            node Alice a1, a2;
+            edge Link a0 a1 1;
+            edge Link a1 a2 1;
+            edge Link a2 a0 1;
+            walker Visitor on a0;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let trace = "# timestamp dpu node edge\n0 0 a0 -\n5 0 a1 0\n9 1 a2 1\n12 1 a0 2\n20 0 a1 0\n";
+    let mut profile = Profile::new(&g);
+    profile.add(&g, HopReader::new(trace.as_bytes()))?;
+    assert_eq!(profile.hops, 5);
+    assert_eq!(profile.edge_counts, vec![2, 1, 1]);
+    assert_eq!(profile.node_heat, vec![2, 2, 1]);
+    assert_eq!(profile.dpu_hops, BTreeMap::from([(0, 3), (1, 2)]));
+    assert_eq!(profile.span, Some((0, 20)));
+
+    let weighted = profile.weighted_graph(&g);
+    let weights: Vec<i64> = weighted.edge_insts.iter().map(|edge| edge.weight).collect();
+    assert_eq!(weights, vec![2, 1, 1]);
+    // The heat multiplies the cost of a visit, declared or not.
+    let costs: Vec<i64> = weighted.node_insts.iter().map(|node| node.cost()).collect();
+    assert_eq!(costs, vec![3 * 2, 2, 1]);
+    assert_eq!(weighted.walker_insts[0].start_node.heat, Some(2));
+
+    // Nodes the walkers never reached still cost a visit, so they are not packed for free.
+    let mut cold = Profile::new(&g);
+    cold.add(&g, HopReader::new("0 0 a0 -\n".as_bytes()))?;
+    let weighted = cold.weighted_graph(&g);
+    let heats: Vec<Option<i64>> = weighted.node_insts.iter().map(|node| node.heat).collect();
+    assert_eq!(heats, vec![Some(1), None, None]);
+    let costs: Vec<i64> = weighted.node_insts.iter().map(|node| node.cost()).collect();
+    assert_eq!(costs, vec![3, 1, 1]);
+
+    // A cost too large to count is rejected rather than wrapped around.
+    let burning = Rc::new(SemanticNodeInst {
+        varname: String::from("a0"),
+        node_type: g.node_insts[0].node_type.clone(),
+        replicate: None,
+        cost: Some(3),
+        heat: Some(i64::MAX / 2),
+    });
+    assert_eq!(burning.cost(), i64::MAX);
+    let error = PartitionGraph::from_parts(&[burning], &[]).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::CostOverflow(_))
+    ));
+
+    let line = Hop {
+        timestamp: 3,
+        dpu: 1,
+        node: "a2".to_string(),
+        edge: None,
+    }
+    .to_string();
+    assert_eq!(line, "3 1 a2 -");
+    assert!(HopReader::new("1 0 a0\n".as_bytes())
+        .next()
+        .unwrap()
+        .is_err());
+    assert!(profile
+        .add(&g, HopReader::new("1 0 a0 1\n".as_bytes()))
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,518 @@
+use super::graph::PartitionGraph;
+use super::{Balance, DataMappingError, HardwareConfig, Partition};
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+/// Stop a pass after this many moves that do not beat the best cut seen so far.
+const MAX_FRUITLESS_MOVES: usize = 64;
+
+/// Nodes on the boundary of the target core tried as swap partners of each node.
+const MAX_SWAP_CANDIDATES: usize = 64;
+
+/// Per-core edge weight accumulator reused across nodes to avoid allocating.
+struct Affinity {
+    weights: Vec<i64>,
+    touched: Vec<usize>,
+}
+
+impl Affinity {
+    fn new(core_num: usize) -> Affinity {
+        Affinity {
+            weights: vec![0; core_num],
+            touched: vec![],
+        }
+    }
+
+    /// Best move of `node` away from its core, as (cut reduction, target core), among the
+    /// cores holding one of its neighbors.
+    fn best_move(
+        &mut self,
+        graph: &PartitionGraph,
+        node: usize,
+        assignment: &[usize],
+    ) -> Option<(i64, usize)> {
+        for &edge in graph.incident(node) {
+            let other = graph.other_end(edge, node);
+            if other != node {
+                let core = assignment[other];
+                if self.weights[core] == 0 && !self.touched.contains(&core) {
+                    self.touched.push(core);
+                }
+                self.weights[core] += graph.edges[edge].weight;
+            }
+        }
+        let own = assignment[node];
+        let internal = self.weights[own];
+        let mut best: Option<(i64, usize)> = None;
+        for &core in &self.touched {
+            let gain = self.weights[core] - internal;
+            if core != own && best.is_none_or(|(g, c)| gain > g || (gain == g && core < c)) {
+                best = Some((gain, core));
+            }
+        }
+        for core in self.touched.drain(..) {
+            self.weights[core] = 0;
+        }
+        best
+    }
+}
+
+/// Bytes `node` occupies on `core`, ignoring where `node` itself currently is.
+pub fn load_of(graph: &PartitionGraph, node: usize, core: usize, assignment: &[usize]) -> i64 {
+    let mut load = graph.node_sizes[node];
+    for &edge in graph.incident(node) {
+        let other = graph.other_end(edge, node);
+        if other == node || assignment[other] != core {
+            load += graph.edges[edge].size;
+        }
+    }
+    load
+}
+
+/// One Fiduccia–Mattheyses pass: repeatedly move the unlocked boundary node with the
+/// highest gain to the core it is most connected to, as long as the target has room,
+/// stays within the cost limit and is allowed for the node, then roll back to the best
+/// cut seen. Returns the cut reduction.
+pub fn fm_pass(graph: &PartitionGraph, assignment: &mut [usize], hardware: &HardwareConfig) -> i64 {
+    let mut loads = graph.core_loads(assignment, hardware.core_num as usize);
+    let mut costs = graph.core_costs(assignment, hardware.core_num as usize);
+    let mut affinity = Affinity::new(hardware.core_num as usize);
+    let mut locked = vec![false; graph.node_count()];
+    let mut heap = BinaryHeap::new();
+    for node in 0..graph.node_count() {
+        if let Some((gain, core)) = affinity.best_move(graph, node, assignment) {
+            heap.push((gain, node, core));
+        }
+    }
+
+    let mut moves: Vec<(usize, usize)> = vec![];
+    let (mut total, mut best_total, mut best_len) = (0, 0, 0);
+    while let Some((gain, node, core)) = heap.pop() {
+        if locked[node] {
+            continue;
+        }
+        // Gains go stale as neighbors move; requeue with the current value.
+        match affinity.best_move(graph, node, assignment) {
+            Some(current) if current == (gain, core) => {}
+            Some((gain, core)) => {
+                heap.push((gain, node, core));
+                continue;
+            }
+            None => continue,
+        }
+        let from = assignment[node];
+        let added = load_of(graph, node, core, assignment);
+        if loads[core] + added > hardware.core_size
+            || !graph.cost_fits(node, costs[core])
+            || !graph.allowed(node, core, |other| Some(assignment[other]))
+        {
+            continue;
+        }
+        loads[from] -= load_of(graph, node, from, assignment);
+        loads[core] += added;
+        costs[from] -= graph.node_costs[node];
+        costs[core] += graph.node_costs[node];
+        assignment[node] = core;
+        locked[node] = true;
+        moves.push((node, from));
+        total += gain;
+        if total > best_total {
+            best_total = total;
+            best_len = moves.len();
+        } else if moves.len() - best_len > MAX_FRUITLESS_MOVES {
+            break;
+        }
+        for &edge in graph.incident(node) {
+            let other = graph.other_end(edge, node);
+            if !locked[other] {
+                if let Some((gain, core)) = affinity.best_move(graph, other, assignment) {
+                    heap.push((gain, other, core));
+                }
+            }
+        }
+    }
+    for (node, from) in moves.drain(best_len..).rev() {
+        assignment[node] = from;
+    }
+    best_total
+}
+
+/// Run FM passes until one stops improving the cut or `max_passes` is reached.
+/// Returns the total cut reduction.
+pub fn fm_refine(
+    graph: &PartitionGraph,
+    assignment: &mut [usize],
+    hardware: &HardwareConfig,
+    max_passes: usize,
+) -> i64 {
+    let mut improvement = 0;
+    for _ in 0..max_passes {
+        let gain = fm_pass(graph, assignment, hardware);
+        if gain <= 0 {
+            break;
+        }
+        improvement += gain;
+    }
+    improvement
+}
+
+/// Cut reduction of moving `node` to `core`.
+fn move_gain(graph: &PartitionGraph, node: usize, core: usize, assignment: &[usize]) -> i64 {
+    let own = assignment[node];
+    let mut gain = 0;
+    for &edge in graph.incident(node) {
+        let other = graph.other_end(edge, node);
+        if other == node {
+            continue;
+        }
+        if assignment[other] == core {
+            gain += graph.edges[edge].weight;
+        } else if assignment[other] == own {
+            gain -= graph.edges[edge].weight;
+        }
+    }
+    gain
+}
+
+/// Move `node` to `core`, keeping `loads` up to date.
+fn move_node(
+    graph: &PartitionGraph,
+    node: usize,
+    core: usize,
+    assignment: &mut [usize],
+    loads: &mut [i64],
+) {
+    let from = assignment[node];
+    loads[from] -= load_of(graph, node, from, assignment);
+    loads[core] += load_of(graph, node, core, assignment);
+    assignment[node] = core;
+}
+
+/// One Kernighan–Lin pass: swap pairs of nodes on different cores when the exchange lowers
+/// the cut, which makes the moves FM cannot make alone because the target core is full.
+/// Each node is tried against the nodes on the core it is most connected to that neighbor
+/// it or lie on the boundary with its own core. Swaps respect the capacity, the cost limit
+/// and the placement constraints. Returns the cut reduction.
+pub fn swap_pass(
+    graph: &PartitionGraph,
+    assignment: &mut [usize],
+    hardware: &HardwareConfig,
+) -> i64 {
+    let core_num = hardware.core_num as usize;
+    let mut loads = graph.core_loads(assignment, core_num);
+    let mut costs = graph.core_costs(assignment, core_num);
+    let mut affinity = Affinity::new(core_num);
+    // Nodes with a neighbor on another core, by core, as of the start of the pass.
+    let mut boundary = vec![vec![]; core_num];
+    for node in 0..graph.node_count() {
+        let core = assignment[node];
+        if graph
+            .incident(node)
+            .iter()
+            .any(|edge| assignment[graph.other_end(*edge, node)] != core)
+        {
+            boundary[core].push(node);
+        }
+    }
+    let mut improvement = 0;
+    for a in 0..graph.node_count() {
+        let Some((gain_a, q)) = affinity.best_move(graph, a, assignment) else {
+            continue;
+        };
+        let p = assignment[a];
+        let neighbors = graph
+            .incident(a)
+            .iter()
+            .map(|edge| graph.other_end(*edge, a));
+        let facing = boundary[q].iter().copied().filter(|b| {
+            graph
+                .incident(*b)
+                .iter()
+                .any(|edge| assignment[graph.other_end(*edge, *b)] == p)
+        });
+        let mut candidates: Vec<(i64, usize)> = vec![];
+        for b in neighbors.chain(facing.take(MAX_SWAP_CANDIDATES)) {
+            if assignment[b] != q || candidates.iter().any(|(_, c)| *c == b) {
+                continue;
+            }
+            // The edges between `a` and `b` stay cut after the swap.
+            let between: i64 = graph
+                .incident(a)
+                .iter()
+                .filter(|edge| graph.other_end(**edge, a) == b)
+                .map(|edge| graph.edges[*edge].weight)
+                .sum();
+            let gain = gain_a + move_gain(graph, b, p, assignment) - 2 * between;
+            if gain > 0 {
+                candidates.push((gain, b));
+            }
+        }
+        candidates.sort_by_key(|(gain, b)| (std::cmp::Reverse(*gain), *b));
+        for (gain, b) in candidates {
+            let balanced = graph.cost_limit.is_none_or(|limit| {
+                costs[p] - graph.node_costs[a] + graph.node_costs[b] <= limit
+                    && costs[q] - graph.node_costs[b] + graph.node_costs[a] <= limit
+            });
+            if !balanced {
+                continue;
+            }
+            move_node(graph, a, q, assignment, &mut loads);
+            move_node(graph, b, p, assignment, &mut loads);
+            if loads[p] <= hardware.core_size
+                && loads[q] <= hardware.core_size
+                && graph.allowed(a, q, |other| Some(assignment[other]))
+                && graph.allowed(b, p, |other| Some(assignment[other]))
+            {
+                costs[p] += graph.node_costs[b] - graph.node_costs[a];
+                costs[q] += graph.node_costs[a] - graph.node_costs[b];
+                improvement += gain;
+                break;
+            }
+            move_node(graph, b, q, assignment, &mut loads);
+            move_node(graph, a, p, assignment, &mut loads);
+        }
+    }
+    improvement
+}
+
+/// Gains of one round of `refine_partition`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefineRound {
+    /// Cut reduction of the Fiduccia–Mattheyses moves.
+    pub moved: i64,
+    /// Cut reduction of the Kernighan–Lin swaps.
+    pub swapped: i64,
+    /// Cut weight after the round.
+    pub cut_weight: i64,
+}
+
+/// Improve the cut of any partition of `graph`, computed or loaded, with rounds of
+/// Fiduccia–Mattheyses moves then Kernighan–Lin swaps, within the capacity, the `balance`
+/// and the placement constraints. Stops when a round gains nothing or after `max_rounds`,
+/// and returns every round.
+pub fn refine_partition(
+    graph: &SemanticGraph,
+    partition: &mut Partition,
+    hardware: &HardwareConfig,
+    balance: &Balance,
+    max_rounds: usize,
+) -> Result<Vec<RefineRound>> {
+    let start = Instant::now();
+    if !partition.replicas.is_empty() {
+        return Err(DataMappingError::RefineReplicated.into());
+    }
+    let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+    balance.apply(&mut pgraph, hardware.core_num as usize);
+    let mut assignment = vec![0; pgraph.node_count()];
+    for (node, core) in partition.assignment.iter().enumerate() {
+        assignment[group[node]] = *core;
+    }
+    let mut rounds = vec![];
+    for _ in 0..max_rounds {
+        let moved = fm_pass(&pgraph, &mut assignment, hardware);
+        let swapped = swap_pass(&pgraph, &mut assignment, hardware);
+        if moved + swapped <= 0 {
+            break;
+        }
+        rounds.push(RefineRound {
+            moved,
+            swapped,
+            cut_weight: pgraph.cut_weight(&assignment),
+        });
+    }
+    partition.assignment = group.iter().map(|g| assignment[*g]).collect();
+    partition.stats.cut_weight = pgraph.cut_weight(&assignment);
+    partition.stats.elapsed += start.elapsed();
+    Ok(rounds)
+}
+
+/// Cost of the edges of `node` if it sat on `core`: nothing for a neighbor on the same
+/// core, the edge weight within a rank, and `inter_rank_factor` times the weight across.
+fn rank_cost(
+    graph: &PartitionGraph,
+    node: usize,
+    core: usize,
+    assignment: &[usize],
+    hardware: &HardwareConfig,
+    inter_rank_factor: i64,
+) -> i64 {
+    let mut cost = 0;
+    for &edge in graph.incident(node) {
+        let other = graph.other_end(edge, node);
+        let other_core = assignment[other];
+        if other == node || other_core == core {
+            continue;
+        }
+        cost += if hardware.rank_of(other_core) == hardware.rank_of(core) {
+            graph.edges[edge].weight
+        } else {
+            inter_rank_factor * graph.edges[edge].weight
+        };
+    }
+    cost
+}
+
+/// Move nodes to the core of a neighbor while that lowers the rank-weighted cut, where an
+/// edge between ranks counts `inter_rank_factor` times one within a rank. Moves respect
+/// the capacity, the cost limit and the placement constraints, and stop after
+/// `max_passes` sweeps over the nodes. Returns the reduction of the rank-weighted cut.
+pub fn rank_refine(
+    graph: &PartitionGraph,
+    assignment: &mut [usize],
+    hardware: &HardwareConfig,
+    inter_rank_factor: i64,
+    max_passes: usize,
+) -> i64 {
+    let mut loads = graph.core_loads(assignment, hardware.core_num as usize);
+    let mut costs = graph.core_costs(assignment, hardware.core_num as usize);
+    let mut improvement = 0;
+    for _ in 0..max_passes {
+        let mut moved = false;
+        for node in 0..graph.node_count() {
+            let from = assignment[node];
+            let current = rank_cost(graph, node, from, assignment, hardware, inter_rank_factor);
+            let mut best: Option<(i64, usize)> = None;
+            for &edge in graph.incident(node) {
+                let core = assignment[graph.other_end(edge, node)];
+                if core == from || best.is_some_and(|(_, c)| c == core) {
+                    continue;
+                }
+                let gain =
+                    current - rank_cost(graph, node, core, assignment, hardware, inter_rank_factor);
+                if gain > 0 && best.is_none_or(|(g, c)| gain > g || (gain == g && core < c)) {
+                    let added = load_of(graph, node, core, assignment);
+                    if loads[core] + added <= hardware.core_size
+                        && graph.cost_fits(node, costs[core])
+                        && graph.allowed(node, core, |other| Some(assignment[other]))
+                    {
+                        best = Some((gain, core));
+                    }
+                }
+            }
+            if let Some((gain, core)) = best {
+                loads[from] -= load_of(graph, node, from, assignment);
+                loads[core] += load_of(graph, node, core, assignment);
+                costs[from] -= graph.node_costs[node];
+                costs[core] += graph.node_costs[node];
+                assignment[node] = core;
+                improvement += gain;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    improvement
+}
+
+#[test]
+fn test_fm_pass_improves_cut() {
+    use super::graph::GraphEdge;
+
+    // Two triangles joined by a single light edge, with one node of each placed wrongly.
+    let edge = |from, to, weight| GraphEdge {
+        from,
+        to,
+        weight,
+        size: 0,
+    };
+    let graph = PartitionGraph::new(
+        vec![1; 6],
+        vec![
+            edge(0, 1, 5),
+            edge(1, 2, 5),
+            edge(0, 2, 5),
+            edge(3, 4, 5),
+            edge(4, 5, 5),
+            edge(3, 5, 5),
+            edge(2, 3, 1),
+        ],
+    );
+    let hardware = HardwareConfig {
+        core_size: 4,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let mut assignment = vec![0, 0, 1, 1, 1, 0];
+    let before = graph.cut_weight(&assignment);
+    let gain = fm_refine(&graph, &mut assignment, &hardware, 10);
+    assert_eq!(before - gain, graph.cut_weight(&assignment));
+    assert_eq!(graph.cut_weight(&assignment), 1);
+    assert!(graph
+        .core_loads(&assignment, 2)
+        .iter()
+        .all(|load| *load <= 4));
+}
+
+#[test]
+fn test_swap_pass_trades_full_cores() {
+    use super::graph::GraphEdge;
+
+    // Two pairs placed crosswise on full cores: no single move fits, a swap does.
+    let edge = |from, to, weight| GraphEdge {
+        from,
+        to,
+        weight,
+        size: 0,
+    };
+    let graph = PartitionGraph::new(vec![1; 4], vec![edge(0, 1, 5), edge(2, 3, 5)]);
+    let hardware = HardwareConfig {
+        core_size: 2,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let mut assignment = vec![0, 1, 1, 0];
+    assert_eq!(fm_refine(&graph, &mut assignment, &hardware, 10), 0);
+    assert_eq!(swap_pass(&graph, &mut assignment, &hardware), 10);
+    assert_eq!(graph.cut_weight(&assignment), 0);
+}
+
+#[test]
+fn test_refine_partition() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Grid,
+        nodes: 64,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 20,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    // Round robin cuts nearly every edge of the grid.
+    let mut partition = Partition::new(
+        &g.node_insts,
+        &g.edge_insts,
+        (0..64).map(|node| node % 4).collect(),
+        4,
+        "round-robin",
+        Default::default(),
+    )?;
+    let before = partition.stats.cut_weight;
+    let rounds = refine_partition(&g, &mut partition, &hardware, &Balance::default(), 10)?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    assert!(!rounds.is_empty());
+    assert_eq!(
+        rounds.last().unwrap().cut_weight,
+        partition.stats.cut_weight
+    );
+    assert!(partition.stats.cut_weight < before);
+    assert_eq!(
+        partition.stats.cut_weight,
+        pgraph.cut_weight(&partition.assignment)
+    );
+    assert!(pgraph
+        .core_loads(&partition.assignment, 4)
+        .iter()
+        .all(|load| *load <= hardware.core_size));
+    Ok(())
+}
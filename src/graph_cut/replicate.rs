@@ -0,0 +1,257 @@
+use super::{DataMappingError, HardwareConfig, Partition, Partitioner, Replicas};
+use crate::base_type::Size;
+use crate::sem_type::{ReplicaMode, SemanticEdgeInst, SemanticGraph};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Copies hub nodes onto the DPUs of their neighbors, so that their edges are never cut,
+/// and partitions the rest of the graph with another partitioner. Nodes are replicated
+/// when annotated with `@replicate`, or when they have at least `degree_threshold` edges.
+///
+/// Each copy takes the room of the node on its DPU. As the copies are placed after the
+/// partition, every DPU keeps room for one copy of every replicated node. An edge of a
+/// replicated node is stored with its other endpoint, next to a copy. Copies would escape
+/// pins and separations, so pinned or separated nodes are never replicated by degree, and
+/// annotating one with `@replicate` is an error.
+pub struct ReplicatingPartitioner {
+    pub inner: Box<dyn Partitioner>,
+    pub degree_threshold: Option<usize>,
+    /// Consistency of the copies of nodes replicated by degree.
+    pub mode: ReplicaMode,
+}
+
+impl ReplicatingPartitioner {
+    /// How each node of `graph` is replicated, if at all.
+    fn replicated(&self, graph: &SemanticGraph) -> Result<Vec<Option<ReplicaMode>>> {
+        let mut degrees: HashMap<&str, usize> = HashMap::new();
+        for edge in &graph.edge_insts {
+            *degrees.entry(edge.from_var.varname.as_str()).or_insert(0) += 1;
+            if edge.to_var.varname != edge.from_var.varname {
+                *degrees.entry(edge.to_var.varname.as_str()).or_insert(0) += 1;
+            }
+        }
+        let constraints = &graph.constraints;
+        let constrained = |name: &str| {
+            constraints.pins.iter().any(|(node, _)| node == name)
+                || constraints
+                    .separate
+                    .iter()
+                    .any(|(a, b)| a == name || b == name)
+        };
+        graph
+            .node_insts
+            .iter()
+            .map(|node| {
+                if constrained(&node.varname) {
+                    return match node.replicate {
+                        Some(_) => {
+                            Err(DataMappingError::ConstrainedReplica(node.varname.clone()).into())
+                        }
+                        None => Ok(None),
+                    };
+                }
+                let degree = degrees.get(node.varname.as_str()).copied().unwrap_or(0);
+                Ok(node.replicate.or(self
+                    .degree_threshold
+                    .filter(|threshold| degree >= *threshold)
+                    .map(|_| self.mode)))
+            })
+            .collect()
+    }
+}
+
+impl Partitioner for ReplicatingPartitioner {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let replicated = self.replicated(graph)?;
+        if replicated.iter().all(Option::is_none) {
+            return self.inner.partition(graph, hardware);
+        }
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+
+        // Edges of a replicated node turn into loops on the other endpoint, which charge
+        // their bytes to its DPU but can never be cut.
+        let edge_insts: Vec<Rc<SemanticEdgeInst>> = graph
+            .edge_insts
+            .iter()
+            .map(|edge| -> Result<Rc<SemanticEdgeInst>> {
+                let (from, to) = (find(&edge.from_var.varname)?, find(&edge.to_var.varname)?);
+                if replicated[from].is_none() && replicated[to].is_none() {
+                    return Ok(edge.clone());
+                }
+                let owner = if replicated[from].is_some() {
+                    &edge.to_var
+                } else {
+                    &edge.from_var
+                };
+                Ok(Rc::new(SemanticEdgeInst {
+                    edge_type: edge.edge_type.clone(),
+                    from_var: owner.clone(),
+                    to_var: owner.clone(),
+                    weight: 0,
+                }))
+            })
+            .collect::<Result<_>>()?;
+        let reserved: i64 = graph
+            .node_insts
+            .iter()
+            .zip(&replicated)
+            .filter(|(_, mode)| mode.is_some())
+            .map(|(node, _)| node.node_type.size_byte())
+            .sum();
+        if reserved >= hardware.core_size {
+            let (node, _) = graph
+                .node_insts
+                .iter()
+                .zip(&replicated)
+                .find(|(_, mode)| mode.is_some())
+                .unwrap();
+            return Err(DataMappingError::CapacityExceeded(node.varname.clone()).into());
+        }
+        let reduced = SemanticGraph {
+            node_insts: graph.node_insts.clone(),
+            edge_insts,
+            walker_insts: graph.walker_insts.clone(),
+            constraints: graph.constraints.clone(),
+        };
+        let inner = self.inner.partition(
+            &reduced,
+            &HardwareConfig {
+                core_size: hardware.core_size - reserved,
+                ..hardware.clone()
+            },
+        )?;
+
+        let mut neighbor_cores = vec![vec![]; graph.node_insts.len()];
+        for edge in &graph.edge_insts {
+            let (from, to) = (find(&edge.from_var.varname)?, find(&edge.to_var.varname)?);
+            neighbor_cores[from].push(inner.assignment[to]);
+            neighbor_cores[to].push(inner.assignment[from]);
+        }
+        let replicas = replicated
+            .iter()
+            .enumerate()
+            .filter_map(|(node, mode)| {
+                let mut cores = std::mem::take(&mut neighbor_cores[node]);
+                cores.retain(|core| *core != inner.assignment[node]);
+                cores.sort_unstable();
+                cores.dedup();
+                mode.map(|mode| Replicas { node, mode, cores })
+            })
+            .collect();
+
+        let mut partition = Partition::new(
+            &reduced.node_insts,
+            &reduced.edge_insts,
+            inner.assignment,
+            inner.core_num,
+            inner.stats.partitioner,
+            start.elapsed(),
+        )?;
+        partition.replicas = replicas;
+        partition.stats.status = inner.stats.status;
+        Ok(partition)
+    }
+}
+
+#[test]
+fn test_replicated_hub_is_not_cut() -> Result<()> {
+    use super::MultilevelPartitioner;
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // A hub with 11 leaves, 4 bytes each, on 3 DPUs.
+    let config = GeneratorConfig {
+        kind: GraphKind::Star,
+        nodes: 12,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 5,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partitioner = |degree_threshold| ReplicatingPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        degree_threshold,
+        mode: ReplicaMode::ReadOnly,
+    };
+    let plain = partitioner(None).partition(&g, &hardware)?;
+    assert!(plain.replicas.is_empty());
+    assert!(plain.stats.cut_weight >= 6);
+
+    let replicated = partitioner(Some(5)).partition(&g, &hardware)?;
+    assert_eq!(replicated.stats.cut_weight, 0);
+    assert_eq!(replicated.replicas.len(), 1);
+    let hub = &replicated.replicas[0];
+    assert_eq!(hub.node, 0);
+    assert_eq!(hub.cores.len(), 2);
+    assert!(!hub.cores.contains(&replicated.assignment[0]));
+    // Every DPU holds a copy of the hub and stays within its capacity.
+    for nodes in replicated.core_nodes(&g) {
+        assert!(nodes.iter().any(|node| node.varname == "n0"));
+        assert!(nodes.len() as i64 * 4 <= hardware.core_size);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_constrained_nodes_are_not_replicated() -> Result<()> {
+    use super::MultilevelPartitioner;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // A hub kept apart from one of its leaves: a copy next to the leaf would break that.
+    let analyze = |annotation: &str| {
+        let source = format!(
+            "node Alice {{ n1: int32; }}; edge Alice Alice Link {{}};
+             graph {{
+                 {} node Alice hub;
+                 node Alice a0, a1, a2;
+                 edge Link hub a0 1; edge Link hub a1 1; edge Link hub a2 1;
+                 @separate(hub, a0);
+             }};",
+            annotation
+        );
+        semantic_analysis(parse_str(&source)?).map(|sm| sm.graphs[0].clone())
+    };
+    let hardware = HardwareConfig {
+        core_size: 4 * 3,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partitioner = ReplicatingPartitioner {
+        inner: Box::new(MultilevelPartitioner::default()),
+        degree_threshold: Some(3),
+        mode: ReplicaMode::ReadOnly,
+    };
+    let by_degree = partitioner.partition(&analyze("")?, &hardware)?;
+    assert!(by_degree.replicas.is_empty());
+    assert_ne!(by_degree.assignment[0], by_degree.assignment[1]);
+
+    let error = partitioner
+        .partition(&analyze("@replicate")?, &hardware)
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::ConstrainedReplica(node)) if node == "hub"
+    ));
+    Ok(())
+}
@@ -0,0 +1,1060 @@
+use super::components::solve_by_components;
+use super::graph::PartitionGraph;
+use super::infeasible::explain_unsat;
+use super::{
+    Balance, DataMappingError, HardwareConfig, Objective, Partition, Partitioner, Requirement,
+    SolveStatus, SolverBudget, Z3Encoding, Z3Partitioner,
+};
+use crate::sem_type::{SemanticEdgeInst, SemanticGraph, SemanticNodeInst};
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Instant;
+use z3::{
+    ast::{Ast, Bool, Int},
+    Config, Context, Params, SatResult, Solver,
+};
+
+impl Partitioner for Z3Partitioner {
+    fn name(&self) -> &'static str {
+        "z3"
+    }
+
+    /// Disconnected components are solved independently, in parallel, and packed onto the
+    /// DPUs; the graph is solved as a whole when they cannot be packed, or when the
+    /// objective weighs the largest core cost, which couples the components. Placement
+    /// constraints also keep the graph whole.
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let (mut pgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+        self.balance.apply(&mut pgraph, hardware.core_num as usize);
+        let solve = |graph: &PartitionGraph, hardware: &HardwareConfig| {
+            solve_graph(
+                graph,
+                hardware.core_size,
+                hardware.core_num,
+                self.encoding,
+                &self.budget,
+                &self.balance,
+            )
+        };
+        let decomposed = match self.balance.objective {
+            Objective::Cut if !pgraph.is_constrained() => {
+                solve_by_components(&pgraph, hardware, solve)
+            }
+            _ => Ok(None),
+        };
+        let result = match decomposed {
+            Ok(Some(solution)) => Ok(solution),
+            Ok(None) => solve(&pgraph, hardware),
+            Err(e) => Err(e),
+        };
+        let (assignment, status) = match result {
+            Err(e) if matches!(e.downcast_ref(), Some(DataMappingError::BudgetExhausted)) => {
+                let Some(fallback) = &self.fallback else {
+                    return Err(e);
+                };
+                let mut partition = fallback.partition(graph, hardware)?;
+                partition.stats.status = SolveStatus::Fallback(self.name());
+                return Ok(partition);
+            }
+            result => {
+                let names: Vec<&str> = graph
+                    .node_insts
+                    .iter()
+                    .map(|node| node.varname.as_str())
+                    .collect();
+                result.map_err(|e| explain_unsat(e, &pgraph, &names, &group, hardware))?
+            }
+        };
+        let mut partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            group.iter().map(|g| assignment[*g]).collect(),
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )?;
+        partition.stats.status = status;
+        Ok(partition)
+    }
+}
+
+/// The decision variables of an encoded instance.
+enum Encoded<'ctx> {
+    Ite {
+        xs: Vec<Int<'ctx>>,
+        objective: Int<'ctx>,
+        /// Compute cost of every core.
+        costs: Vec<Int<'ctx>>,
+    },
+    OneHot {
+        /// `cores[i][j]` holds if node `i` is on core `j`. Node `i` may only use the first
+        /// `i + 1` cores.
+        cores: Vec<Vec<Bool<'ctx>>>,
+        /// Co-located weight is the weighted sum of these literals plus `offset`.
+        objective: Vec<(Bool<'ctx>, i32)>,
+        offset: i64,
+        /// Compute cost of every core as a weighted sum of core literals.
+        costs: Vec<Vec<(Bool<'ctx>, i32)>>,
+    },
+}
+
+impl<'ctx> Encoded<'ctx> {
+    /// Constraint requiring a co-located weight of at least `target`.
+    fn at_least(&self, context: &'ctx Context, target: i64) -> Result<Bool<'ctx>> {
+        Ok(match self {
+            Encoded::Ite { objective, .. } => objective.ge(&Int::from_i64(context, target)),
+            Encoded::OneHot {
+                objective, offset, ..
+            } => {
+                let terms: Vec<(&Bool, i32)> = objective.iter().map(|(b, w)| (b, *w)).collect();
+                Bool::pb_ge(context, &terms, pb_coefficient(target - offset)?)
+            }
+        })
+    }
+
+    /// Constraint keeping the compute cost of every core within `limit`.
+    fn cost_at_most(&self, context: &'ctx Context, limit: i64) -> Result<Bool<'ctx>> {
+        let bounds = match self {
+            Encoded::Ite { costs, .. } => costs
+                .iter()
+                .map(|cost| cost.le(&Int::from_i64(context, limit)))
+                .collect(),
+            Encoded::OneHot { costs, .. } => {
+                let limit = pb_coefficient(limit)?;
+                costs
+                    .iter()
+                    .map(|cost| {
+                        let terms: Vec<(&Bool, i32)> = cost.iter().map(|(b, c)| (b, *c)).collect();
+                        Bool::pb_le(context, &terms, limit)
+                    })
+                    .collect::<Vec<Bool>>()
+            }
+        };
+        Ok(Bool::and(context, &bounds.iter().collect::<Vec<&Bool>>()))
+    }
+
+    /// Subtract `weight` times the largest core cost from the objective. `max_cost` is an
+    /// upper bound on any core cost.
+    fn penalize_max_cost(
+        &mut self,
+        context: &'ctx Context,
+        solver: &Solver<'ctx>,
+        weight: i64,
+        max_cost: i64,
+    ) -> Result<()> {
+        match self {
+            Encoded::Ite {
+                objective, costs, ..
+            } => {
+                let max = Int::new_const(context, "max_cost");
+                for cost in costs.iter() {
+                    solver.assert(&cost.le(&max));
+                }
+                *objective -= max * Int::from_i64(context, weight);
+            }
+            Encoded::OneHot {
+                objective,
+                offset,
+                costs,
+                ..
+            } => {
+                // The largest cost in binary, max = sum of 2^t m_t. Every core bounds
+                // cost + sum of 2^t !m_t <= 2^bits - 1, and the objective gains
+                // weight * 2^t for every !m_t.
+                let bits = (i64::BITS - max_cost.max(1).leading_zeros()) as usize;
+                let top = (1i64 << bits) - 1;
+                let digits: Vec<(Bool, i64)> = (0..bits)
+                    .map(|t| (Bool::new_const(context, format!("m_{}", t)).not(), 1 << t))
+                    .collect();
+                for cost in costs.iter() {
+                    let mut terms: Vec<(&Bool, i32)> = cost.iter().map(|(b, c)| (b, *c)).collect();
+                    for (digit, value) in &digits {
+                        terms.push((digit, pb_coefficient(*value)?));
+                    }
+                    solver.assert(&Bool::pb_le(context, &terms, pb_coefficient(top)?));
+                }
+                for (digit, value) in digits {
+                    objective.push((digit, pb_coefficient(weight * value)?));
+                }
+                *offset -= weight * top;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the core of every node from the current model.
+    fn assignment(&self, solver: &Solver<'ctx>) -> Vec<usize> {
+        let model = solver.get_model().unwrap();
+        match self {
+            Encoded::Ite { xs, .. } => xs
+                .iter()
+                .map(|x| model.eval(x, true).unwrap().as_i64().unwrap() as usize)
+                .collect(),
+            Encoded::OneHot { cores, .. } => cores
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .position(|b| model.eval(b, true).unwrap().as_bool().unwrap())
+                        .unwrap()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether every coefficient and bound of the one-hot encoding of `graph` fits in the
+/// `i32` of Z3's pseudo-boolean constraints, with the largest core cost weighted by
+/// `penalty` in the objective.
+fn fits_pseudo_boolean(graph: &PartitionGraph, core_size: i64, penalty: i64) -> bool {
+    let total_weight: i64 = graph.edges.iter().map(|edge| edge.weight.abs()).sum();
+    // The largest core cost is encoded in binary, below twice the total cost.
+    let top = graph.total_cost().max(1).saturating_mul(2);
+    [
+        core_size,
+        graph.cost_limit.unwrap_or(0),
+        top,
+        total_weight.saturating_add(penalty.saturating_mul(top)),
+    ]
+    .into_iter()
+    .chain(graph.node_sizes.iter().copied())
+    .chain(graph.edges.iter().map(|edge| edge.size))
+    .all(|value| i32::try_from(value).is_ok())
+}
+
+fn pb_coefficient(value: i64) -> Result<i32> {
+    Ok(i32::try_from(value).map_err(|_| DataMappingError::CoefficientOverflow(value))?)
+}
+
+/// Constraints asserted under a label of their own, so that an unsat core can name them.
+type Tracked<'ctx> = Vec<(Bool<'ctx>, Requirement)>;
+
+fn assert_tracked<'ctx>(
+    context: &'ctx Context,
+    solver: &Solver<'ctx>,
+    tracked: &mut Tracked<'ctx>,
+    constraint: &Bool<'ctx>,
+    requirement: Requirement,
+) {
+    let label = Bool::new_const(context, format!("track_{}", tracked.len()));
+    solver.assert_and_track(constraint, &label);
+    tracked.push((label, requirement));
+}
+
+/// The original formulation: one `Int` per node and, for every core, an `ite` sum over
+/// every node and edge.
+fn encode_ite<'ctx>(
+    context: &'ctx Context,
+    solver: &Solver<'ctx>,
+    tracked: &mut Tracked<'ctx>,
+    graph: &PartitionGraph,
+    core_size: i64,
+    core_num: i64,
+) -> Encoded<'ctx> {
+    let xs: Vec<Int> = (0..graph.node_count())
+        .map(|i| Int::new_const(context, format! {"x_{}", i}))
+        .collect();
+
+    for x in &xs {
+        solver.assert(&x.ge(&Int::from_i64(context, 0)));
+        solver.assert(&x.lt(&Int::from_i64(context, core_num)));
+    }
+    for (node, (x, fixed)) in xs.iter().zip(&graph.fixed).enumerate() {
+        if let Some(core) = fixed {
+            let pin = x._eq(&Int::from_i64(context, *core as i64));
+            assert_tracked(context, solver, tracked, &pin, Requirement::Pin(node));
+        }
+    }
+    for (a, separated) in graph.separated.iter().enumerate() {
+        for &b in separated.iter().filter(|b| **b > a) {
+            let apart = xs[a]._eq(&xs[b]).not();
+            assert_tracked(
+                context,
+                solver,
+                tracked,
+                &apart,
+                Requirement::Separate(a, b),
+            );
+        }
+    }
+
+    for j in 0..core_num {
+        // For bag j, compute total weight.
+        // We build an Int expression representing the total weight in bag j.
+        let mut total_weight = Int::from_i64(context, 0);
+        for (x, size) in xs.iter().zip(&graph.node_sizes) {
+            // Convert the boolean decision (true/false) to an integer 1/0.
+            // This is done via an if-then-else.
+            let node_in_core = x._eq(&Int::from_i64(context, j));
+            let node_size_in_core =
+                node_in_core.ite(&Int::from_i64(context, *size), &Int::from_i64(context, 0));
+            total_weight += node_size_in_core;
+        }
+
+        // Calculate all edges
+        for edge in &graph.edges {
+            let list = [
+                &Int::from_i64(context, j)._eq(&xs[edge.from]),
+                &Int::from_i64(context, j)._eq(&xs[edge.to]),
+            ];
+            let edge_in_core = Bool::or(context, &list);
+            let edge_weight = edge_in_core.ite(
+                &Int::from_i64(context, edge.size),
+                &Int::from_i64(context, 0),
+            );
+            total_weight += edge_weight;
+        }
+        // The total weight must be <= capacity of bag j.
+        let capacity_expr = Int::from_i64(context, core_size);
+        let capacity_constraint = total_weight.le(&capacity_expr);
+        assert_tracked(
+            context,
+            solver,
+            tracked,
+            &capacity_constraint,
+            Requirement::Capacity(j as usize),
+        );
+    }
+
+    let costs = (0..core_num)
+        .map(|j| {
+            let mut cost = Int::from_i64(context, 0);
+            for (x, node_cost) in xs.iter().zip(&graph.node_costs) {
+                cost += x._eq(&Int::from_i64(context, j)).ite(
+                    &Int::from_i64(context, *node_cost),
+                    &Int::from_i64(context, 0),
+                );
+            }
+            cost
+        })
+        .collect();
+
+    let mut objective = Int::from_i64(context, 0);
+    for edge in &graph.edges {
+        let weight = Int::from_i64(context, edge.weight);
+        objective += xs[edge.from]
+            ._eq(&xs[edge.to])
+            .ite(&weight, &Int::from_i64(context, 0));
+    }
+    Encoded::Ite {
+        xs,
+        objective,
+        costs,
+    }
+}
+
+/// One-hot formulation. Capacities are pseudo-boolean sums over node and edge literals,
+/// co-location is a boolean per edge tied to the core literals of its endpoints, and
+/// interchangeable cores are ordered by their first node: node 0 goes to core 0, and a
+/// node may open core `j` only once some earlier node is on core `j - 1`. Cores are no
+/// longer interchangeable once a node is pinned, so pins turn that ordering off.
+fn encode_one_hot<'ctx>(
+    context: &'ctx Context,
+    solver: &Solver<'ctx>,
+    tracked: &mut Tracked<'ctx>,
+    graph: &PartitionGraph,
+    core_size: i64,
+    core_num: i64,
+) -> Result<Encoded<'ctx>> {
+    let core_num = core_num as usize;
+    let symmetric = graph.fixed.iter().all(Option::is_none);
+    let cores: Vec<Vec<Bool>> = (0..graph.node_count())
+        .map(|i| {
+            let usable = if symmetric {
+                core_num.min(i + 1)
+            } else {
+                core_num
+            };
+            (0..usable)
+                .map(|j| Bool::new_const(context, format!("b_{}_{}", i, j)))
+                .collect()
+        })
+        .collect();
+    let on = |i: usize, j: usize| cores[i].get(j);
+
+    for row in &cores {
+        let terms: Vec<(&Bool, i32)> = row.iter().map(|b| (b, 1)).collect();
+        solver.assert(&Bool::pb_eq(context, &terms, 1));
+    }
+    for (node, (row, fixed)) in cores.iter().zip(&graph.fixed).enumerate() {
+        if let Some(core) = fixed {
+            assert_tracked(
+                context,
+                solver,
+                tracked,
+                &row[*core],
+                Requirement::Pin(node),
+            );
+        }
+    }
+    for (a, separated) in graph.separated.iter().enumerate() {
+        for &b in separated.iter().filter(|b| **b > a) {
+            let apart: Vec<Bool> = (0..core_num)
+                .filter_map(|j| match (on(a, j), on(b, j)) {
+                    (Some(x), Some(y)) => Some(Bool::and(context, &[x, y]).not()),
+                    _ => None,
+                })
+                .collect();
+            let apart = Bool::and(context, &apart.iter().collect::<Vec<&Bool>>());
+            assert_tracked(
+                context,
+                solver,
+                tracked,
+                &apart,
+                Requirement::Separate(a, b),
+            );
+        }
+    }
+
+    // `opened[i][j]`: one of the nodes 0..=i is on core j.
+    let mut opened: Vec<Vec<Bool>> = vec![];
+    for (i, row) in cores.iter().enumerate().filter(|_| symmetric) {
+        let mut current = vec![];
+        for (j, b) in row.iter().enumerate() {
+            let open = Bool::new_const(context, format!("open_{}_{}", i, j));
+            match opened
+                .last()
+                .and_then(|previous: &Vec<Bool>| previous.get(j))
+            {
+                Some(previous) => solver.assert(&open.implies(&Bool::or(context, &[previous, b]))),
+                None => solver.assert(&open.implies(b)),
+            }
+            if j > 0 {
+                solver.assert(&b.implies(&opened[i - 1][j - 1]));
+            }
+            current.push(open);
+        }
+        opened.push(current);
+    }
+
+    let mut loads: Vec<Vec<(Bool, i32)>> = vec![vec![]; core_num];
+    for (row, size) in cores.iter().zip(&graph.node_sizes) {
+        let size = pb_coefficient(*size)?;
+        if size > 0 {
+            for (j, b) in row.iter().enumerate() {
+                loads[j].push((b.clone(), size));
+            }
+        }
+    }
+    let mut costs: Vec<Vec<(Bool, i32)>> = vec![vec![]; core_num];
+    for (row, cost) in cores.iter().zip(&graph.node_costs) {
+        let cost = pb_coefficient(*cost)?;
+        if cost > 0 {
+            for (j, b) in row.iter().enumerate() {
+                costs[j].push((b.clone(), cost));
+            }
+        }
+    }
+    let mut objective = vec![];
+    let mut offset = 0;
+    for (e, edge) in graph.edges.iter().enumerate() {
+        let (from, to, weight) = (&edge.from, &edge.to, &edge.weight);
+        // The edge is stored on every core holding one of its endpoints.
+        let size = pb_coefficient(edge.size)?;
+        if size > 0 {
+            for (j, load) in loads.iter_mut().enumerate() {
+                let ends: Vec<&Bool> = [on(*from, j), on(*to, j)].into_iter().flatten().collect();
+                if ends.is_empty() {
+                    continue;
+                }
+                let stored = Bool::new_const(context, format!("s_{}_{}", e, j));
+                for end in ends {
+                    solver.assert(&end.implies(&stored));
+                }
+                load.push((stored, size));
+            }
+        }
+
+        if from == to {
+            offset += weight;
+            continue;
+        }
+        let together = Bool::new_const(context, format!("y_{}", e));
+        for j in 0..core_num {
+            match (on(*from, j), on(*to, j)) {
+                (Some(f), Some(t)) if *weight > 0 => {
+                    solver.assert(&together.implies(&f.implies(t)));
+                }
+                (Some(f), None) if *weight > 0 => {
+                    solver.assert(&together.implies(&f.not()));
+                }
+                (None, Some(t)) if *weight > 0 => {
+                    solver.assert(&together.implies(&t.not()));
+                }
+                (Some(f), Some(t)) if *weight < 0 => {
+                    solver.assert(&Bool::and(context, &[f, t]).implies(&together));
+                }
+                _ => {}
+            }
+        }
+        // Negative weights count when the edge is cut, keeping coefficients positive.
+        match weight.signum() {
+            1 => objective.push((together, pb_coefficient(*weight)?)),
+            -1 => {
+                objective.push((together.not(), pb_coefficient(-weight)?));
+                offset += weight;
+            }
+            _ => {}
+        }
+    }
+
+    let capacity = pb_coefficient(core_size)?;
+    for (j, load) in loads.iter().enumerate() {
+        let terms: Vec<(&Bool, i32)> = load.iter().map(|(b, size)| (b, *size)).collect();
+        let fits = Bool::pb_le(context, &terms, capacity);
+        assert_tracked(context, solver, tracked, &fits, Requirement::Capacity(j));
+    }
+    Ok(Encoded::OneHot {
+        cores,
+        objective,
+        offset,
+        costs,
+    })
+}
+
+/// Where a bisection stopped.
+enum Search {
+    /// The best value found is proven maximal.
+    Optimal,
+    /// The budget ran out; no value above `upper` is possible.
+    Stopped { upper: i64 },
+}
+
+/// An encoded instance being solved within a budget that started at `start`.
+struct Session<'a, 'ctx> {
+    context: &'ctx Context,
+    solver: &'a Solver<'ctx>,
+    encoded: &'a Encoded<'ctx>,
+    tracked: &'a Tracked<'ctx>,
+    budget: &'a SolverBudget,
+    start: Instant,
+}
+
+impl<'ctx> Session<'_, 'ctx> {
+    /// Anytime maximization: every model found is kept in `best` with its value, and the
+    /// objective is bisected between the best value found and the best upper bound proven
+    /// so far. `at_least(target)` constrains the value to `target` or more, and `value`
+    /// evaluates an assignment.
+    fn maximize(
+        &self,
+        best: &mut Option<(Vec<usize>, i64)>,
+        mut upper: i64,
+        at_least: impl Fn(i64) -> Result<Bool<'ctx>>,
+        value: impl Fn(&[usize]) -> i64,
+    ) -> Result<Search> {
+        let (context, solver, budget) = (self.context, self.solver, self.budget);
+        loop {
+            let mut params = Params::new(context);
+            if let Some(timeout) = budget.timeout {
+                let remaining = timeout.saturating_sub(self.start.elapsed());
+                if remaining.is_zero() {
+                    return Ok(Search::Stopped { upper });
+                }
+                params.set_u32(
+                    "timeout",
+                    remaining.as_millis().clamp(1, u32::MAX as u128) as u32,
+                );
+            }
+            if let Some(rlimit) = budget.rlimit {
+                params.set_u32("rlimit", rlimit);
+            }
+            solver.set_params(&params);
+
+            let target = match best {
+                Some((_, found)) if *found >= upper => return Ok(Search::Optimal),
+                Some((_, found)) => *found + 1 + (upper - *found - 1) / 2,
+                None => i64::MIN,
+            };
+            solver.push();
+            if best.is_some() {
+                solver.assert(&at_least(target)?);
+            }
+            let result = solver.check();
+            match result {
+                SatResult::Sat => {
+                    let assignment = self.encoded.assignment(solver);
+                    let found = value(&assignment);
+                    *best = Some((assignment, found));
+                }
+                SatResult::Unsat if best.is_none() => {
+                    let core = solver.get_unsat_core();
+                    let requirements = self
+                        .tracked
+                        .iter()
+                        .filter(|(label, _)| core.contains(label))
+                        .map(|(_, requirement)| *requirement)
+                        .collect();
+                    return Err(DataMappingError::Unsatisfiable(requirements).into());
+                }
+                SatResult::Unsat => upper = target - 1,
+                SatResult::Unknown => return Ok(Search::Stopped { upper }),
+            }
+            solver.pop(1);
+        }
+    }
+}
+
+/// Solve the exact formulation of `graph` within `budget`. When the budget runs out, the
+/// best assignment found so far is returned with a `Feasible` status carrying the proven
+/// lower bound on the objective; `BudgetExhausted` means no assignment was found at all,
+/// and `Unsatisfiable` that none exists, with the constraints of an unsat core.
+///
+/// The compute cost of every core is kept within `graph.cost_limit`, and `balance`
+/// selects how the largest core cost enters the objective.
+pub fn solve_graph(
+    graph: &PartitionGraph,
+    core_size: i64,
+    core_num: i64,
+    encoding: Z3Encoding,
+    budget: &SolverBudget,
+    balance: &Balance,
+) -> Result<(Vec<usize>, SolveStatus)> {
+    let start = Instant::now();
+    let config = Config::new();
+    let context = Context::new(&config);
+    let solver = Solver::new(&context);
+    let mut tracked = vec![];
+    let penalty = match balance.objective {
+        Objective::Weighted => balance.weight,
+        Objective::Cut | Objective::Lexicographic => 0,
+    };
+    // Capacities beyond `i32` leave only the `ite` encoding.
+    let encoding = match encoding {
+        Z3Encoding::OneHot if !fits_pseudo_boolean(graph, core_size, penalty) => Z3Encoding::Ite,
+        encoding => encoding,
+    };
+    let mut encoded = match encoding {
+        Z3Encoding::Ite => encode_ite(&context, &solver, &mut tracked, graph, core_size, core_num),
+        Z3Encoding::OneHot => {
+            encode_one_hot(&context, &solver, &mut tracked, graph, core_size, core_num)?
+        }
+    };
+    if let Some(limit) = graph.cost_limit {
+        let within = encoded.cost_at_most(&context, limit)?;
+        assert_tracked(
+            &context,
+            &solver,
+            &mut tracked,
+            &within,
+            Requirement::CostLimit,
+        );
+    }
+
+    // No core can cost less than its share of the total, nor less than any node.
+    let core_count = core_num.max(1) as usize;
+    let total_cost = graph.total_cost();
+    let min_max_cost = graph
+        .node_costs
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max((total_cost + core_num.max(1) - 1) / core_num.max(1));
+    let max_cost = |assignment: &[usize]| {
+        graph
+            .core_costs(assignment, core_count)
+            .into_iter()
+            .max()
+            .unwrap_or(0)
+    };
+
+    // The objective maximized is the co-located weight, less the weighted largest cost.
+    if penalty != 0 {
+        encoded.penalize_max_cost(&context, &solver, penalty, total_cost)?;
+    }
+    let total_weight: i64 = graph.edges.iter().map(|edge| edge.weight).sum();
+    let upper: i64 = graph
+        .edges
+        .iter()
+        .map(|edge| edge.weight.max(0))
+        .sum::<i64>()
+        - penalty * min_max_cost;
+    let session = Session {
+        context: &context,
+        solver: &solver,
+        encoded: &encoded,
+        tracked: &tracked,
+        budget,
+        start,
+    };
+    let mut best: Option<(Vec<usize>, i64)> = None;
+    let search = session.maximize(
+        &mut best,
+        upper,
+        |target| encoded.at_least(&context, target),
+        |assignment| total_weight - graph.cut_weight(assignment) - penalty * max_cost(assignment),
+    )?;
+    let mut status = match search {
+        Search::Optimal => SolveStatus::Optimal,
+        Search::Stopped { upper } => SolveStatus::Feasible {
+            bound: total_weight - upper,
+        },
+    };
+
+    // Among the minimum cuts, look for the one with the cheapest most loaded core.
+    if balance.objective == Objective::Lexicographic && status == SolveStatus::Optimal {
+        let (assignment, colocated) = best.take().unwrap();
+        solver.assert(&encoded.at_least(&context, colocated)?);
+        best = Some((assignment.clone(), -max_cost(&assignment)));
+        let search = session.maximize(
+            &mut best,
+            -min_max_cost,
+            |target| encoded.cost_at_most(&context, -target),
+            |assignment| -max_cost(assignment),
+        )?;
+        if let Search::Stopped { .. } = search {
+            status = SolveStatus::Feasible {
+                bound: total_weight - colocated,
+            };
+        }
+    }
+    let (assignment, _) = best.ok_or(DataMappingError::BudgetExhausted)?;
+    Ok((assignment, status))
+}
+
+/// Solve the exact formulation for the given nodes and edges as a single problem.
+pub fn assign_with_z3(
+    unions: &[Rc<SemanticNodeInst>],
+    edges: &[Rc<SemanticEdgeInst>],
+    core_size: i64,
+    core_num: i64,
+    encoding: Z3Encoding,
+    budget: &SolverBudget,
+) -> Result<Partition> {
+    let start = Instant::now();
+    let graph = PartitionGraph::from_parts(unions, edges)?;
+    let (assignment, status) = solve_graph(
+        &graph,
+        core_size,
+        core_num,
+        encoding,
+        budget,
+        &Balance::default(),
+    )
+    .map_err(|e| {
+        let names: Vec<&str> = unions.iter().map(|node| node.varname.as_str()).collect();
+        let group: Vec<usize> = (0..names.len()).collect();
+        let hardware = HardwareConfig {
+            core_size,
+            core_num,
+            rank_size: None,
+            edge_placement: Default::default(),
+        };
+        explain_unsat(e, &graph, &names, &group, &hardware)
+    })?;
+    let mut partition = Partition::new(
+        unions,
+        edges,
+        assignment,
+        core_num as usize,
+        "z3",
+        start.elapsed(),
+    )?;
+    partition.stats.status = status;
+    Ok(partition)
+}
+
+#[test]
+fn test_z3() -> Result<()> {
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::fs;
+
+    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
+    let sm = semantic_analysis(parse_str(&file_context)?)?;
+    let g = sm.graphs[0].clone();
+    let solve = |core_num| {
+        assign_with_z3(
+            &g.node_insts,
+            &g.edge_insts,
+            3,
+            core_num,
+            Z3Encoding::OneHot,
+            &SolverBudget::default(),
+        )
+    };
+    // The graph takes 10 bytes, more than 3 DPUs of 3 bytes hold.
+    let error = solve(3).unwrap_err();
+    let Some(DataMappingError::Infeasible(infeasibility)) = error.downcast_ref() else {
+        panic!("unexpected error {}", error);
+    };
+    assert!(infeasibility.reasons[0].contains("exceeds total capacity (9 bytes) by 1 byte"));
+    assert_eq!(infeasibility.min_dpus, Some(4));
+    let enough = infeasibility.enough_dpus.unwrap();
+    solve(enough as i64)?;
+    Ok(())
+}
+
+#[test]
+fn test_z3_generated_chain() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 6,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    // Three 4-byte nodes fit on a core, so the chain is cut exactly once.
+    let hardware = HardwareConfig {
+        core_size: 12,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = Z3Partitioner::default().partition(&g, &hardware)?;
+    assert_eq!(partition.stats.cut_weight, 1);
+    assert_eq!(partition.stats.status, SolveStatus::Optimal);
+    assert_eq!(partition.core_sizes(), vec![3, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_z3_budget_fallback() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Rmat,
+        nodes: 200,
+        edges: 800,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 20,
+        core_num: 16,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let budget = SolverBudget {
+        timeout: None,
+        rlimit: Some(1),
+    };
+    let strict = Z3Partitioner {
+        budget: budget.clone(),
+        fallback: None,
+        ..Default::default()
+    };
+    let error = strict.partition(&g, &hardware).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DataMappingError::BudgetExhausted)
+    ));
+
+    let partition = Z3Partitioner {
+        budget,
+        ..Default::default()
+    }
+    .partition(&g, &hardware)?;
+    assert_eq!(partition.stats.partitioner, "multilevel");
+    assert_eq!(partition.stats.status, SolveStatus::Fallback("z3"));
+    Ok(())
+}
+
+#[test]
+fn test_z3_timeout_keeps_best_model() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+    use std::time::Duration;
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Uniform,
+        nodes: 40,
+        edges: 120,
+        max_weight: 9,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 4 * 10,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = Z3Partitioner {
+        budget: SolverBudget {
+            timeout: Some(Duration::from_millis(500)),
+            rlimit: None,
+        },
+        fallback: None,
+        ..Default::default()
+    }
+    .partition(&g, &hardware)?;
+    match partition.stats.status {
+        SolveStatus::Optimal => {}
+        SolveStatus::Feasible { bound } => assert!(bound <= partition.stats.cut_weight),
+        status => panic!("Z3 always reports how far it got, not `{}`", status),
+    }
+    // The best model found is a complete partition within the capacity.
+    assert_eq!(partition.assignment.len(), 40);
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    assert!(pgraph
+        .core_loads(&partition.assignment, 4)
+        .iter()
+        .all(|load| *load <= hardware.core_size));
+    Ok(())
+}
+
+#[test]
+fn test_z3_capacity_beyond_i32() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 6,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    let capacity = i32::MAX as i64 + 1;
+    assert!(fits_pseudo_boolean(&pgraph, 64 * 1024 * 1024, 0));
+    assert!(!fits_pseudo_boolean(&pgraph, capacity, 0));
+    let hardware = HardwareConfig {
+        core_size: capacity,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = Z3Partitioner::default().partition(&g, &hardware)?;
+    assert_eq!(partition.stats.cut_weight, 0);
+    assert_eq!(partition.stats.status, SolveStatus::Optimal);
+    Ok(())
+}
+
+#[test]
+fn test_z3_encodings_agree() -> Result<()> {
+    use crate::generator::{generate_graph, GeneratorConfig, GraphKind};
+    use crate::sem_type::SemanticEdgeInst;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::fs;
+
+    // 1-byte nodes and 1-byte edges, so that edge storage matters for capacity.
+    let file_context = fs::read_to_string(String::from("examples/test_dm.dspim"))?;
+    let sm = semantic_analysis(parse_str(&file_context)?)?;
+    let edge_type = sm.edges["Self"].clone();
+    for kind in [GraphKind::Grid, GraphKind::Uniform, GraphKind::Rmat] {
+        let config = GeneratorConfig {
+            kind,
+            nodes: 9,
+            edges: 14,
+            max_weight: 6,
+            ..Default::default()
+        };
+        let mut g = generate_graph(&config, &edge_type)?;
+        // Some edges are better cut than kept.
+        g.edge_insts = g
+            .edge_insts
+            .iter()
+            .enumerate()
+            .map(|(i, edge)| {
+                Rc::new(SemanticEdgeInst {
+                    edge_type: edge.edge_type.clone(),
+                    from_var: edge.from_var.clone(),
+                    to_var: edge.to_var.clone(),
+                    weight: if i % 4 == 3 {
+                        -edge.weight
+                    } else {
+                        edge.weight
+                    },
+                })
+            })
+            .collect();
+        let hardware = HardwareConfig {
+            core_size: 12,
+            core_num: 3,
+            rank_size: None,
+            edge_placement: Default::default(),
+        };
+        let solve = |encoding| {
+            Z3Partitioner {
+                encoding,
+                fallback: None,
+                ..Default::default()
+            }
+            .partition(&g, &hardware)
+        };
+        let ite = solve(Z3Encoding::Ite)?;
+        let one_hot = solve(Z3Encoding::OneHot)?;
+        assert_eq!(ite.stats.status, SolveStatus::Optimal);
+        assert_eq!(one_hot.stats.status, SolveStatus::Optimal);
+        assert_eq!(ite.stats.cut_weight, one_hot.stats.cut_weight);
+        let pgraph = super::graph::PartitionGraph::from_semantic(&g)?;
+        assert!(pgraph
+            .core_loads(&one_hot.assignment, 3)
+            .iter()
+            .all(|load| *load <= hardware.core_size));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_z3_balance() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    // Six 4-byte nodes in a chain all fit on one core, which is the only uncut partition.
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 6,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    let hardware = HardwareConfig {
+        core_size: 100,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    // Both encodings must agree on the cut and the core costs.
+    let solve = |balance: Balance| -> Result<(i64, Vec<i64>)> {
+        let mut results = vec![];
+        for encoding in [Z3Encoding::Ite, Z3Encoding::OneHot] {
+            let partition = Z3Partitioner {
+                encoding,
+                balance,
+                fallback: None,
+                ..Default::default()
+            }
+            .partition(&g, &hardware)?;
+            assert_eq!(partition.stats.status, SolveStatus::Optimal);
+            let mut costs = pgraph.core_costs(&partition.assignment, 2);
+            costs.sort_unstable_by(|a, b| b.cmp(a));
+            results.push((partition.stats.cut_weight, costs));
+        }
+        assert_eq!(results[0], results[1]);
+        Ok(results.pop().unwrap())
+    };
+    assert_eq!(solve(Balance::default())?, (0, vec![24, 0]));
+    let weighted = Balance {
+        objective: Objective::Weighted,
+        weight: 1,
+        ..Default::default()
+    };
+    assert_eq!(solve(weighted)?, (1, vec![12, 12]));
+    let exact = Balance {
+        epsilon: Some(0.0),
+        ..Default::default()
+    };
+    assert_eq!(solve(exact)?, (1, vec![12, 12]));
+    // Cuts 3 | 3 and 4 | 2 are both within 1.5 times the average.
+    let lexicographic = Balance {
+        epsilon: Some(0.5),
+        objective: Objective::Lexicographic,
+        ..Default::default()
+    };
+    assert_eq!(solve(lexicographic)?, (1, vec![12, 12]));
+
+    for partition in [
+        super::MultilevelPartitioner {
+            balance: exact,
+            ..Default::default()
+        }
+        .partition(&g, &hardware)?,
+        super::GreedyPartitioner {
+            order: super::VisitOrder::Bfs,
+            balance: exact,
+        }
+        .partition(&g, &hardware)?,
+    ] {
+        assert_eq!(pgraph.core_costs(&partition.assignment, 2), vec![12, 12]);
+    }
+    Ok(())
+}
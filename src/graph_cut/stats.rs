@@ -0,0 +1,422 @@
+use super::{DataMappingError, HardwareConfig, Hypergraph, Partition, WalkerTraces};
+use crate::base_type::Size;
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// What one DPU holds under a partition.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoreStats {
+    /// Bytes of the nodes, copies included, and of the edges stored on the DPU.
+    pub bytes: i64,
+    /// Compute cost of the nodes whose primary copy is on the DPU, a resource apart from
+    /// the bytes.
+    pub cost: i64,
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+/// Expected DPU changes along the predicted paths of a walker.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalkerJumps {
+    pub walker: String,
+    pub start: String,
+    pub expected_jumps: f64,
+}
+
+/// Quality of a partition, measured on the graph it maps rather than as reported by the
+/// partitioner, so that partitions from any source can be compared.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartitionStats {
+    pub partitioner: String,
+    /// Bytes available on each DPU.
+    pub capacity: i64,
+    pub cores: Vec<CoreStats>,
+    /// DPUs holding at least one node.
+    pub cores_used: usize,
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// Edges whose endpoints have no copy on a common DPU.
+    pub cut_edges: usize,
+    pub cut_weight: i64,
+    /// Cut weight of the edges of each type.
+    pub cut_by_edge_type: BTreeMap<String, i64>,
+    /// Connectivity-minus-one of the walker-step hypergraph: the weight of every node's net
+    /// of outgoing edges times the other DPUs it reaches. Unlike `cut_weight`, several edges
+    /// from a node into the same DPU count once.
+    pub connectivity: i64,
+    /// Bytes on the fullest DPU over the average bytes per DPU, minus one.
+    pub imbalance: f64,
+    /// Cost of the busiest DPU over the average cost per DPU, minus one.
+    pub cost_imbalance: f64,
+    pub walker_jumps: Vec<WalkerJumps>,
+}
+
+impl PartitionStats {
+    pub fn new(
+        graph: &SemanticGraph,
+        partition: &Partition,
+        hardware: &HardwareConfig,
+        traces: &WalkerTraces,
+    ) -> Result<PartitionStats> {
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+        let assignment = &partition.assignment;
+        let mut copies: Vec<Vec<usize>> = vec![vec![]; graph.node_insts.len()];
+        for replicas in &partition.replicas {
+            copies[replicas.node] = replicas.cores.clone();
+        }
+        let holds =
+            |core: usize, node: usize| assignment[node] == core || copies[node].contains(&core);
+
+        let mut cores = vec![
+            CoreStats {
+                bytes: 0,
+                cost: 0,
+                nodes: 0,
+                edges: 0,
+            };
+            partition.core_num
+        ];
+        for (node, inst) in graph.node_insts.iter().enumerate() {
+            cores[assignment[node]].cost += inst.cost();
+            for core in std::iter::once(assignment[node]).chain(copies[node].iter().copied()) {
+                cores[core].bytes += inst.node_type.size_byte();
+                cores[core].nodes += 1;
+            }
+        }
+        let (mut cut_edges, mut cut_weight) = (0, 0);
+        let mut cut_by_edge_type = BTreeMap::new();
+        let edge_cores = partition.edge_cores(graph, hardware.edge_placement)?;
+        for (edge, stored_on) in graph.edge_insts.iter().zip(edge_cores) {
+            let (from, to) = (find(&edge.from_var.varname)?, find(&edge.to_var.varname)?);
+            let (from_core, to_core) = (assignment[from], assignment[to]);
+            if !holds(from_core, to) && !holds(to_core, from) {
+                cut_edges += 1;
+                cut_weight += edge.weight;
+                *cut_by_edge_type
+                    .entry(edge.edge_type.named_block.name.clone())
+                    .or_insert(0) += edge.weight;
+            }
+            for core in stored_on {
+                cores[core].bytes += edge.edge_type.named_block.size_byte();
+                cores[core].edges += 1;
+            }
+        }
+
+        // As for the cut, a pin sharing a DPU with a copy of the source, or with a copy on
+        // the source's DPU, is reached without a jump.
+        let connectivity = Hypergraph::from_semantic(graph)?
+            .nets
+            .iter()
+            .map(|net| {
+                let source_core = assignment[net.source];
+                let mut reached: Vec<usize> = net
+                    .pins
+                    .iter()
+                    .map(|pin| {
+                        if holds(source_core, *pin) || holds(assignment[*pin], net.source) {
+                            source_core
+                        } else {
+                            assignment[*pin]
+                        }
+                    })
+                    .collect();
+                reached.sort_unstable();
+                reached.dedup();
+                net.weight * (reached.len() as i64 - 1)
+            })
+            .sum();
+
+        let imbalance_of = |amount: &dyn Fn(&CoreStats) -> i64| {
+            let total: i64 = cores.iter().map(amount).sum();
+            let fullest = cores.iter().map(amount).max().unwrap_or(0);
+            if total > 0 {
+                fullest as f64 * cores.len() as f64 / total as f64 - 1.0
+            } else {
+                0.0
+            }
+        };
+        let imbalance = imbalance_of(&|core| core.bytes);
+        let cost_imbalance = imbalance_of(&|core| core.cost);
+        let walker_jumps = graph
+            .walker_insts
+            .iter()
+            .zip(traces.expected_jumps(assignment))
+            .map(|(walker, expected_jumps)| WalkerJumps {
+                walker: walker.walker_type.name.clone(),
+                start: walker.start_node.varname.clone(),
+                expected_jumps,
+            })
+            .collect();
+        Ok(PartitionStats {
+            partitioner: partition.stats.partitioner.to_string(),
+            capacity: hardware.core_size,
+            cores_used: cores.iter().filter(|core| core.nodes > 0).count(),
+            cores,
+            node_count: graph.node_insts.len(),
+            edge_count: graph.edge_insts.len(),
+            cut_edges,
+            cut_weight,
+            cut_by_edge_type,
+            connectivity,
+            imbalance,
+            cost_imbalance,
+            walker_jumps,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// A text table of the used DPUs followed by the totals.
+impl fmt::Display for PartitionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>12} {:>7} {:>12} {:>7} {:>7}",
+            "DPU", "bytes", "used", "cost", "nodes", "edges"
+        )?;
+        for (core, stats) in self.cores.iter().enumerate() {
+            if stats.nodes > 0 {
+                writeln!(
+                    f,
+                    "{:>6} {:>12} {:>6.1}% {:>12} {:>7} {:>7}",
+                    core,
+                    stats.bytes,
+                    100.0 * stats.bytes as f64 / self.capacity as f64,
+                    stats.cost,
+                    stats.nodes,
+                    stats.edges
+                )?;
+            }
+        }
+        writeln!(
+            f,
+            "{} of {} DPUs used, {} nodes, {} edges, load imbalance {:.3}, cost imbalance {:.3}",
+            self.cores_used,
+            self.cores.len(),
+            self.node_count,
+            self.edge_count,
+            self.imbalance,
+            self.cost_imbalance
+        )?;
+        write!(
+            f,
+            "Cut {} edges of weight {}",
+            self.cut_edges, self.cut_weight
+        )?;
+        for (edge_type, weight) in &self.cut_by_edge_type {
+            write!(f, ", {} {}", edge_type, weight)?;
+        }
+        write!(
+            f,
+            "\nConnectivity-minus-one {} against pairwise cut weight {}",
+            self.connectivity, self.cut_weight
+        )?;
+        for jumps in &self.walker_jumps {
+            write!(
+                f,
+                "\nWalker {} on {}: {:.2} expected DPU jumps",
+                jumps.walker, jumps.start, jumps.expected_jumps
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_partition_stats() -> Result<()> {
+    use super::{MilpPartitioner, Partitioner, ReplicatingPartitioner, TraceOptions};
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+    use crate::sem_type::ReplicaMode;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // Six 4-byte nodes in a chain, cut once into halves on 3 DPUs of 12 bytes.
+    let config = GeneratorConfig {
+        kind: GraphKind::Chain,
+        nodes: 6,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let hardware = HardwareConfig {
+        core_size: 12,
+        core_num: 3,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let traces = WalkerTraces::sample(&g, &TraceOptions::default(), 0)?;
+    let partition = MilpPartitioner::default().partition(&g, &hardware)?;
+    let stats = PartitionStats::new(&g, &partition, &hardware, &traces)?;
+    assert_eq!(stats.cut_weight, partition.stats.cut_weight);
+    assert_eq!(stats.cut_edges, 1);
+    assert_eq!(
+        stats.cut_by_edge_type,
+        BTreeMap::from([("Link".to_string(), 1)])
+    );
+    assert_eq!(stats.cores_used, 2);
+    assert_eq!((stats.node_count, stats.edge_count), (6, 5));
+    assert_eq!(stats.cores.iter().map(|core| core.bytes).sum::<i64>(), 24);
+    assert!((stats.imbalance - 0.5).abs() < 1e-9);
+    assert_eq!(stats.connectivity, 1);
+    assert!(stats.to_json()?.contains("\"cut_weight\": 1"));
+
+    // A replicated hub adds a copy to every DPU with one of its neighbors, and no cut.
+    let roomy = HardwareConfig {
+        core_size: 16,
+        ..hardware
+    };
+    let source = "
+        node Leaf { n1: int32; };
+        edge Leaf Leaf Link {};
+        walker Leaf Visitor;
+        graph {
+            node Leaf n0, n1, n2, n3, n4, n5, n6;
+            edge Link n0 n1 1;
+            edge Link n0 n2 1;
+            edge Link n0 n3 1;
+            edge Link n0 n4 1;
+            edge Link n0 n5 1;
+            edge Link n0 n6 1;
+            walker Visitor on n0;
+        };
+    ";
+    let star = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let star_traces = WalkerTraces::sample(&star, &TraceOptions::default(), 0)?;
+    let partition = ReplicatingPartitioner {
+        inner: Box::new(MilpPartitioner::default()),
+        degree_threshold: Some(3),
+        mode: ReplicaMode::ReadOnly,
+    }
+    .partition(&star, &roomy)?;
+    let stats = PartitionStats::new(&star, &partition, &roomy, &star_traces)?;
+    assert_eq!((stats.cut_weight, stats.connectivity), (0, 0));
+    // The walker visits the hub, then its leaves, which do not all fit on one DPU.
+    let path = &star_traces.paths[0][0];
+    assert_eq!(path.len(), 7);
+    let jumps = path
+        .windows(2)
+        .filter(|step| partition.assignment[step[0]] != partition.assignment[step[1]])
+        .count();
+    assert!(jumps >= 1);
+    assert_eq!(
+        stats.walker_jumps,
+        vec![WalkerJumps {
+            walker: "Visitor".to_string(),
+            start: "n0".to_string(),
+            expected_jumps: jumps as f64,
+        }]
+    );
+    assert_eq!(
+        stats.cores.iter().map(|core| core.nodes).sum::<usize>(),
+        7 + partition.replicas[0].cores.len()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_edge_placement() -> Result<()> {
+    use super::{EdgePlacement, MilpPartitioner, Partitioner, TraceOptions};
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // A hub with four 4-byte edges to 4-byte leaves, on 2 DPUs of 20 bytes: the edges fit
+    // when stored once, with the hub or with the leaves, but not duplicated when cut.
+    let source = "
+        node Leaf { n1: int32; };
+        edge Leaf Leaf Link { n1: int32; };
+        graph {
+            node Leaf hub, a, b, c, d;
+            edge Link hub a 1;
+            edge Link hub b 1;
+            edge Link hub c 1;
+            edge Link hub d 1;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let traces = WalkerTraces::sample(&g, &TraceOptions::default(), 0)?;
+    for placement in [EdgePlacement::Source, EdgePlacement::Destination] {
+        let hardware = HardwareConfig {
+            core_size: 20,
+            core_num: 2,
+            rank_size: None,
+            edge_placement: placement,
+        };
+        let partition = MilpPartitioner::default().partition(&g, &hardware)?;
+        let stats = PartitionStats::new(&g, &partition, &hardware, &traces)?;
+        assert!(stats.cores.iter().all(|core| core.bytes <= 20));
+        assert_eq!(stats.cores.iter().map(|core| core.bytes).sum::<i64>(), 36);
+        assert_eq!(stats.cores.iter().map(|core| core.edges).sum::<usize>(), 4);
+    }
+    let hardware = HardwareConfig {
+        core_size: 20,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: EdgePlacement::Both,
+    };
+    assert!(MilpPartitioner::default().partition(&g, &hardware).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_cost_dimension() -> Result<()> {
+    use super::TraceOptions;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+    use std::time::Duration;
+
+    // The vector nodes cost what their type or instance declares, the scalars their size.
+    let source = "
+        @cost(10) node Vec { v: int32[128]; };
+        node Scalar { n: int32; };
+        edge Scalar Scalar Link {};
+        graph {
+            node Vec v0;
+            @cost(30) node Vec v1;
+            node Scalar s0, s1;
+            edge Link s0 s1 1;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let costs: Vec<i64> = g.node_insts.iter().map(|node| node.cost()).collect();
+    assert_eq!(costs, vec![10, 30, 4, 4]);
+
+    let hardware = HardwareConfig {
+        core_size: 1024,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = Partition::new(
+        &g.node_insts,
+        &g.edge_insts,
+        vec![0, 1, 0, 0],
+        2,
+        "manual",
+        Duration::ZERO,
+    )?;
+    let traces = WalkerTraces::sample(&g, &TraceOptions::default(), 0)?;
+    let stats = PartitionStats::new(&g, &partition, &hardware, &traces)?;
+    let by_core: Vec<(i64, i64)> = stats
+        .cores
+        .iter()
+        .map(|core| (core.bytes, core.cost))
+        .collect();
+    assert_eq!(by_core, vec![(520, 18), (512, 30)]);
+    assert!((stats.cost_imbalance - 0.25).abs() < 1e-9);
+
+    let negative = source.replace("@cost(30)", "@cost(-1)");
+    assert!(semantic_analysis(parse_str(&negative)?).is_err());
+    Ok(())
+}
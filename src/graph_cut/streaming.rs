@@ -0,0 +1,686 @@
+use super::graph::PartitionGraph;
+use super::{
+    DataMappingError, EdgePlacement, HardwareConfig, Partition, Partitioner, SolveStats,
+    SolveStatus,
+};
+use crate::base_type::Size;
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::rc::Rc;
+use std::time::Instant;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("Line {0} of the edge list is malformed: `{1}`")]
+    MalformedLine(usize, String),
+}
+
+/// Scoring rule used to place a node from the neighbors already placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StreamHeuristic {
+    /// Linear Deterministic Greedy: neighbor weight scaled by the free fraction of the core
+    Ldg,
+    /// Fennel: neighbor weight minus a superlinear penalty on the nodes already on the core
+    Fennel,
+}
+
+/// One edge as read from a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEdge {
+    pub from: String,
+    pub to: String,
+    pub weight: i64,
+}
+
+/// Reads a whitespace separated edge list, one `from to [weight]` per line. Blank lines
+/// and lines starting with `#` or `%` are skipped; the weight defaults to 1.
+pub struct EdgeListReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line_no: usize,
+}
+
+impl<R: BufRead> EdgeListReader<R> {
+    pub fn new(reader: R) -> EdgeListReader<R> {
+        EdgeListReader {
+            lines: reader.lines(),
+            line_no: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for EdgeListReader<R> {
+    type Item = Result<StreamEdge>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line_no += 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let weight = match fields.get(2).map(|w| w.parse::<i64>()) {
+                None => Some(1),
+                Some(Ok(weight)) => Some(weight),
+                Some(Err(_)) => None,
+            };
+            return Some(match (fields.len(), weight) {
+                (2 | 3, Some(weight)) => Ok(StreamEdge {
+                    from: fields[0].to_string(),
+                    to: fields[1].to_string(),
+                    weight,
+                }),
+                _ => Err(StreamError::MalformedLine(self.line_no, line.to_string()).into()),
+            });
+        }
+    }
+}
+
+/// One-pass partitioner for graphs too large to load. Consecutive edges sharing a source
+/// are taken together and the source is placed, for good, on the best core given its
+/// neighbors placed so far. Edges to nodes not placed yet are remembered on those nodes
+/// until they show up as a source themselves, or the stream ends. Nodes are charged
+/// `node_size` bytes and edges `edge_size` bytes, following the same capacity model as the
+/// other partitioners.
+pub struct StreamingPartitioner {
+    pub heuristic: StreamHeuristic,
+    pub node_size: i64,
+    pub edge_size: i64,
+    /// Node and edge counts used by Fennel's balance term. When unknown, the counts seen so
+    /// far are used instead.
+    pub expected_nodes: Option<usize>,
+    pub expected_edges: Option<usize>,
+}
+
+impl Default for StreamingPartitioner {
+    fn default() -> Self {
+        StreamingPartitioner {
+            heuristic: StreamHeuristic::Fennel,
+            node_size: 0,
+            edge_size: 0,
+            expected_nodes: None,
+            expected_edges: None,
+        }
+    }
+}
+
+/// Exponent of Fennel's balance penalty.
+const FENNEL_GAMMA: f64 = 1.5;
+
+/// Segment tree over core loads answering "which core is least loaded" in O(1).
+struct MinLoadTree {
+    leaves: usize,
+    tree: Vec<(i64, usize)>,
+}
+
+impl MinLoadTree {
+    fn new(core_num: usize) -> MinLoadTree {
+        let leaves = core_num.next_power_of_two();
+        let mut tree = vec![(i64::MAX, usize::MAX); 2 * leaves];
+        for core in 0..core_num {
+            tree[leaves + core] = (0, core);
+        }
+        for i in (1..leaves).rev() {
+            tree[i] = tree[2 * i].min(tree[2 * i + 1]);
+        }
+        MinLoadTree { leaves, tree }
+    }
+
+    fn update(&mut self, core: usize, load: i64) {
+        let mut i = self.leaves + core;
+        self.tree[i] = (load, core);
+        while i > 1 {
+            i /= 2;
+            self.tree[i] = self.tree[2 * i].min(self.tree[2 * i + 1]);
+        }
+    }
+
+    fn min_core(&self) -> usize {
+        self.tree[1].1
+    }
+}
+
+/// Placement state of a running stream.
+struct StreamState<'a> {
+    partitioner: &'a StreamingPartitioner,
+    hardware: &'a HardwareConfig,
+    /// Interned node names, shared between the lookup table and the list in order of first
+    /// appearance.
+    index: HashMap<Rc<str>, usize>,
+    names: Vec<Rc<str>>,
+    assignment: Vec<Option<usize>>,
+    /// Edges seen from placed nodes to each unplaced node, summed per core of the placed end,
+    /// as (core, weight, bytes on the node's core if cut, bytes on the node's core in any
+    /// case). At most one entry per core, however many edges point at the node.
+    pending: Vec<Vec<(usize, i64, i64, i64)>>,
+    loads: Vec<i64>,
+    counts: Vec<usize>,
+    min_load: MinLoadTree,
+    edges_seen: usize,
+    cut_weight: i64,
+    /// Scratch per-core state of the node being placed, reset after every placement:
+    /// neighbor weight, bytes charged to the core if the node goes elsewhere, and bytes
+    /// already charged to the core if the node goes there.
+    affinity: Vec<i64>,
+    external: Vec<i64>,
+    discount: Vec<i64>,
+    touched: Vec<usize>,
+    is_touched: Vec<bool>,
+    /// Placement constraints of an in-memory graph, as its constrained graph and the node
+    /// of that graph holding each interned node, with the core each of those is on.
+    constraints: Option<(PartitionGraph, Vec<usize>)>,
+    group_core: Vec<Option<usize>>,
+}
+
+impl<'a> StreamState<'a> {
+    fn new(partitioner: &'a StreamingPartitioner, hardware: &'a HardwareConfig) -> StreamState<'a> {
+        let core_num = hardware.core_num as usize;
+        StreamState {
+            partitioner,
+            hardware,
+            index: HashMap::new(),
+            names: vec![],
+            assignment: vec![],
+            pending: vec![],
+            loads: vec![0; core_num],
+            counts: vec![0; core_num],
+            min_load: MinLoadTree::new(core_num),
+            edges_seen: 0,
+            cut_weight: 0,
+            affinity: vec![0; core_num],
+            external: vec![0; core_num],
+            discount: vec![0; core_num],
+            touched: vec![],
+            is_touched: vec![false; core_num],
+            constraints: None,
+            group_core: vec![],
+        }
+    }
+
+    /// Whether `node` may go on `core` without breaking a placement constraint.
+    fn allowed(&self, node: usize, core: usize) -> bool {
+        let Some((graph, group)) = &self.constraints else {
+            return true;
+        };
+        let g = group[node];
+        self.group_core[g].is_none_or(|placed| placed == core)
+            && graph.allowed(g, core, |other| self.group_core[other])
+    }
+
+    /// Whether a placement constraint involves `node`.
+    fn is_constrained(&self, node: usize) -> bool {
+        self.constraints.as_ref().is_some_and(|(graph, group)| {
+            let g = group[node];
+            graph.fixed[g].is_some()
+                || !graph.separated[g].is_empty()
+                || self.group_core[g].is_some()
+        })
+    }
+
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(node) = self.index.get(name) {
+            return *node;
+        }
+        let node = self.names.len();
+        let name: Rc<str> = Rc::from(name);
+        self.index.insert(Rc::clone(&name), node);
+        self.names.push(name);
+        self.assignment.push(None);
+        self.pending.push(vec![]);
+        node
+    }
+
+    fn charge(&mut self, core: usize, bytes: i64, node: usize) -> Result<()> {
+        self.loads[core] += bytes;
+        self.min_load.update(core, self.loads[core]);
+        if self.loads[core] > self.hardware.core_size {
+            return Err(DataMappingError::CapacityExceeded(self.names[node].to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// The bytes of an edge of `size` on the core of its source, on the core of its target
+    /// when the edge is cut, and on the core of its target in any case.
+    fn split(&self, size: i64) -> (i64, i64, i64) {
+        match self.hardware.edge_placement {
+            EdgePlacement::Source => (size, 0, 0),
+            EdgePlacement::Destination => (0, 0, size),
+            EdgePlacement::Both => (size, size, 0),
+        }
+    }
+
+    fn touch(&mut self, core: usize, weight: i64, external: i64, discount: i64) {
+        if !self.is_touched[core] {
+            self.is_touched[core] = true;
+            self.touched.push(core);
+        }
+        self.affinity[core] += weight;
+        self.external[core] += external;
+        self.discount[core] += discount;
+    }
+
+    fn score(&self, core: usize) -> f64 {
+        let weight = self.affinity[core] as f64;
+        match self.partitioner.heuristic {
+            StreamHeuristic::Ldg => {
+                weight * (1.0 - self.loads[core] as f64 / self.hardware.core_size as f64)
+            }
+            StreamHeuristic::Fennel => {
+                let k = self.hardware.core_num as f64;
+                let n = self
+                    .partitioner
+                    .expected_nodes
+                    .unwrap_or(self.names.len())
+                    .max(1) as f64;
+                let m = self
+                    .partitioner
+                    .expected_edges
+                    .unwrap_or(self.edges_seen)
+                    .max(1) as f64;
+                let alpha = k.sqrt() * m / n.powf(1.5);
+                weight - alpha * FENNEL_GAMMA * (self.counts[core] as f64).powf(FENNEL_GAMMA - 1.0)
+            }
+        }
+    }
+
+    /// Pick a core among the touched cores and the least loaded one, for `node` costing
+    /// `own` bytes; any core the constraints allow when they involve the node. Clears the
+    /// scratch state.
+    fn choose(&mut self, node: usize, own: i64) -> Option<usize> {
+        let capacity = self.hardware.core_size;
+        let overflowing: Vec<usize> = self
+            .touched
+            .iter()
+            .copied()
+            .filter(|j| self.loads[*j] + self.external[*j] > capacity)
+            .collect();
+        let neutral = self.min_load.min_core();
+        let mut candidates = self.touched.clone();
+        if !self.is_touched[neutral] {
+            candidates.push(neutral);
+        }
+        if self.is_constrained(node) {
+            for core in 0..self.loads.len() {
+                if !candidates.contains(&core) {
+                    candidates.push(core);
+                }
+            }
+        }
+        let mut best: Option<(f64, usize)> = None;
+        for core in candidates {
+            if self.loads[core] + own - self.discount[core] > capacity
+                || overflowing.iter().any(|j| *j != core)
+                || !self.allowed(node, core)
+            {
+                continue;
+            }
+            let score = self.score(core);
+            let better = best.is_none_or(|(s, c)| {
+                score > s || (score == s && (self.loads[core], core) < (self.loads[c], c))
+            });
+            if better {
+                best = Some((score, core));
+            }
+        }
+        for core in self.touched.drain(..) {
+            self.affinity[core] = 0;
+            self.external[core] = 0;
+            self.discount[core] = 0;
+            self.is_touched[core] = false;
+        }
+        best.map(|(_, core)| core)
+    }
+
+    /// Place `node`, which costs `own` bytes on its core on top of its size and pending edges, and
+    /// settle its pending edges. The caller has touched the cores of any other neighbors.
+    fn place(&mut self, node: usize, own: i64, node_size: i64) -> Result<usize> {
+        // A pending edge is already charged to the other endpoint's core.
+        let pending = std::mem::take(&mut self.pending[node]);
+        let mut own = own + node_size;
+        for (core, weight, if_cut, always) in &pending {
+            own += if_cut + always;
+            self.touch(*core, *weight, 0, *if_cut);
+        }
+        let core = self
+            .choose(node, own)
+            .ok_or_else(|| DataMappingError::CapacityExceeded(self.names[node].to_string()))?;
+        self.assignment[node] = Some(core);
+        if let Some((_, group)) = &self.constraints {
+            self.group_core[group[node]] = Some(core);
+        }
+        self.counts[core] += 1;
+        self.charge(core, node_size, node)?;
+        for (other, weight, if_cut, always) in pending {
+            self.charge(core, always, node)?;
+            if other != core {
+                self.charge(core, if_cut, node)?;
+                self.cut_weight += weight;
+            }
+        }
+        Ok(core)
+    }
+
+    /// Process consecutive edges sharing the source `from`, given as (to, weight, size).
+    fn push_group(
+        &mut self,
+        from: &str,
+        group: &[(String, i64, i64)],
+        node_size: &impl Fn(&str) -> i64,
+    ) -> Result<()> {
+        let source = self.intern(from);
+        let targets: Vec<usize> = group.iter().map(|(to, _, _)| self.intern(to)).collect();
+        let core = match self.assignment[source] {
+            Some(core) => core,
+            None => {
+                // The source's share of every edge of the group is charged to its core,
+                // and the target's share of edges to placed nodes to their cores.
+                let mut own = 0;
+                for (target, (_, weight, size)) in targets.iter().zip(group) {
+                    let (at_source, if_cut, always) = self.split(*size);
+                    own += at_source;
+                    match self.assignment[*target] {
+                        _ if *target == source => own += always,
+                        Some(core) => self.touch(core, *weight, if_cut, 0),
+                        None => {}
+                    }
+                }
+                self.place(source, own, node_size(from))?
+            }
+        };
+        for (target, (_, weight, size)) in targets.into_iter().zip(group) {
+            let (at_source, if_cut, always) = self.split(*size);
+            self.edges_seen += 1;
+            self.charge(core, at_source, source)?;
+            if target == source {
+                self.charge(core, always, source)?;
+                continue;
+            }
+            match self.assignment[target] {
+                Some(target_core) if target_core != core => {
+                    self.charge(target_core, if_cut + always, target)?;
+                    self.cut_weight += weight;
+                }
+                Some(_) => self.charge(core, always, target)?,
+                None => self.defer(target, core, *weight, if_cut, always),
+            }
+        }
+        Ok(())
+    }
+
+    /// Record an edge from a node placed on `core` to the unplaced `node`.
+    fn defer(&mut self, node: usize, core: usize, weight: i64, if_cut: i64, always: i64) {
+        let pending = &mut self.pending[node];
+        match pending.iter_mut().find(|(other, ..)| *other == core) {
+            Some((_, w, c, a)) => {
+                *w += weight;
+                *c += if_cut;
+                *a += always;
+            }
+            None => pending.push((core, weight, if_cut, always)),
+        }
+    }
+
+    /// Place the nodes that never appeared as a source, in order of first appearance.
+    fn finish(&mut self, node_size: &impl Fn(&str) -> i64) -> Result<()> {
+        for node in 0..self.names.len() {
+            if self.assignment[node].is_none() {
+                let size = node_size(&self.names[node]);
+                self.place(node, 0, size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StreamingPartitioner {
+    /// Place `edges` in a single pass. Edge weights and sizes are given per edge, node
+    /// sizes by `node_size`.
+    fn run(
+        &self,
+        state: &mut StreamState,
+        edges: impl IntoIterator<Item = Result<(StreamEdge, i64)>>,
+        node_size: &impl Fn(&str) -> i64,
+    ) -> Result<()> {
+        let mut group: Vec<(String, i64, i64)> = vec![];
+        let mut source: Option<String> = None;
+        for edge in edges {
+            let (edge, size) = edge?;
+            if source.as_ref() != Some(&edge.from) {
+                if let Some(from) = source.take() {
+                    state.push_group(&from, &group, node_size)?;
+                    group.clear();
+                }
+                source = Some(edge.from);
+            }
+            group.push((edge.to, edge.weight, size));
+        }
+        if let Some(from) = source {
+            state.push_group(&from, &group, node_size)?;
+        }
+        state.finish(node_size)
+    }
+
+    /// Partition an edge stream, e.g. an `EdgeListReader`. Nodes appear in the result in
+    /// the order they were first seen.
+    pub fn partition_stream(
+        &self,
+        edges: impl IntoIterator<Item = Result<StreamEdge>>,
+        hardware: &HardwareConfig,
+    ) -> Result<Partition> {
+        let start = Instant::now();
+        let mut state = StreamState::new(self, hardware);
+        let edges = edges
+            .into_iter()
+            .map(|edge| edge.map(|edge| (edge, self.edge_size)));
+        self.run(&mut state, edges, &|_| self.node_size)?;
+        // Free the lookup table first, so that each name is copied out as it is dropped.
+        drop(state.index);
+        Ok(Partition {
+            nodes: state
+                .names
+                .into_iter()
+                .map(|name| name.to_string())
+                .collect(),
+            assignment: state.assignment.into_iter().flatten().collect(),
+            core_num: hardware.core_num as usize,
+            replicas: vec![],
+            stats: SolveStats {
+                partitioner: self.name(),
+                elapsed: start.elapsed(),
+                cut_weight: state.cut_weight,
+                status: SolveStatus::Unknown,
+            },
+        })
+    }
+}
+
+impl Partitioner for StreamingPartitioner {
+    fn name(&self) -> &'static str {
+        match self.heuristic {
+            StreamHeuristic::Ldg => "ldg",
+            StreamHeuristic::Fennel => "fennel",
+        }
+    }
+
+    /// Stream the edges of an in-memory graph in declaration order, using the sizes of the
+    /// node and edge types rather than `node_size` and `edge_size`.
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        let partitioner = StreamingPartitioner {
+            expected_nodes: Some(graph.node_insts.len()),
+            expected_edges: Some(graph.edge_insts.len()),
+            ..*self
+        };
+        let sizes: HashMap<&str, i64> = graph
+            .node_insts
+            .iter()
+            .map(|node| (node.varname.as_str(), node.node_type.size_byte()))
+            .collect();
+        let node_size = |name: &str| sizes.get(name).copied().unwrap_or(0);
+        let mut state = StreamState::new(&partitioner, hardware);
+        let edges = graph.edge_insts.iter().map(|edge| {
+            Ok((
+                StreamEdge {
+                    from: edge.from_var.varname.clone(),
+                    to: edge.to_var.varname.clone(),
+                    weight: edge.weight,
+                },
+                edge.edge_type.named_block.size_byte(),
+            ))
+        });
+        for node in &graph.node_insts {
+            state.intern(&node.varname);
+        }
+        if !graph.constraints.is_empty() {
+            let (cgraph, group) = PartitionGraph::constrained(graph, hardware)?;
+            state.group_core = vec![None; cgraph.node_count()];
+            state.constraints = Some((cgraph, group));
+        }
+        partitioner.run(&mut state, edges, &node_size)?;
+        let assignment = graph
+            .node_insts
+            .iter()
+            .map(|node| state.assignment[state.index[node.varname.as_str()]].unwrap_or(0))
+            .collect();
+        Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            assignment,
+            hardware.core_num as usize,
+            self.name(),
+            start.elapsed(),
+        )
+    }
+}
+
+#[test]
+fn test_edge_list_reader() {
+    let input = "# comment\n% comment\n\na b\nb c 7\nc\n";
+    let edges: Vec<Result<StreamEdge>> = EdgeListReader::new(input.as_bytes()).collect();
+    assert_eq!(edges.len(), 3);
+    assert_eq!(
+        edges[1].as_ref().unwrap(),
+        &StreamEdge {
+            from: "b".to_string(),
+            to: "c".to_string(),
+            weight: 7,
+        }
+    );
+    assert_eq!(edges[0].as_ref().unwrap().weight, 1);
+    assert!(edges[2].is_err());
+}
+
+#[test]
+fn test_streaming_grid() -> Result<()> {
+    use crate::generator::{default_types, generate_graph, GeneratorConfig, GraphKind};
+
+    let config = GeneratorConfig {
+        kind: GraphKind::Grid,
+        nodes: 1024,
+        ..Default::default()
+    };
+    let g = generate_graph(&config, &default_types())?;
+    let pgraph = PartitionGraph::from_semantic(&g)?;
+    let text: String = g
+        .edge_insts
+        .iter()
+        .map(|edge| {
+            format!(
+                "{} {} {}\n",
+                edge.from_var.varname, edge.to_var.varname, edge.weight
+            )
+        })
+        .collect();
+    let hardware = HardwareConfig {
+        core_size: 4 * 300,
+        core_num: 4,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    // Round robin placement cuts every horizontal edge of the grid.
+    let round_robin: Vec<usize> = (0..pgraph.node_count()).map(|node| node % 4).collect();
+    for heuristic in [StreamHeuristic::Ldg, StreamHeuristic::Fennel] {
+        let partitioner = StreamingPartitioner {
+            heuristic,
+            node_size: 4,
+            expected_nodes: Some(g.node_insts.len()),
+            expected_edges: Some(g.edge_insts.len()),
+            ..Default::default()
+        };
+        let streamed =
+            partitioner.partition_stream(EdgeListReader::new(text.as_bytes()), &hardware)?;
+        // The generator declares edges by source, in node order.
+        assert_eq!(streamed.nodes[0], "n0");
+        let order: HashMap<&str, usize> = streamed
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+        let assignment: Vec<usize> = g
+            .node_insts
+            .iter()
+            .map(|node| streamed.assignment[order[node.varname.as_str()]])
+            .collect();
+        assert_eq!(streamed.stats.cut_weight, pgraph.cut_weight(&assignment));
+        assert!(streamed.stats.cut_weight * 3 < pgraph.cut_weight(&round_robin));
+        assert!(pgraph
+            .core_loads(&assignment, 4)
+            .iter()
+            .all(|load| *load <= hardware.core_size));
+
+        let in_memory = partitioner.partition(&g, &hardware)?;
+        assert_eq!(in_memory.stats.cut_weight, streamed.stats.cut_weight);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pending_edges_are_summed_per_core() -> Result<()> {
+    // Every leaf points at the hub, which is only placed once the stream ends.
+    let text: String = (0..64).map(|leaf| format!("l{} hub 2\n", leaf)).collect();
+    let hardware = HardwareConfig {
+        core_size: 4 * 100,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partitioner = StreamingPartitioner {
+        node_size: 4,
+        ..Default::default()
+    };
+    let mut state = StreamState::new(&partitioner, &hardware);
+    let edges = EdgeListReader::new(text.as_bytes())
+        .map(|edge| edge.map(|edge| (edge, partitioner.edge_size)));
+    let mut group = vec![];
+    for edge in edges {
+        let (edge, size) = edge?;
+        group.clear();
+        group.push((edge.to, edge.weight, size));
+        state.push_group(&edge.from, &group, &|_| partitioner.node_size)?;
+    }
+    let hub = state.index["hub"];
+    assert!(state.pending[hub].len() <= 2);
+    assert_eq!(
+        state.pending[hub]
+            .iter()
+            .map(|(_, weight, ..)| weight)
+            .sum::<i64>(),
+        2 * 64
+    );
+    state.finish(&|_| partitioner.node_size)?;
+    let core = state.assignment[hub].unwrap();
+    let cut = (0..64)
+        .filter(|leaf| state.assignment[state.index[format!("l{}", leaf).as_str()]] != Some(core))
+        .count() as i64;
+    assert_eq!(state.cut_weight, 2 * cut);
+    Ok(())
+}
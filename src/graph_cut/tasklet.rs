@@ -0,0 +1,224 @@
+use super::{DataMappingError, Partition};
+use crate::base_type::Size;
+use crate::sem_type::SemanticGraph;
+use anyhow::Result;
+
+/// Most tasklets a DPU runs.
+pub const MAX_TASKLETS: usize = 24;
+
+/// Alignment of MRAM transfers and of every node in MRAM, in bytes.
+pub const MRAM_ALIGNMENT: i64 = 8;
+
+/// Most bytes one `mram_read` or `mram_write` moves.
+pub const MAX_MRAM_TRANSFER: i64 = 2048;
+
+/// Command line options for splitting the nodes of every DPU among its tasklets.
+#[derive(Debug, Clone, clap::Args)]
+pub struct TaskletOptions {
+    /// Tasklets per DPU, the `NR_TASKLETS` the DPU binary is built with
+    #[arg(long, default_value_t = 16)]
+    pub tasklets: usize,
+    /// Bytes of WRAM shared by the tasklets of a DPU
+    #[arg(long, default_value_t = 64 * 1024)]
+    pub wram_bytes: i64,
+}
+
+impl Default for TaskletOptions {
+    fn default() -> Self {
+        TaskletOptions {
+            tasklets: 16,
+            wram_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// The nodes held on one DPU, split among its tasklets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DpuTasklets {
+    /// Nodes held on the DPU, copies included, as indices into `SemanticGraph::node_insts`,
+    /// tasklet after tasklet. They lie one after the other in MRAM in this order, at the
+    /// addresses given by `addrs`.
+    pub nodes: Vec<usize>,
+    /// Tasklet `t` runs `nodes[offsets[t]..offsets[t + 1]]`.
+    pub offsets: Vec<usize>,
+    /// Bytes of each node of `nodes`.
+    pub bytes: Vec<i64>,
+    /// Compute cost of the nodes of each tasklet.
+    pub work: Vec<i64>,
+}
+
+/// Second-stage partition of every DPU's nodes across its tasklets. Each DPU is split on
+/// its own: nodes are taken by decreasing cost and given to the tasklet with the least work
+/// so far, so that the tasklets finish together. A tasklet streams its nodes through a
+/// cache of `block` bytes, its whole working set, which fits its share of WRAM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskletPlan {
+    pub tasklets: usize,
+    /// Bytes of WRAM of each tasklet.
+    pub share: i64,
+    /// Bytes of the cache of each tasklet: the largest aligned MRAM transfer within `share`.
+    pub block: i64,
+    pub dpus: Vec<DpuTasklets>,
+}
+
+impl DpuTasklets {
+    /// Offset of each node of `nodes` in the MRAM heap, each padded to `MRAM_ALIGNMENT`, and
+    /// the padded end of the last one.
+    pub fn addrs(&self) -> Vec<i64> {
+        let pad = |bytes: i64| (bytes + MRAM_ALIGNMENT - 1) / MRAM_ALIGNMENT * MRAM_ALIGNMENT;
+        std::iter::once(0)
+            .chain(self.bytes.iter().scan(0, |addr, bytes| {
+                *addr += pad(*bytes);
+                Some(*addr)
+            }))
+            .collect()
+    }
+}
+
+impl TaskletPlan {
+    pub fn new(
+        graph: &SemanticGraph,
+        partition: &Partition,
+        options: &TaskletOptions,
+    ) -> Result<TaskletPlan> {
+        let tasklets = options.tasklets;
+        if !(1..=MAX_TASKLETS).contains(&tasklets) {
+            return Err(DataMappingError::TaskletCount(tasklets).into());
+        }
+        let share = options.wram_bytes / tasklets as i64;
+        let block = share.min(MAX_MRAM_TRANSFER) / MRAM_ALIGNMENT * MRAM_ALIGNMENT;
+        if block == 0 {
+            return Err(DataMappingError::WramExceeded(MRAM_ALIGNMENT, share).into());
+        }
+
+        let mut held = vec![vec![]; partition.core_num];
+        for (node, core) in partition.assignment.iter().enumerate() {
+            held[*core].push(node);
+        }
+        for replicas in &partition.replicas {
+            for core in &replicas.cores {
+                held[*core].push(replicas.node);
+            }
+        }
+        let dpus = held
+            .into_iter()
+            .map(|mut nodes| {
+                let bytes = |node: usize| graph.node_insts[node].node_type.size_byte();
+                let cost = |node: usize| graph.node_insts[node].cost();
+                nodes.sort_by_key(|node| (std::cmp::Reverse(cost(*node)), *node));
+                let mut runs = vec![vec![]; tasklets];
+                let mut work = vec![0; tasklets];
+                for node in nodes {
+                    let tasklet = (0..tasklets)
+                        .min_by_key(|t| (work[*t], runs[*t].len(), *t))
+                        .unwrap();
+                    runs[tasklet].push(node);
+                    work[tasklet] += cost(node);
+                }
+                let mut offsets = vec![0];
+                let mut nodes = vec![];
+                for mut run in runs {
+                    run.sort_unstable();
+                    nodes.extend(run);
+                    offsets.push(nodes.len());
+                }
+                DpuTasklets {
+                    bytes: nodes.iter().map(|node| bytes(*node)).collect(),
+                    nodes,
+                    offsets,
+                    work,
+                }
+            })
+            .collect();
+        Ok(TaskletPlan {
+            tasklets,
+            share,
+            block,
+            dpus,
+        })
+    }
+
+    /// Most nodes held on one DPU.
+    pub fn max_dpu_nodes(&self) -> usize {
+        self.dpus
+            .iter()
+            .map(|dpu| dpu.nodes.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Work of the busiest tasklet over the average work per tasklet, minus one, on the
+    /// DPU where it is highest.
+    pub fn imbalance(&self) -> f64 {
+        self.dpus
+            .iter()
+            .map(|dpu| {
+                let total: i64 = dpu.work.iter().sum();
+                let busiest = dpu.work.iter().copied().max().unwrap_or(0);
+                if total > 0 {
+                    busiest as f64 * self.tasklets as f64 / total as f64 - 1.0
+                } else {
+                    0.0
+                }
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+#[test]
+fn test_tasklet_plan() -> Result<()> {
+    use super::{HardwareConfig, MultilevelPartitioner, Partitioner};
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // One DPU with a heavy node and four light ones, on two tasklets.
+    let source = "
+        node Big { v: int32[8]; };
+        node Small { n1: int32; };
+        edge Small Small Link { w: int32; };
+        graph {
+            node Big b;
+            node Small s0, s1, s2, s3;
+            edge Link s0 s1 1;
+            edge Link s0 s2 1;
+        };
+    ";
+    let graph = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let hardware = HardwareConfig {
+        core_size: 1024,
+        core_num: 1,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let partition = MultilevelPartitioner::default().partition(&graph, &hardware)?;
+    let options = TaskletOptions {
+        tasklets: 2,
+        wram_bytes: 64,
+    };
+    let plan = TaskletPlan::new(&graph, &partition, &options)?;
+    assert_eq!((plan.share, plan.block), (32, 32));
+    let dpu = &plan.dpus[0];
+    assert_eq!(dpu.nodes, vec![0, 1, 2, 3, 4]);
+    assert_eq!(dpu.offsets, vec![0, 1, 5]);
+    assert_eq!(dpu.work, vec![32, 16]);
+    assert!((plan.imbalance() - 1.0 / 3.0).abs() < 1e-9);
+
+    // The cache is at most one MRAM transfer, and at least one aligned one.
+    let roomy = TaskletPlan::new(&graph, &partition, &TaskletOptions::default())?;
+    assert_eq!((roomy.share, roomy.block), (4096, 2048));
+    let odd = TaskletOptions {
+        tasklets: 3,
+        ..options
+    };
+    assert_eq!(TaskletPlan::new(&graph, &partition, &odd)?.block, 16);
+    let tight = TaskletOptions {
+        tasklets: 16,
+        ..options
+    };
+    assert!(TaskletPlan::new(&graph, &partition, &tight).is_err());
+    let many = TaskletOptions {
+        tasklets: 25,
+        ..options
+    };
+    assert!(TaskletPlan::new(&graph, &partition, &many).is_err());
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use super::{DataMappingError, HardwareConfig, Partition, Partitioner};
+use crate::base_type::NamedBlock;
+use crate::sem_type::{SemanticEdge, SemanticEdgeInst, SemanticGraph};
+use anyhow::Result;
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// How the paths of a walker are predicted from its start node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceModel {
+    /// A single depth-limited breadth-first visit, as `bfs_walk` in `evaluation/main.py`
+    Bfs,
+    /// Random walks along outgoing edges, each step choosing an edge uniformly
+    RandomWalk,
+}
+
+/// Command line options for deriving edge weights from walker traces.
+#[derive(Debug, Clone, clap::Args)]
+pub struct TraceOptions {
+    /// Partition on walker traversal frequencies instead of the declared edge weights
+    #[arg(long)]
+    pub trace_weights: bool,
+    /// How walker paths are predicted
+    #[arg(long, value_enum, default_value_t = TraceModel::Bfs)]
+    pub trace_model: TraceModel,
+    /// Depth of the breadth-first visit
+    #[arg(long, default_value_t = 3)]
+    pub trace_depth: usize,
+    /// Steps of every random walk
+    #[arg(long, default_value_t = 16)]
+    pub walk_length: usize,
+    /// Random walks sampled per walker
+    #[arg(long, default_value_t = 100)]
+    pub walk_samples: usize,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        TraceOptions {
+            trace_weights: false,
+            trace_model: TraceModel::Bfs,
+            trace_depth: 3,
+            walk_length: 16,
+            walk_samples: 100,
+        }
+    }
+}
+
+/// Predicted paths of the walkers of a graph, as indices into `SemanticGraph::node_insts`.
+#[derive(Debug, Clone)]
+pub struct WalkerTraces {
+    /// `paths[w]` holds the sampled paths of `SemanticGraph::walker_insts[w]`.
+    pub paths: Vec<Vec<Vec<usize>>>,
+}
+
+impl WalkerTraces {
+    pub fn sample(graph: &SemanticGraph, options: &TraceOptions, seed: u64) -> Result<Self> {
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let find = |name: &String| -> Result<usize> {
+            Ok(*index
+                .get(name.as_str())
+                .ok_or(DataMappingError::NodeNotFound(name.clone()))?)
+        };
+        let mut successors = vec![vec![]; graph.node_insts.len()];
+        for edge in &graph.edge_insts {
+            successors[find(&edge.from_var.varname)?].push(find(&edge.to_var.varname)?);
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let paths = graph
+            .walker_insts
+            .iter()
+            .map(|walker| -> Result<Vec<Vec<usize>>> {
+                let start = find(&walker.start_node.varname)?;
+                Ok(match options.trace_model {
+                    TraceModel::Bfs => vec![bfs_walk(&successors, start, options.trace_depth)],
+                    TraceModel::RandomWalk => (0..options.walk_samples)
+                        .map(|_| random_walk(&successors, start, options.walk_length, &mut rng))
+                        .collect(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(WalkerTraces { paths })
+    }
+
+    /// How often each pair of nodes is visited one right after the other, over all
+    /// sampled paths, keyed by the smaller node first.
+    pub fn transitions(&self) -> HashMap<(usize, usize), i64> {
+        let mut counts = HashMap::new();
+        for path in self.paths.iter().flatten() {
+            for step in path.windows(2) {
+                if step[0] != step[1] {
+                    let key = (step[0].min(step[1]), step[0].max(step[1]));
+                    *counts.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// A copy of `graph` whose edge weights are the traversal counts. Consecutive visits
+    /// between nodes without an edge, as in a breadth-first order, become edges of an
+    /// empty type, so they weigh in the cut without taking any room.
+    pub fn weighted_graph(&self, graph: &SemanticGraph) -> SemanticGraph {
+        let index: HashMap<&str, usize> = graph
+            .node_insts
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.varname.as_str(), i))
+            .collect();
+        let mut counts = self.transitions();
+        let mut edge_insts: Vec<Rc<SemanticEdgeInst>> = graph
+            .edge_insts
+            .iter()
+            .map(|edge| {
+                let (from, to) = (
+                    index[edge.from_var.varname.as_str()],
+                    index[edge.to_var.varname.as_str()],
+                );
+                let key = (from.min(to), from.max(to));
+                Rc::new(SemanticEdgeInst {
+                    edge_type: edge.edge_type.clone(),
+                    from_var: edge.from_var.clone(),
+                    to_var: edge.to_var.clone(),
+                    weight: counts.remove(&key).unwrap_or(0),
+                })
+            })
+            .collect();
+        let mut jumps: Vec<((usize, usize), i64)> = counts.into_iter().collect();
+        jumps.sort_unstable();
+        for ((from, to), weight) in jumps {
+            let (from_var, to_var) = (&graph.node_insts[from], &graph.node_insts[to]);
+            edge_insts.push(Rc::new(SemanticEdgeInst {
+                edge_type: Rc::new(SemanticEdge {
+                    from: from_var.node_type.clone(),
+                    to: to_var.node_type.clone(),
+                    named_block: NamedBlock {
+                        name: String::from("Trace"),
+                        fields: vec![],
+                        cost: None,
+                    },
+                }),
+                from_var: from_var.clone(),
+                to_var: to_var.clone(),
+                weight,
+            }));
+        }
+        SemanticGraph {
+            node_insts: graph.node_insts.clone(),
+            edge_insts,
+            walker_insts: graph.walker_insts.clone(),
+            constraints: graph.constraints.clone(),
+        }
+    }
+
+    /// Average number of DPU changes along the paths of each walker.
+    pub fn expected_jumps(&self, assignment: &[usize]) -> Vec<f64> {
+        self.paths
+            .iter()
+            .map(|paths| {
+                let jumps: usize = paths
+                    .iter()
+                    .map(|path| {
+                        path.windows(2)
+                            .filter(|step| assignment[step[0]] != assignment[step[1]])
+                            .count()
+                    })
+                    .sum();
+                jumps as f64 / paths.len().max(1) as f64
+            })
+            .collect()
+    }
+}
+
+fn bfs_walk(successors: &[Vec<usize>], start: usize, max_depth: usize) -> Vec<usize> {
+    let mut visited = vec![false; successors.len()];
+    visited[start] = true;
+    let mut path = vec![start];
+    let mut queue = VecDeque::from([(start, 0)]);
+    while let Some((node, depth)) = queue.pop_front() {
+        if depth < max_depth {
+            for &next in &successors[node] {
+                if !visited[next] {
+                    visited[next] = true;
+                    path.push(next);
+                    queue.push_back((next, depth + 1));
+                }
+            }
+        }
+    }
+    path
+}
+
+fn random_walk(
+    successors: &[Vec<usize>],
+    start: usize,
+    length: usize,
+    rng: &mut ChaCha8Rng,
+) -> Vec<usize> {
+    let mut path = vec![start];
+    for _ in 0..length {
+        match successors[*path.last().unwrap()].choose(rng) {
+            Some(next) => path.push(*next),
+            None => break,
+        }
+    }
+    path
+}
+
+/// Runs another partitioner on the walker traversal frequencies of the graph rather than
+/// its declared edge weights. The cut weight reported is that of the declared weights.
+pub struct TracePartitioner {
+    pub inner: Box<dyn Partitioner>,
+    pub options: TraceOptions,
+    pub seed: u64,
+}
+
+impl Partitioner for TracePartitioner {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn partition(&self, graph: &SemanticGraph, hardware: &HardwareConfig) -> Result<Partition> {
+        let start = Instant::now();
+        if graph.walker_insts.is_empty() {
+            return Err(DataMappingError::NoWalkers.into());
+        }
+        let traces = WalkerTraces::sample(graph, &self.options, self.seed)?;
+        let traced = self
+            .inner
+            .partition(&traces.weighted_graph(graph), hardware)?;
+        let mut partition = Partition::new(
+            &graph.node_insts,
+            &graph.edge_insts,
+            traced.assignment,
+            traced.core_num,
+            traced.stats.partitioner,
+            start.elapsed(),
+        )?;
+        partition.stats.status = traced.stats.status;
+        Ok(partition)
+    }
+}
+
+#[test]
+fn test_trace_weights_reduce_jumps() -> Result<()> {
+    use super::MilpPartitioner;
+    use crate::{parser::parse_str, semantics_analysis::semantic_analysis};
+
+    // The walker goes a0 -> a1 -> a2 -> a3, while the heavy edges point backwards and pair
+    // a0 with a2 and a1 with a3.
+    let source = "
+        node Alice { n1: int8; };
+        edge Alice Alice Link {};
+        walker Alice Visitor;
+        graph {
+            node Alice a0, a1, a2, a3;
+            edge Link a0 a1 1;
+            edge Link a1 a2 1;
+            edge Link a2 a3 1;
+            edge Link a2 a0 10;
+            edge Link a3 a1 10;
+            walker Visitor on a0;
+        };
+    ";
+    let g = semantic_analysis(parse_str(source)?)?.graphs[0].clone();
+    let options = TraceOptions {
+        trace_weights: true,
+        ..Default::default()
+    };
+    let traces = WalkerTraces::sample(&g, &options, 0)?;
+    assert_eq!(traces.paths, vec![vec![vec![0, 1, 2, 3]]]);
+
+    let hardware = HardwareConfig {
+        core_size: 2,
+        core_num: 2,
+        rank_size: None,
+        edge_placement: Default::default(),
+    };
+    let declared = MilpPartitioner::default().partition(&g, &hardware)?;
+    let traced = TracePartitioner {
+        inner: Box::new(MilpPartitioner::default()),
+        options: options.clone(),
+        seed: 0,
+    }
+    .partition(&g, &hardware)?;
+    assert_eq!(traces.expected_jumps(&declared.assignment), vec![3.0]);
+    assert_eq!(traces.expected_jumps(&traced.assignment), vec![1.0]);
+    assert_eq!(traced.stats.cut_weight, 21);
+
+    let walks = TraceOptions {
+        trace_model: TraceModel::RandomWalk,
+        walk_length: 5,
+        ..options
+    };
+    let traces = WalkerTraces::sample(&g, &walks, 0)?;
+    assert_eq!(traces.paths[0].len(), 100);
+    assert!(traces.paths[0]
+        .iter()
+        .all(|path| path.len() == 6 && path[0] == 0));
+    Ok(())
+}
@@ -0,0 +1,7 @@
+pub mod base_type;
+pub mod code_gen;
+pub mod generator;
+pub mod graph_cut;
+pub mod parser;
+pub mod sem_type;
+pub mod semantics_analysis;
@@ -1,30 +1,125 @@
-mod base_type;
-mod code_gen;
-mod graph_cut;
-mod parser;
-mod sem_type;
-mod semantics_analysis;
-use crate::parser::parse_str;
 use anyhow::Result;
-use clap::Parser;
-use code_gen::write_to_app;
-use code_gen::write_to_task;
-use code_gen::TypeCodeGen;
-use graph_cut::assign_with_z3;
-use sem_type::SemanticGlobal;
-use semantics_analysis::semantic_analysis;
+use clap::{Parser, Subcommand};
+use dataspatialpim::code_gen::write_edge_placement;
+use dataspatialpim::code_gen::write_to_app;
+use dataspatialpim::code_gen::write_to_task;
+use dataspatialpim::code_gen::TypeCodeGen;
+use dataspatialpim::generator::{
+    default_types, generate_graph, graph_to_dspim, GeneratorConfig, GraphKind,
+};
+use dataspatialpim::graph_cut::{
+    graph::PartitionGraph, refine_partition, EdgeListReader, EdgePlacement, HardwareConfig,
+    IncrementalPartitioner, PartitionFile, PartitionStats, PartitionerOptions, Profile,
+    StreamHeuristic, StreamingPartitioner, TaskletOptions, TaskletPlan, WalkerTraces,
+};
+use dataspatialpim::parser::parse_str;
+use dataspatialpim::sem_type::SemanticGlobal;
+use dataspatialpim::semantics_analysis::semantic_analysis;
 use std::fs;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Write};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short, long)]
-    file: String,
+    #[arg(short, long, required = true)]
+    file: Option<String>,
     #[arg(short, long, default_value_t = 1)]
     count: u8,
     #[arg(short, long, default_value_t = String::from("generated_code.cpp"))]
     output: String,
+    /// Weigh edges by their traversals and balance nodes on their visits, as recorded in
+    /// these walker hop traces
+    #[arg(long)]
+    profile: Vec<String>,
+    /// Load the partition from this file instead of partitioning
+    #[arg(long)]
+    partition_in: Option<String>,
+    /// Update the partition in this file for the current graph, keeping nodes in place
+    #[arg(long, conflicts_with = "partition_in")]
+    repartition_from: Option<String>,
+    /// Previously placed nodes that `--repartition-from` may move to lower the cut
+    #[arg(long, default_value_t = 0)]
+    max_migrations: usize,
+    /// Write the nodes placed, moved and removed by `--repartition-from` as JSON
+    #[arg(long, requires = "repartition_from")]
+    migration_plan: Option<String>,
+    /// Improve the partition, computed or loaded, with up to this many rounds of
+    /// Fiduccia–Mattheyses moves and Kernighan–Lin swaps
+    #[arg(long, default_value_t = 0)]
+    refine_rounds: usize,
+    /// Save the partition to this file, to be reused with `--partition-in`
+    #[arg(long)]
+    partition_out: Option<String>,
+    /// Also write the partition quality report to this file as JSON
+    #[arg(long)]
+    report: Option<String>,
+    #[command(flatten)]
+    partitioner: PartitionerOptions,
+    #[command(flatten)]
+    tasklets: TaskletOptions,
+    /// Number of DPUs to partition onto
+    #[arg(long, global = true, default_value_t = 100)]
+    dpus: i64,
+    /// Bytes of MRAM available on each DPU
+    #[arg(long, global = true, default_value_t = 64 * 1024 * 1024)]
+    dpu_capacity: i64,
+    /// DPUs per rank; partitions across ranks first, then across the DPUs of each rank
+    #[arg(long, global = true)]
+    rank_size: Option<i64>,
+    /// DPU storing each edge, which is charged against its capacity
+    #[arg(long, global = true, value_enum, default_value_t = EdgePlacement::Both)]
+    edge_placement: EdgePlacement,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a synthetic graph as a .dspim file
+    Generate {
+        #[arg(short, long, value_enum, default_value_t = GraphKind::Uniform)]
+        kind: GraphKind,
+        #[arg(short, long, default_value_t = 1000)]
+        nodes: usize,
+        #[arg(short, long, default_value_t = 4000)]
+        edges: usize,
+        #[arg(short, long, default_value_t = 4)]
+        degree: usize,
+        #[arg(long, default_value_t = 1)]
+        max_weight: i64,
+        #[arg(short, long, default_value_t = 0)]
+        seed: u64,
+        /// Take the node and edge types from this .dspim file instead of the built-in ones
+        #[arg(long, requires = "edge_type")]
+        types: Option<String>,
+        /// Edge type (from `--types`) to instantiate; its node type is used for the nodes
+        #[arg(long)]
+        edge_type: Option<String>,
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Partition an edge list file in a single pass, writing one `node dpu` line per node
+    Stream {
+        /// Edge list with one `from to [weight]` line per edge, ideally sorted by `from`
+        #[arg(short, long)]
+        input: String,
+        #[arg(long, value_enum, default_value_t = StreamHeuristic::Fennel)]
+        heuristic: StreamHeuristic,
+        /// Bytes of each node
+        #[arg(long, default_value_t = 8)]
+        node_size: i64,
+        /// Bytes of each edge
+        #[arg(long, default_value_t = 0)]
+        edge_size: i64,
+        /// Number of nodes in the file, if known, for Fennel's balance term
+        #[arg(long)]
+        expected_nodes: Option<usize>,
+        /// Number of edges in the file, if known, for Fennel's balance term
+        #[arg(long)]
+        expected_edges: Option<usize>,
+        #[arg(short, long)]
+        output: String,
+    },
 }
 
 fn print_info(sem: SemanticGlobal) -> () {
@@ -577,18 +672,219 @@ fn write_to_task(file_name: &str, sem: &SemanticGlobal) -> Result<()> {
 
 */
 
+fn generate(
+    config: &GeneratorConfig,
+    types: Option<String>,
+    edge_type: Option<String>,
+    output: String,
+) -> Result<()> {
+    let edge_type = match (types, edge_type) {
+        (Some(types), Some(edge_type)) => {
+            let sem = semantic_analysis(parse_str(&fs::read_to_string(types)?)?)?;
+            sem.edges
+                .get(&edge_type)
+                .ok_or_else(|| anyhow::anyhow!("Edge type `{}` is not defined", edge_type))?
+                .clone()
+        }
+        _ => default_types(),
+    };
+    let graph = generate_graph(config, &edge_type)?;
+    fs::write(output, graph_to_dspim(&edge_type, &graph))?;
+    Ok(())
+}
+
+fn stream(
+    partitioner: &StreamingPartitioner,
+    input: &str,
+    output: &str,
+    hardware: &HardwareConfig,
+) -> Result<()> {
+    let reader = EdgeListReader::new(BufReader::new(fs::File::open(input)?));
+    let partition = partitioner.partition_stream(reader, hardware)?;
+    println!(
+        "Partitioned {} nodes with {} in {:?}, cut weight {}",
+        partition.nodes.len(),
+        partition.stats.partitioner,
+        partition.stats.elapsed,
+        partition.stats.cut_weight
+    );
+    let mut output_file = BufWriter::new(fs::File::create(output)?);
+    for (node, core) in partition.nodes.iter().zip(&partition.assignment) {
+        writeln!(output_file, "{} {}", node, core)?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let file_content = fs::read_to_string(args.file)?;
+    let hardware = HardwareConfig {
+        core_size: args.dpu_capacity,
+        core_num: args.dpus,
+        rank_size: args.rank_size,
+        edge_placement: args.edge_placement,
+    };
+    hardware.check()?;
+    match args.command {
+        Some(Command::Generate {
+            kind,
+            nodes,
+            edges,
+            degree,
+            max_weight,
+            seed,
+            types,
+            edge_type,
+            output,
+        }) => {
+            let config = GeneratorConfig {
+                kind,
+                nodes,
+                edges,
+                degree,
+                max_weight,
+                seed,
+            };
+            return generate(&config, types, edge_type, output);
+        }
+        Some(Command::Stream {
+            input,
+            heuristic,
+            node_size,
+            edge_size,
+            expected_nodes,
+            expected_edges,
+            output,
+        }) => {
+            let partitioner = StreamingPartitioner {
+                heuristic,
+                node_size,
+                edge_size,
+                expected_nodes,
+                expected_edges,
+            };
+            return stream(&partitioner, &input, &output, &hardware);
+        }
+        None => {}
+    }
+
+    let file_content = fs::read_to_string(args.file.unwrap())?;
     let sem = semantic_analysis(parse_str(&file_content)?)?;
 
     print_info(sem.clone());
-    let g = &sem.graphs[0];
-    let assignment = assign_with_z3(&g.node_insts, &g.edge_insts, 64 * 1024 * 1024, 100);
+    let profiled;
+    let g = if args.profile.is_empty() {
+        &sem.graphs[0]
+    } else {
+        let profile = Profile::load(&sem.graphs[0], &args.profile)?;
+        println!("{}", profile);
+        profiled = profile.weighted_graph(&sem.graphs[0]);
+        &profiled
+    };
+    let mut partition = match (&args.partition_in, &args.repartition_from) {
+        (Some(file_name), _) => {
+            let file = PartitionFile::load(file_name)?;
+            if !file.matches(g, &hardware) {
+                println!(
+                    "Warning: {} was computed for another graph or hardware",
+                    file_name
+                );
+            }
+            file.to_partition(g, &hardware)?
+        }
+        (None, Some(file_name)) => {
+            let incremental = IncrementalPartitioner {
+                max_migrations: args.max_migrations,
+                balance: args.partitioner.balance(),
+            };
+            let (partition, plan) =
+                incremental.repartition(g, &PartitionFile::load(file_name)?, &hardware)?;
+            for placement in &plan.placed {
+                println!("Place {} on DPU {}", placement.node, placement.dpu);
+            }
+            for migration in &plan.moved {
+                println!(
+                    "Move {} from DPU {} to DPU {}",
+                    migration.node, migration.from, migration.to
+                );
+            }
+            for node in &plan.removed {
+                println!("Remove {}", node);
+            }
+            if plan.over_budget > 0 {
+                println!(
+                    "{} moves beyond --max-migrations were needed to make room",
+                    plan.over_budget
+                );
+            }
+            if let Some(plan_file) = &args.migration_plan {
+                fs::write(plan_file, serde_json::to_string_pretty(&plan)?)?;
+            }
+            partition
+        }
+        (None, None) => args.partitioner.build().partition(g, &hardware)?,
+    };
+    if args.refine_rounds > 0 {
+        let before = partition.stats.cut_weight;
+        let rounds = refine_partition(
+            g,
+            &mut partition,
+            &hardware,
+            &args.partitioner.balance(),
+            args.refine_rounds,
+        )?;
+        for (i, round) in rounds.iter().enumerate() {
+            println!(
+                "Refinement round {}: moves gained {}, swaps gained {}, cut weight {}",
+                i + 1,
+                round.moved,
+                round.swapped,
+                round.cut_weight
+            );
+        }
+        println!(
+            "Refined cut weight from {} to {}",
+            before, partition.stats.cut_weight
+        );
+    }
+    if let Some(file_name) = &args.partition_out {
+        PartitionFile::new(g, &partition, &hardware).save(file_name)?;
+    }
+    println!(
+        "Partitioned {} nodes with {} in {:?}, cut weight {} ({})",
+        partition.nodes.len(),
+        partition.stats.partitioner,
+        partition.stats.elapsed,
+        partition.stats.cut_weight,
+        partition.stats.status
+    );
+    if hardware.rank_size.is_some() {
+        println!(
+            "Inter-rank cut weight {}",
+            PartitionGraph::from_semantic(g)?.rank_cut_weight(&partition.assignment, &hardware)
+        );
+    }
+    let options = &args.partitioner;
+    let traces = WalkerTraces::sample(g, &options.trace, options.seed)?;
+    let stats = PartitionStats::new(g, &partition, &hardware, &traces)?;
+    println!("{}", stats);
+    if let Some(report) = &args.report {
+        fs::write(report, stats.to_json()?)?;
+    }
+    let plan = TaskletPlan::new(g, &partition, &args.tasklets)?;
+    println!(
+        "{} tasklets per DPU with {} bytes of WRAM each, streaming {}-byte blocks, tasklet work imbalance {:.3}",
+        plan.tasklets,
+        plan.share,
+        plan.block,
+        plan.imbalance()
+    );
 
     write_to_file(&args.output, &sem).ok();
-    write_to_app("./examples/app.c", &sem).ok();
-    write_to_task("./examples/task.c", &sem).ok();
+    if !g.edge_insts.is_empty() {
+        write_edge_placement(&args.output, g, &partition, hardware.edge_placement)?;
+    }
+    write_to_app("./examples/app.c", &sem, &partition, &plan).ok();
+    write_to_task("./examples/task.c", &plan).ok();
     Ok(())
 }
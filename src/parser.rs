@@ -75,6 +75,31 @@ pub fn test_graph() {
     println!("{:?}", graph);
 }
 
+#[test]
+pub fn test_annotation() {
+    use crate::base_type::AnnotationArg;
+
+    let node = dspim::NodeRuleParser::new()
+        .parse("@cost(12) node nd {cost: int16;};")
+        .expect("Parsing error");
+    assert_eq!(node.1[0].name, "cost");
+    assert_eq!(node.1[0].args, vec![AnnotationArg::Num(12)]);
+    assert_eq!(node.0.fields[0].varname, "cost");
+
+    let graph = dspim::GraphRuleParser::new()
+        .parse("graph { @pin(dpu = 3) node Hello h1; node Hello h2; @colocate(h1, h2); };")
+        .expect("Parsing error");
+    assert_eq!(
+        graph.node_insts[0].annotations[0].args,
+        vec![AnnotationArg::Named(
+            String::from("dpu"),
+            Box::new(AnnotationArg::Num(3))
+        )]
+    );
+    assert_eq!(graph.annotations[0].name, "colocate");
+    assert_eq!(graph.annotations[0].args.len(), 2);
+}
+
 pub fn parse_str(content: &str) -> Result<Vec<GeneralBlock>> {
     let input = dspim::GeneralRuleParser::new()
         .parse(content)
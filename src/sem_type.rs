@@ -13,9 +13,45 @@ pub struct SemanticWalker {
     pub node_type: Rc<NamedBlock>,
 }
 
+/// How the copies of a node replicated on several DPUs are kept consistent.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplicaMode {
+    /// The copies are only read; the primary copy is written back
+    ReadOnly,
+    /// Every copy may be written; the host merges them into the node
+    Reconcile,
+}
+
 pub struct SemanticNodeInst {
     pub varname: String,
     pub node_type: Rc<NamedBlock>,
+    /// Set by a `@replicate` annotation on the instance.
+    pub replicate: Option<ReplicaMode>,
+    /// Compute cost of a visit, set by a `@cost(n)` annotation on the instance.
+    pub cost: Option<i64>,
+    /// Walker arrivals recorded by a runtime profile.
+    pub heat: Option<i64>,
+}
+
+impl SemanticNodeInst {
+    /// Compute cost of a visit: declared on the instance, else on its type, else its size.
+    pub fn visit_cost(&self) -> i64 {
+        self.cost.unwrap_or_else(|| self.node_type.cost())
+    }
+
+    /// Compute cost of the instance for load balancing: the cost of a visit, times the
+    /// visits if profiled. Saturates at `i64::MAX`; see `checked_cost`.
+    pub fn cost(&self) -> i64 {
+        self.visit_cost().saturating_mul(self.heat.unwrap_or(1))
+    }
+
+    /// `cost`, or `None` when the product overflows.
+    pub fn checked_cost(&self) -> Option<i64> {
+        self.visit_cost().checked_mul(self.heat.unwrap_or(1))
+    }
 }
 
 pub struct SemanticEdgeInst {
@@ -30,11 +66,29 @@ pub struct SemanticWalkerInst {
     pub start_node: Rc<SemanticNodeInst>,
 }
 
+/// Where the user requires nodes to go, by node name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlacementConstraints {
+    /// Set by `@pin(dpu = n)` on a node instance.
+    pub pins: Vec<(String, usize)>,
+    /// Set by `@colocate(a, b)` in the graph.
+    pub colocate: Vec<(String, String)>,
+    /// Set by `@separate(a, b)` in the graph.
+    pub separate: Vec<(String, String)>,
+}
+
+impl PlacementConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty() && self.colocate.is_empty() && self.separate.is_empty()
+    }
+}
+
 #[derive(Clone)]
 pub struct SemanticGraph {
     pub node_insts: Vec<Rc<SemanticNodeInst>>,
     pub edge_insts: Vec<Rc<SemanticEdgeInst>>,
     pub walker_insts: Vec<Rc<SemanticWalkerInst>>,
+    pub constraints: PlacementConstraints,
 }
 
 #[derive(Clone)]
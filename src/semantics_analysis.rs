@@ -1,7 +1,9 @@
-use crate::base_type::{Edge, GeneralBlock, Graph, NamedBlock, Walker};
+use crate::base_type::{
+    Annotation, AnnotationArg, Edge, GeneralBlock, Graph, NamedBlock, Node, NodeInst, Walker,
+};
 use crate::sem_type::{
-    SemanticEdge, SemanticEdgeInst, SemanticGlobal, SemanticGraph, SemanticNodeInst,
-    SemanticWalker, SemanticWalkerInst,
+    PlacementConstraints, ReplicaMode, SemanticEdge, SemanticEdgeInst, SemanticGlobal,
+    SemanticGraph, SemanticNodeInst, SemanticWalker, SemanticWalkerInst,
 };
 use anyhow::Result;
 use std::collections::HashMap;
@@ -12,10 +14,110 @@ use thiserror::Error;
 pub enum SemanticsError {
     #[error("Token `{0}` is not defined.")]
     UndefinedToken(String),
+    #[error("Annotation `@{0}` is not valid on `{1}`.")]
+    InvalidAnnotation(String, String),
     #[error("Unknown error.")]
     Unknown,
 }
 
+/// Apply the annotations of a node type declaration to its block.
+fn annotate_node(node: Node) -> Result<NamedBlock> {
+    let Node(mut block, annotations) = node;
+    for Annotation { name, args } in annotations {
+        match (name.as_str(), args.as_slice()) {
+            ("cost", [AnnotationArg::Num(cost)]) if *cost >= 0 => block.cost = Some(*cost),
+            _ => return Err(SemanticsError::InvalidAnnotation(name, block.name).into()),
+        }
+    }
+    Ok(block)
+}
+
+/// The replication, the DPU and the compute cost requested by the annotations of a node
+/// instance.
+fn annotate_node_inst(
+    inst: &NodeInst,
+) -> Result<(Option<ReplicaMode>, Option<usize>, Option<i64>)> {
+    let (mut replicate, mut pin, mut cost) = (None, None, None);
+    for Annotation { name, args } in &inst.annotations {
+        match (name.as_str(), args.as_slice()) {
+            ("replicate", []) => replicate = Some(ReplicaMode::ReadOnly),
+            ("replicate", [AnnotationArg::Token(mode)]) if mode == "read_only" => {
+                replicate = Some(ReplicaMode::ReadOnly)
+            }
+            ("replicate", [AnnotationArg::Token(mode)]) if mode == "reconcile" => {
+                replicate = Some(ReplicaMode::Reconcile)
+            }
+            ("pin", [AnnotationArg::Named(key, dpu)]) if key == "dpu" => match **dpu {
+                AnnotationArg::Num(dpu) if dpu >= 0 => pin = Some(dpu as usize),
+                _ => {
+                    return Err(SemanticsError::InvalidAnnotation(
+                        name.clone(),
+                        inst.varname.clone(),
+                    )
+                    .into())
+                }
+            },
+            ("cost", [AnnotationArg::Num(n)]) if *n >= 0 => cost = Some(*n),
+            _ => {
+                return Err(
+                    SemanticsError::InvalidAnnotation(name.clone(), inst.varname.clone()).into(),
+                )
+            }
+        }
+    }
+    Ok((replicate, pin, cost))
+}
+
+/// The placement constraints of a graph: the pins of its node instances, and its
+/// `@colocate(a, b, ...)` and `@separate(a, b, ...)` statements. Colocation chains the
+/// named nodes, separation applies to every pair of them.
+fn graph_constraints(
+    graph: &Graph,
+    nodes: &HashMap<String, Rc<SemanticNodeInst>>,
+) -> Result<PlacementConstraints> {
+    let mut constraints = PlacementConstraints::default();
+    for inst in &graph.node_insts {
+        if let (_, Some(dpu), _) = annotate_node_inst(inst)? {
+            constraints.pins.push((inst.varname.clone(), dpu));
+        }
+    }
+    for Annotation { name, args } in &graph.annotations {
+        let names = args
+            .iter()
+            .map(|arg| match arg {
+                AnnotationArg::Token(node) if nodes.contains_key(node) => Ok(node.clone()),
+                AnnotationArg::Token(node) => Err(SemanticsError::UndefinedToken(node.clone())),
+                _ => Err(SemanticsError::InvalidAnnotation(
+                    name.clone(),
+                    String::from("graph"),
+                )),
+            })
+            .collect::<Result<Vec<String>, SemanticsError>>()?;
+        match name.as_str() {
+            "colocate" if names.len() >= 2 => {
+                for pair in names.windows(2) {
+                    constraints
+                        .colocate
+                        .push((pair[0].clone(), pair[1].clone()));
+                }
+            }
+            "separate" if names.len() >= 2 => {
+                for (i, a) in names.iter().enumerate() {
+                    for b in &names[i + 1..] {
+                        constraints.separate.push((a.clone(), b.clone()));
+                    }
+                }
+            }
+            _ => {
+                return Err(
+                    SemanticsError::InvalidAnnotation(name.clone(), String::from("graph")).into(),
+                )
+            }
+        }
+    }
+    Ok(constraints)
+}
+
 fn transform_edge_hashmap_to_semantic<'input>(
     node_types: &HashMap<String, Rc<NamedBlock>>,
     edge_types: HashMap<String, Edge>,
@@ -76,6 +178,7 @@ fn transform_graph_to_semantic(
                 .node_insts
                 .iter()
                 .map(|inst| -> Result<Rc<SemanticNodeInst>> {
+                    let (replicate, _, cost) = annotate_node_inst(inst)?;
                     Ok(Rc::new(SemanticNodeInst {
                         varname: inst.varname.clone(),
                         node_type: node_types
@@ -84,6 +187,9 @@ fn transform_graph_to_semantic(
                                 inst.varname.clone(),
                             )))?
                             .clone(),
+                        replicate,
+                        cost,
+                        heat: None,
                     }))
                 })
                 .into_iter()
@@ -133,6 +239,7 @@ fn transform_graph_to_semantic(
                 node_insts: sem_node_insts,
                 edge_insts: sem_edge_insts_res?,
                 walker_insts: sem_walker_insts_res?,
+                constraints: graph_constraints(graph, &node_hash_map)?,
             })
         })
         .collect();
@@ -148,7 +255,8 @@ pub fn semantic_analysis(general: Vec<GeneralBlock>) -> Result<SemanticGlobal> {
     for block in general {
         match block {
             GeneralBlock::NodeBlock(node) => {
-                node_types.insert(node.0.name.clone(), Rc::new(node.0));
+                let block = annotate_node(node)?;
+                node_types.insert(block.name.clone(), Rc::new(block));
             }
             GeneralBlock::EdgeBlock(edge) => {
                 edge_types.insert(edge.named_block.name.clone(), edge);